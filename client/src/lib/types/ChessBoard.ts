@@ -1,5 +1,5 @@
 import type { Api } from "chessground/api";
-import type { MoveMetadata, Key } from "chessground/types";
+import type { MoveMetadata, Key, Role } from "chessground/types";
 
 export interface MoveEvent {
   orig: Key;
@@ -7,3 +7,9 @@ export interface MoveEvent {
   metadata: MoveMetadata;
   cg: Api;
 }
+
+export interface DropEvent {
+  role: Role;
+  key: Key;
+  cg: Api;
+}
@@ -1,12 +1,35 @@
-import type { Key } from "chessground/types";
+import type { Key, Role } from "chessground/types";
 
-const toDests = (destObject): Map<Key, Key[]> =>
-  new Map(
-    Object.entries(destObject).map((entry: [string, string]) => [
-      entry[0] as Key,
-      entry[1].match(/.{1,2}/g) as Key[],
-    ])
-  );
+type LegalMove = {
+  from?: Key;
+  to: Key;
+  uci: string;
+  kind: "normal" | "promotion" | "castle" | "en_passant" | "drop";
+  promotion?: string;
+  piece?: string;
+};
+
+type Pocket = Record<Exclude<Role, "king">, number>;
+
+type Pockets = { white: Pocket; black: Pocket };
+
+const toDests = (legalMoves: LegalMove[]): Map<Key, Key[]> => {
+  const dests = new Map<Key, Key[]>();
+
+  for (const move of legalMoves) {
+    if (move.kind === "drop") {
+      continue;
+    }
+
+    const squares = dests.get(move.from) ?? [];
+    if (!squares.includes(move.to)) {
+      squares.push(move.to);
+    }
+    dests.set(move.from, squares);
+  }
+
+  return dests;
+};
 
 const uciToMove = (
   uci: string
@@ -24,4 +47,16 @@ const moveToUci = (move: {
   promotion?: string;
 }): string => move.orig + move.dest + (move.promotion ?? "");
 
-export { toDests, uciToMove, moveToUci };
+const dropLetters: Record<string, string> = {
+  pawn: "P",
+  knight: "N",
+  bishop: "B",
+  rook: "R",
+  queen: "Q",
+};
+
+const dropToUci = (role: Role, key: Key): string =>
+  `${dropLetters[role]}@${key}`;
+
+export { toDests, uciToMove, moveToUci, dropToUci };
+export type { LegalMove, Pocket, Pockets };
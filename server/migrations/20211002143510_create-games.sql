@@ -0,0 +1,16 @@
+create table games (
+    id uuid default uuid_generate_v4() primary key,
+    white_id uuid not null,
+    black_id uuid not null,
+    initial_fen varchar not null,
+    moves_uci varchar[] not null,
+    moves_san varchar[] not null,
+    result varchar not null,
+    termination varchar not null,
+    time_control jsonb,
+    started_at timestamp not null,
+    ended_at timestamp not null default current_timestamp
+);
+
+create index games_white_id_idx on games (white_id, ended_at desc);
+create index games_black_id_idx on games (black_id, ended_at desc);
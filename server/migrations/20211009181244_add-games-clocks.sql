@@ -0,0 +1 @@
+alter table games add column clocks bigint[] not null default '{}';
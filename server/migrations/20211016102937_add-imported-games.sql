@@ -0,0 +1,8 @@
+alter table games
+    alter column white_id drop not null,
+    alter column black_id drop not null,
+    alter column termination drop not null,
+    add column imported boolean not null default false,
+    add column white_name varchar,
+    add column black_name varchar,
+    add column pgn text;
@@ -0,0 +1,28 @@
+create table ratings (
+    user_id uuid not null references users (id) on delete cascade,
+    speed varchar not null,
+    rating double precision not null,
+    deviation double precision not null,
+    volatility double precision not null,
+    games integer not null default 0,
+    updated_at timestamp not null default current_timestamp,
+    primary key (user_id, speed)
+);
+
+create table rating_history (
+    id uuid default uuid_generate_v4() primary key,
+    user_id uuid not null references users (id) on delete cascade,
+    game_id uuid references games (id) on delete set null,
+    speed varchar not null,
+    rating double precision not null,
+    deviation double precision not null,
+    volatility double precision not null,
+    created_at timestamp not null default current_timestamp
+);
+
+create index rating_history_user_id_idx on rating_history (user_id, speed, created_at);
+
+alter table games
+    add column rated boolean not null default false,
+    add column white_rating integer,
+    add column black_rating integer;
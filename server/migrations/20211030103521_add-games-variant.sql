@@ -0,0 +1,2 @@
+alter table games
+    add column variant varchar not null default 'standard';
@@ -0,0 +1,748 @@
+//! Bughouse rooms: two teams of two playing on two linked boards.
+//!
+//! Partners sit on different boards with opposite colors, every piece
+//! captured on a board goes to the pocket of the partner of its capturer on
+//! the other board. The first board to end ends the other one, the partner of
+//! the winner winning too.
+
+pub mod model;
+mod snapshot;
+
+pub use model::*;
+
+use super::room::{
+    Abort, AcceptDraw, ClaimDraw, ClaimVictory, DeclineDraw, GameEndResult, Join, Leave,
+    MergePlayer, Move, OfferDraw, Player, PlayerInfo, Resign, Spectator, Sweep, TimeControl,
+    Variant,
+};
+use super::room_manager::{BughouseLobbyRoom, LobbyRoom, RemoveRoom, RoomManager, RoomStarted};
+use super::websocket::model::{ServerError, ServerMessage};
+use super::websocket::Send;
+
+use crate::app::games::model::{self as games, NewGame};
+use crate::util::chess::{get_dests, parse_move};
+use crate::util::position::Position;
+use crate::util::variant::{Bughouse, Variant as _};
+use actix::prelude::*;
+use actix_redis::Command;
+use chess::Color;
+use chrono::{NaiveDateTime, Utc};
+use log::{error, info};
+use sqlx::PgPool;
+use std::collections::HashSet;
+use std::time::Instant;
+use uuid::Uuid;
+
+#[allow(clippy::large_enum_variant)]
+pub enum GameState {
+    /// Players take the free seats in the order they join, the game starts
+    /// once all of them are taken
+    Waiting { seats: Vec<Option<Player>> },
+    Started {
+        spectators: HashSet<Spectator>,
+        /// Players by seat
+        players: Vec<Player>,
+        boards: [Board; 2],
+        /// Board of every move, in the order they were played
+        played: Vec<usize>,
+        started_at: NaiveDateTime,
+    },
+}
+
+pub struct BughouseRoom {
+    room_id: String,
+    creator: PlayerInfo,
+    /// Rating of the creator when the room was created
+    creator_rating: Option<i32>,
+    time_control: Option<TimeControl>,
+    created_at: NaiveDateTime,
+    state: GameState,
+    flag_timer: Option<SpawnHandle>,
+    /// Since when no player has been connected, as last seen by a sweep
+    absent_since: Option<Instant>,
+    room_manager: Addr<RoomManager>,
+    redis: Recipient<Command>,
+    db_pool: PgPool,
+}
+
+impl BughouseRoom {
+    pub fn new(
+        room_id: String,
+        creator: PlayerInfo,
+        time_control: Option<TimeControl>,
+        room_manager: Addr<RoomManager>,
+        redis: Recipient<Command>,
+        db_pool: PgPool,
+    ) -> Self {
+        let mut seats = vec![None; SEATS];
+        seats[0] = Some(Player::new(creator.clone()));
+
+        Self {
+            room_id,
+            creator,
+            creator_rating: None,
+            time_control,
+            created_at: Utc::now().naive_utc(),
+            state: GameState::Waiting { seats },
+            flag_timer: None,
+            absent_since: None,
+            room_manager,
+            redis,
+            db_pool,
+        }
+    }
+
+    pub fn with_creator_rating(mut self, rating: Option<i32>) -> Self {
+        self.creator_rating = rating;
+        self
+    }
+
+    /// Whether the room waits for players
+    pub fn is_open(&self) -> bool {
+        matches!(self.state, GameState::Waiting { .. })
+    }
+
+    /// How the room is shown in the lobby
+    pub fn lobby_room(&self) -> LobbyRoom {
+        LobbyRoom::Bughouse(BughouseLobbyRoom {
+            room_id: self.room_id.clone(),
+            creator: self.creator.public(&self.room_id),
+            creator_rating: self.creator_rating,
+            time_control: self.time_control,
+            created_at: self.created_at,
+        })
+    }
+
+    /// Sends `message` to every session of the player `id`
+    fn send_player(&self, id: Uuid, message: ServerMessage) {
+        let player = match &self.state {
+            GameState::Waiting { seats } => seats.iter().flatten().find(|player| player.id == id),
+            GameState::Started { players, .. } => players.iter().find(|player| player.id == id),
+        };
+
+        if let Some(player) = player {
+            player.send(&message);
+        }
+    }
+
+    /// Tells the seated players who sits where while the room fills
+    fn send_seats(&self) {
+        if let GameState::Waiting { seats } = &self.state {
+            let message = ServerMessage::BughouseSeats {
+                seats: seats
+                    .iter()
+                    .map(|seat| seat.as_ref().map(|player| player.public(&self.room_id)))
+                    .collect(),
+            };
+
+            for player in seats.iter().flatten() {
+                player.send(&message);
+            }
+        }
+    }
+
+    /// Sends every player and spectator a message built from their seat and
+    /// the boards as they see them, with legal moves for the players to move
+    fn send_boards<F>(&self, message: F, now: Instant)
+    where
+        F: Fn(Option<Seat>, Vec<BoardState>) -> ServerMessage,
+    {
+        if let GameState::Started {
+            players,
+            spectators,
+            boards,
+            ..
+        } = &self.state
+        {
+            for (index, player) in players.iter().enumerate() {
+                let seat = Seat::from_index(index);
+                player.send(&message(Some(seat), board_states(boards, Some(seat), now)));
+            }
+
+            let spectated = message(None, board_states(boards, None, now));
+            for spectator in spectators.iter() {
+                spectator.session.do_send(Send(spectated.clone())).ok();
+            }
+        }
+    }
+
+    /// Both boards as seen from `seat`
+    fn state_message(&self, seat: Option<Seat>, now: Instant) -> Option<ServerMessage> {
+        match &self.state {
+            GameState::Waiting { .. } => None,
+            GameState::Started {
+                players, boards, ..
+            } => Some(ServerMessage::BughouseState {
+                seat: seat.map(SeatInfo::from),
+                players: players
+                    .iter()
+                    .map(|player| player.public(&self.room_id))
+                    .collect(),
+                boards: board_states(boards, seat, now),
+                moves: boards.iter().map(|board| board.uci_moves.clone()).collect(),
+            }),
+        }
+    }
+
+    fn start(&mut self, players: Vec<Player>, ctx: &mut Context<Self>) {
+        let now = Instant::now();
+        let initial = Bughouse::new(Position::standard());
+
+        self.state = GameState::Started {
+            spectators: HashSet::new(),
+            players,
+            boards: [
+                Board::new(initial, self.time_control, now),
+                Board::new(initial, self.time_control, now),
+            ],
+            played: Vec::new(),
+            started_at: Utc::now().naive_utc(),
+        };
+
+        for (index, player) in self.players().iter().enumerate() {
+            if let Some(message) = self.state_message(Some(Seat::from_index(index)), now) {
+                player.send(&message);
+            }
+        }
+
+        self.save();
+        self.room_manager.do_send(RoomStarted {
+            room_id: self.room_id.clone(),
+        });
+        self.schedule_flag(ctx);
+    }
+
+    /// Arms a timer firing when the first of the sides to move runs out of
+    /// time
+    fn schedule_flag(&mut self, ctx: &mut Context<Self>) {
+        if let Some(handle) = self.flag_timer.take() {
+            ctx.cancel_future(handle);
+        }
+
+        if let GameState::Started { boards, .. } = &self.state {
+            let now = Instant::now();
+            let left = boards
+                .iter()
+                .filter_map(|board| board.clock.as_ref()?.time_to_flag(now))
+                .min();
+
+            if let Some(left) = left {
+                self.flag_timer = Some(ctx.run_later(left, |act, ctx| act.check_flag(ctx)));
+            }
+        }
+    }
+
+    fn check_flag(&mut self, ctx: &mut Context<Self>) {
+        self.flag_timer = None;
+
+        let flagged = match &self.state {
+            GameState::Started { boards, .. } => flagged(boards, Instant::now()),
+            GameState::Waiting { .. } => None,
+        };
+
+        match flagged {
+            Some((board, color)) => self.end_game(board, GameEndResult::out_of_time(color), ctx),
+            None => self.schedule_flag(ctx),
+        }
+    }
+
+    /// Games of both boards to store in the database once they are over
+    fn new_games(&self, results: &[GameEndResult]) -> Vec<NewGame> {
+        match &self.state {
+            GameState::Waiting { .. } => Vec::new(),
+            GameState::Started {
+                players,
+                boards,
+                started_at,
+                ..
+            } => boards
+                .iter()
+                .zip(results)
+                .enumerate()
+                .map(|(index, (board, result))| {
+                    let player = |color| {
+                        &players[Seat {
+                            board: index,
+                            color,
+                        }
+                        .index()]
+                    };
+                    let (white, black) = (player(Color::White), player(Color::Black));
+
+                    NewGame {
+                        white_id: white.id,
+                        black_id: black.id,
+                        white_name: white.username.clone(),
+                        black_name: black.username.clone(),
+                        variant: Variant::Bughouse,
+                        initial_fen: Bughouse::new(Position::standard()).fen(),
+                        moves_uci: board.uci_moves.clone(),
+                        moves_san: board.san_moves.clone(),
+                        pockets: board.position.pockets().map(|pockets| pockets.to_string()),
+                        termination: result.clone(),
+                        time_control: self.time_control,
+                        clocks: board
+                            .clock
+                            .as_ref()
+                            .map(|clock| {
+                                clock
+                                    .history()
+                                    .iter()
+                                    .map(|left| left.as_millis() as i64)
+                                    .collect()
+                            })
+                            .unwrap_or_default(),
+                        started_at: *started_at,
+                        rated: false,
+                        white_rating: None,
+                        black_rating: None,
+                    }
+                })
+                .collect(),
+        }
+    }
+
+    /// Ends both boards, the game on `board` having ended with `result`
+    fn end_game(&mut self, board: usize, result: GameEndResult, ctx: &mut Context<Self>) {
+        // The partner of the winner wins the other board
+        let other = match result.white_score() {
+            Some(score) if score > 0.5 => GameEndResult::partner_wins(Color::Black),
+            Some(score) if score < 0.5 => GameEndResult::partner_wins(Color::White),
+            _ => result.clone(),
+        };
+        let mut results = vec![other; 2];
+        results[board] = result;
+
+        let scores: Vec<&str> = results.iter().map(GameEndResult::score).collect();
+        self.save_closed(&scores.join(" "));
+
+        let new_games = self.new_games(&results);
+        let db_pool = self.db_pool.clone();
+        let room_id = self.room_id.clone();
+
+        async move {
+            for new_game in new_games {
+                if let Err(e) = games::Game::create(&db_pool, new_game).await {
+                    error!("Could not archive game of room {}: {:?}", room_id, e);
+                }
+            }
+        }
+        .into_actor(self)
+        .map(move |_, act, ctx| {
+            act.send_boards(
+                |_, _| ServerMessage::BughouseEnd {
+                    board,
+                    results: results.clone(),
+                },
+                Instant::now(),
+            );
+
+            act.room_manager.do_send(RemoveRoom {
+                room_id: act.room_id.clone(),
+            });
+
+            ctx.stop();
+        })
+        .wait(ctx);
+    }
+
+    /// Players by seat once the game started
+    fn players(&self) -> &[Player] {
+        match &self.state {
+            GameState::Started { players, .. } => players,
+            GameState::Waiting { .. } => &[],
+        }
+    }
+
+    /// Seat of the player `id` in a started game
+    fn seat_of(&self, id: Uuid) -> Option<Seat> {
+        self.players()
+            .iter()
+            .position(|player| player.id == id)
+            .map(Seat::from_index)
+    }
+
+    /// Draws are not part of bughouse
+    fn unsupported(&self, id: Uuid) {
+        self.send_player(
+            id,
+            ServerMessage::Err {
+                what: ServerError::OutOfContext,
+            },
+        );
+    }
+}
+
+/// Every board as seen from `seat`, its player gets their legal moves when
+/// it is their turn
+fn board_states(boards: &[Board], seat: Option<Seat>, now: Instant) -> Vec<BoardState> {
+    boards
+        .iter()
+        .enumerate()
+        .map(|(index, board)| {
+            let dests = seat
+                .filter(|seat| seat.board == index && seat.color == board.side_to_move())
+                .map(|_| get_dests(&board.position));
+
+            board.state(dests, now)
+        })
+        .collect()
+}
+
+/// Board and side that ran out of time, if any
+fn flagged(boards: &[Board], now: Instant) -> Option<(usize, Color)> {
+    boards
+        .iter()
+        .enumerate()
+        .find_map(|(index, board)| Some((index, board.clock.as_ref()?.flagged(now)?)))
+}
+
+impl Actor for BughouseRoom {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        info!("Started bughouse room !");
+
+        self.save();
+        // Restored rooms may already have running clocks
+        self.schedule_flag(ctx);
+    }
+}
+
+impl Handler<Join> for BughouseRoom {
+    type Result = ();
+
+    fn handle(&mut self, msg: Join, ctx: &mut Self::Context) -> Self::Result {
+        match &mut self.state {
+            GameState::Waiting { seats } => {
+                match seats
+                    .iter_mut()
+                    .flatten()
+                    .find(|player| player.id == msg.id)
+                {
+                    Some(player) => {
+                        player.sessions.insert(msg.session);
+                    }
+                    None => {
+                        if let Some(seat) = seats.iter_mut().find(|seat| seat.is_none()) {
+                            let mut player = Player::new(PlayerInfo {
+                                id: msg.id,
+                                username: msg.username,
+                            });
+                            player.sessions.insert(msg.session);
+                            *seat = Some(player);
+                        }
+                    }
+                }
+
+                if seats.iter().all(Option::is_some) {
+                    let players = seats.drain(..).flatten().collect();
+                    self.start(players, ctx);
+                } else {
+                    self.save();
+                    self.send_seats();
+                }
+            }
+            GameState::Started {
+                players,
+                spectators,
+                ..
+            } => {
+                let seat = match players.iter_mut().position(|player| player.id == msg.id) {
+                    Some(index) => {
+                        players[index].sessions.insert(msg.session.clone());
+                        Some(Seat::from_index(index))
+                    }
+                    None => {
+                        spectators.insert(Spectator {
+                            id: msg.id,
+                            session: msg.session.clone(),
+                        });
+                        None
+                    }
+                };
+
+                // Only the new session needs the state, the other ones are
+                // up to date
+                if let Some(message) = self.state_message(seat, Instant::now()) {
+                    msg.session.do_send(Send(message)).ok();
+                }
+            }
+        }
+    }
+}
+
+impl Handler<Leave> for BughouseRoom {
+    type Result = ();
+
+    fn handle(&mut self, msg: Leave, _ctx: &mut Self::Context) -> Self::Result {
+        match &mut self.state {
+            GameState::Waiting { seats } => {
+                let creator = self.creator.id;
+                let seat = seats
+                    .iter_mut()
+                    .find(|seat| seat.as_ref().map(|player| player.id) == Some(msg.id));
+
+                if let Some(seat) = seat {
+                    let left = seat.as_mut().map_or(false, |player| {
+                        player.sessions.remove(&msg.session) && !player.is_present()
+                    });
+
+                    // The creator keeps their seat, as in other rooms
+                    if left && msg.id != creator {
+                        *seat = None;
+                        self.save();
+                        self.send_seats();
+                    }
+                }
+            }
+            GameState::Started {
+                players,
+                spectators,
+                ..
+            } => {
+                let player = players.iter_mut().find(|player| player.id == msg.id);
+                if !player.map_or(false, |player| player.sessions.remove(&msg.session)) {
+                    spectators.remove(&msg.session);
+                }
+            }
+        }
+    }
+}
+
+impl Handler<Move> for BughouseRoom {
+    type Result = ();
+
+    fn handle(&mut self, msg: Move, ctx: &mut Self::Context) -> Self::Result {
+        let now = Instant::now();
+
+        let seat = match self.seat_of(msg.id) {
+            Some(seat) => seat,
+            None => return,
+        };
+
+        if let GameState::Started { boards, played, .. } = &mut self.state {
+            if seat.color != boards[seat.board].side_to_move() {
+                self.send_player(
+                    msg.id,
+                    ServerMessage::Err {
+                        what: ServerError::NotYourTurn,
+                    },
+                );
+                return;
+            }
+
+            if let Some((board, color)) = flagged(boards, now) {
+                self.end_game(board, GameEndResult::out_of_time(color), ctx);
+                return;
+            }
+
+            let uci = match parse_move(&boards[seat.board].position, &msg.uci)
+                .map_err(ServerError::from)
+                .and_then(|chess_move| {
+                    play(boards, seat.board, chess_move).ok_or(ServerError::IllegalMove)
+                }) {
+                Ok(uci) => uci,
+                Err(what) => {
+                    self.send_player(msg.id, ServerMessage::Err { what });
+                    return;
+                }
+            };
+
+            let board = &mut boards[seat.board];
+            if let Some(clock) = &mut board.clock {
+                clock.press(now);
+            }
+            played.push(seat.board);
+
+            let result = board.position.result();
+
+            self.send_boards(
+                |_, boards| ServerMessage::BughouseMove {
+                    board: seat.board,
+                    uci: uci.clone(),
+                    boards,
+                },
+                now,
+            );
+
+            self.save();
+
+            match result {
+                Some(result) => self.end_game(seat.board, result, ctx),
+                None => self.schedule_flag(ctx),
+            }
+        }
+    }
+}
+
+impl Handler<Resign> for BughouseRoom {
+    type Result = ();
+
+    fn handle(&mut self, msg: Resign, ctx: &mut Self::Context) -> Self::Result {
+        if let Some(seat) = self.seat_of(msg.id) {
+            self.end_game(seat.board, GameEndResult::resigns(seat.color), ctx);
+        }
+    }
+}
+
+impl Handler<Abort> for BughouseRoom {
+    type Result = ();
+
+    fn handle(&mut self, msg: Abort, ctx: &mut Self::Context) -> Self::Result {
+        let seat = match self.seat_of(msg.id) {
+            Some(seat) => seat,
+            None => return,
+        };
+
+        if let GameState::Started { boards, .. } = &self.state {
+            // Games can only be aborted until both sides of a board have
+            // played a move
+            if boards.iter().all(|board| board.uci_moves.len() < 2) {
+                self.end_game(seat.board, GameEndResult::Aborted, ctx);
+            } else {
+                self.send_player(
+                    msg.id,
+                    ServerMessage::Err {
+                        what: ServerError::AbortNotAllowed,
+                    },
+                );
+            }
+        }
+    }
+}
+
+impl Handler<OfferDraw> for BughouseRoom {
+    type Result = ();
+
+    fn handle(&mut self, msg: OfferDraw, _ctx: &mut Self::Context) -> Self::Result {
+        self.unsupported(msg.id);
+    }
+}
+
+impl Handler<AcceptDraw> for BughouseRoom {
+    type Result = ();
+
+    fn handle(&mut self, msg: AcceptDraw, _ctx: &mut Self::Context) -> Self::Result {
+        self.unsupported(msg.id);
+    }
+}
+
+impl Handler<DeclineDraw> for BughouseRoom {
+    type Result = ();
+
+    fn handle(&mut self, msg: DeclineDraw, _ctx: &mut Self::Context) -> Self::Result {
+        self.unsupported(msg.id);
+    }
+}
+
+impl Handler<ClaimVictory> for BughouseRoom {
+    type Result = ();
+
+    fn handle(&mut self, msg: ClaimVictory, _ctx: &mut Self::Context) -> Self::Result {
+        self.unsupported(msg.id);
+    }
+}
+
+impl Handler<ClaimDraw> for BughouseRoom {
+    type Result = ();
+
+    fn handle(&mut self, msg: ClaimDraw, _ctx: &mut Self::Context) -> Self::Result {
+        self.unsupported(msg.id);
+    }
+}
+
+impl Handler<Sweep> for BughouseRoom {
+    type Result = ();
+
+    fn handle(&mut self, msg: Sweep, ctx: &mut Self::Context) -> Self::Result {
+        // Correspondence games may be left for days between moves
+        if self.time_control.is_none() && !self.is_open() {
+            return;
+        }
+
+        let connected = |player: &Player| player.sessions.iter().any(|session| session.connected());
+
+        let (present, grace) = match &self.state {
+            GameState::Waiting { seats } => {
+                (seats.iter().flatten().any(connected), msg.waiting_grace)
+            }
+            GameState::Started { players, .. } => {
+                (players.iter().any(connected), msg.abandoned_grace)
+            }
+        };
+
+        if present {
+            self.absent_since = None;
+            return;
+        }
+
+        let now = Instant::now();
+        let absent_since = *self.absent_since.get_or_insert(now);
+
+        if now.duration_since(absent_since) < grace {
+            return;
+        }
+
+        info!("Closing abandoned bughouse room {}", self.room_id);
+
+        match &self.state {
+            GameState::Waiting { .. } => {
+                self.save_closed("*");
+                self.room_manager.do_send(RemoveRoom {
+                    room_id: self.room_id.clone(),
+                });
+                ctx.stop();
+            }
+            GameState::Started { boards, .. } => {
+                // Every player left, no team wins
+                let result = if boards.iter().all(|board| board.uci_moves.len() < 2) {
+                    GameEndResult::Aborted
+                } else {
+                    GameEndResult::AbandonedDraw
+                };
+                self.end_game(0, result, ctx);
+            }
+        }
+    }
+}
+
+impl Handler<MergePlayer> for BughouseRoom {
+    type Result = ();
+
+    fn handle(&mut self, msg: MergePlayer, _ctx: &mut Self::Context) -> Self::Result {
+        let merge = |player: &mut Player| {
+            if player.id == msg.from {
+                player.id = msg.to.id;
+                player.username = msg.to.username.clone();
+            }
+        };
+
+        if self.creator.id == msg.from {
+            self.creator = msg.to.clone();
+        }
+
+        match &mut self.state {
+            GameState::Waiting { seats } => seats.iter_mut().flatten().for_each(merge),
+            GameState::Started {
+                players,
+                spectators,
+                ..
+            } => {
+                players.iter_mut().for_each(merge);
+
+                *spectators = spectators
+                    .drain()
+                    .map(|mut spectator| {
+                        if spectator.id == msg.from {
+                            spectator.id = msg.to.id;
+                        }
+                        spectator
+                    })
+                    .collect();
+            }
+        }
+
+        self.save();
+    }
+}
@@ -0,0 +1,187 @@
+use crate::actors::room::{Clock, ClockState, PlayerColor, TimeControl};
+use crate::util::chess::{get_san, LegalMove};
+use crate::util::variant::{Bughouse, Move, Pockets, Variant};
+
+use chess::{Color, ALL_COLORS};
+use serde::{Deserialize, Serialize};
+use std::time::Instant;
+
+/// Players of a bughouse game
+pub const SEATS: usize = 4;
+
+/// A side of one of the two boards. Seats are numbered white then black of
+/// the first board, then of the second one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Seat {
+    pub board: usize,
+    pub color: Color,
+}
+
+impl Seat {
+    pub fn from_index(index: usize) -> Self {
+        Seat {
+            board: index / 2,
+            color: ALL_COLORS[index % 2],
+        }
+    }
+
+    pub fn index(self) -> usize {
+        self.board * 2 + self.color.to_index()
+    }
+
+    /// Partners play opposite sides on different boards
+    pub fn partner(self) -> Self {
+        Seat {
+            board: 1 - self.board,
+            color: !self.color,
+        }
+    }
+
+    /// Team of the seat, white of the first board playing for team 0
+    pub fn team(self) -> usize {
+        self.board ^ self.color.to_index()
+    }
+}
+
+/// A seat as told to its player
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SeatInfo {
+    pub board: usize,
+    pub color: PlayerColor,
+    pub team: usize,
+}
+
+impl From<Seat> for SeatInfo {
+    fn from(seat: Seat) -> Self {
+        SeatInfo {
+            board: seat.board,
+            color: seat.color.into(),
+            team: seat.team(),
+        }
+    }
+}
+
+/// A board as sent to a session
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BoardState {
+    /// Current position, in X-FEN with the pockets
+    pub fen: String,
+    pub turn: PlayerColor,
+    pub check: bool,
+    pub pockets: Option<Pockets>,
+    pub clock: Option<ClockState>,
+    /// Legal moves of the session, only when it is to move on the board
+    pub dests: Option<Vec<LegalMove>>,
+}
+
+/// One of the two games of a bughouse room
+pub struct Board {
+    pub position: Bughouse,
+    pub uci_moves: Vec<String>,
+    /// Moves in SAN, written when played as drops depend on the other board
+    pub san_moves: Vec<String>,
+    pub clock: Option<Clock>,
+}
+
+impl Board {
+    pub fn new(position: Bughouse, time_control: Option<TimeControl>, now: Instant) -> Self {
+        Board {
+            position,
+            uci_moves: Vec::new(),
+            san_moves: Vec::new(),
+            clock: time_control.map(|time_control| {
+                let mut clock = Clock::new(time_control);
+                clock.start(Color::White, now);
+                clock
+            }),
+        }
+    }
+
+    pub fn side_to_move(&self) -> Color {
+        self.position.side_to_move()
+    }
+
+    pub fn state(&self, dests: Option<Vec<LegalMove>>, now: Instant) -> BoardState {
+        BoardState {
+            fen: self.position.fen(),
+            turn: self.side_to_move().into(),
+            check: self.position.in_check(),
+            pockets: self.position.pockets(),
+            clock: self.clock.as_ref().map(|clock| clock.state(now)),
+            dests,
+        }
+    }
+}
+
+/// Plays `chess_move` on the board `index`, the piece it captures going to
+/// the partner of the mover on the other board. Returns the move in UCI.
+pub fn play(boards: &mut [Board; 2], index: usize, chess_move: Move) -> Option<String> {
+    let board = &mut boards[index];
+    let mover = Seat {
+        board: index,
+        color: board.side_to_move(),
+    };
+    let (next, captured) = board.position.play_capturing(chess_move)?;
+
+    // Castling is stored the same way, however it was played
+    let uci = board.position.uci(chess_move);
+    board.san_moves.push(get_san(&board.position, chess_move));
+    board.uci_moves.push(uci.clone());
+    board.position = next;
+
+    if let Some(piece) = captured {
+        let partner = mover.partner();
+        boards[partner.board].position.receive(partner.color, piece);
+    }
+
+    Some(uci)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::position::Position;
+    use std::str::FromStr;
+
+    #[test]
+    fn partners_sit_on_opposite_boards_and_colors() {
+        for index in 0..SEATS {
+            let seat = Seat::from_index(index);
+
+            assert_eq!(seat.index(), index);
+            assert_eq!(seat.partner().partner(), seat);
+            assert_ne!(seat.partner().board, seat.board);
+            assert_ne!(seat.partner().color, seat.color);
+            assert_eq!(seat.partner().team(), seat.team());
+        }
+
+        let white = Seat::from_index(0);
+        assert_eq!(white.partner(), Seat::from_index(3));
+        assert_eq!(white.team(), 0);
+        assert_eq!(Seat::from_index(1).team(), 1);
+        assert_eq!(Seat::from_index(2).team(), 1);
+    }
+
+    #[test]
+    fn captures_go_to_the_partner() {
+        let now = Instant::now();
+        let board = || Board::new(Bughouse::new(Position::standard()), None, now);
+        let mut boards = [board(), board()];
+
+        for uci in ["e2e4", "d7d5", "e4d5"].iter() {
+            let chess_move = Move::from_str(uci).unwrap();
+            assert_eq!(play(&mut boards, 0, chess_move).as_deref(), Some(*uci));
+        }
+
+        // White of the first board took a pawn for black of the second one
+        assert_eq!(boards[0].position.pockets(), Some(Pockets::default()));
+        let pockets = boards[1].position.pockets().unwrap();
+        assert_eq!(pockets.black.pawn, 1);
+        assert_eq!(pockets.white.pawn, 0);
+        assert_eq!(boards[0].san_moves, ["e4", "d5", "exd5"]);
+
+        let illegal = Move::from_str("e2e4").unwrap();
+        assert_eq!(play(&mut boards, 0, illegal), None);
+        assert_eq!(boards[0].uci_moves.len(), 3);
+    }
+}
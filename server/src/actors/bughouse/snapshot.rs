@@ -0,0 +1,271 @@
+use super::{play, Board, BughouseRoom, GameState, SEATS};
+
+use crate::actors::room::{room_key, Clock, Player, PlayerInfo, TimeControl};
+use crate::actors::room_manager::RoomManager;
+use crate::util::chess::parse_move;
+use crate::util::position::Position;
+use crate::util::redis::{expire, hset, persist};
+use crate::util::variant::{Bughouse, Variant};
+use actix::prelude::*;
+use actix_redis::Command;
+use chrono::NaiveDateTime;
+use sqlx::PgPool;
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+/*
+   Bughouse rooms live in the same `rc:room:{id}` hashes as other rooms:
+
+   kind          bughouse, missing for other rooms
+   status        waiting, started or finished
+   creator       id of the player who created the room
+   creator_name  username of the creator, empty for anonymous players
+   creator_rating
+                 rating of the creator, missing for anonymous players
+   created_at    creation date, in milliseconds since the epoch
+   time_control  JSON time control, missing for untimed games
+   white_0, black_0, white_1, black_1
+                 ids of the players seated on each side of both boards,
+                 missing for free seats
+   white_0_name, ...
+                 usernames of the players
+   fen_0, fen_1  current position of each board, in X-FEN with the pockets
+   moves         UCI moves of both boards in the order they were played,
+                 each prefixed with its board as in `0:e2e4 1:d2d4`
+   clocks_0, clocks_1
+                 time left to the mover after each move of a board, in
+                 milliseconds
+   started_at    start date, in milliseconds since the epoch
+   result        scores of both boards once finished, as in `1-0 0-1`
+*/
+
+/// Fields holding the id and username of the player of each seat
+const SEAT_FIELDS: [(&str, &str); SEATS] = [
+    ("white_0", "white_0_name"),
+    ("black_0", "black_0_name"),
+    ("white_1", "white_1_name"),
+    ("black_1", "black_1_name"),
+];
+const FEN_FIELDS: [&str; 2] = ["fen_0", "fen_1"];
+const CLOCK_FIELDS: [&str; 2] = ["clocks_0", "clocks_1"];
+
+/// Rooms untouched for this long are gone from Redis, in case their node
+/// never comes back
+const ROOM_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+/// Closed rooms stay readable for a while
+const CLOSED_ROOM_TTL: Duration = Duration::from_secs(10 * 60);
+
+impl BughouseRoom {
+    /// Writes the whole room to Redis
+    pub(super) fn save(&self) {
+        self.redis
+            .do_send(hset(room_key(&self.room_id), self.snapshot()))
+            .ok();
+
+        // Correspondence games may be left for days between moves, they are
+        // kept until they end
+        let key = room_key(&self.room_id);
+        let command = if self.time_control.is_none() && !self.is_open() {
+            persist(key)
+        } else {
+            expire(key, ROOM_TTL)
+        };
+        self.redis.do_send(command).ok();
+    }
+
+    /// Marks the room as finished, it won't be restored anymore
+    pub(super) fn save_closed(&self, result: &str) {
+        self.redis
+            .do_send(hset(
+                room_key(&self.room_id),
+                vec![
+                    ("status", "finished".to_string()),
+                    ("result", result.to_string()),
+                ],
+            ))
+            .ok();
+        self.redis
+            .do_send(expire(room_key(&self.room_id), CLOSED_ROOM_TTL))
+            .ok();
+    }
+
+    fn snapshot(&self) -> Vec<(&'static str, String)> {
+        let mut fields = vec![
+            ("kind", "bughouse".to_string()),
+            ("creator", self.creator.id.to_string()),
+            (
+                "creator_name",
+                self.creator.username.clone().unwrap_or_default(),
+            ),
+            ("created_at", self.created_at.timestamp_millis().to_string()),
+        ];
+
+        if let Some(rating) = self.creator_rating {
+            fields.push(("creator_rating", rating.to_string()));
+        }
+
+        if let Some(time_control) = &self.time_control {
+            if let Ok(time_control) = serde_json::to_string(time_control) {
+                fields.push(("time_control", time_control));
+            }
+        }
+
+        let seats: Vec<Option<&Player>> = match &self.state {
+            GameState::Waiting { seats } => seats.iter().map(Option::as_ref).collect(),
+            GameState::Started { players, .. } => players.iter().map(Some).collect(),
+        };
+
+        // Seats left by a player while waiting are emptied
+        for (player, (id, name)) in seats.iter().zip(SEAT_FIELDS.iter()) {
+            let (player_id, username) = match player {
+                Some(player) => (player.id.to_string(), player.username.clone()),
+                None => (String::new(), None),
+            };
+            fields.push((*id, player_id));
+            fields.push((*name, username.unwrap_or_default()));
+        }
+
+        match &self.state {
+            GameState::Waiting { .. } => fields.push(("status", "waiting".to_string())),
+            GameState::Started {
+                boards,
+                played,
+                started_at,
+                ..
+            } => {
+                let mut next = [0, 0];
+                let moves: Vec<String> = played
+                    .iter()
+                    .map(|board| {
+                        let uci = &boards[*board].uci_moves[next[*board]];
+                        next[*board] += 1;
+                        format!("{}:{}", board, uci)
+                    })
+                    .collect();
+
+                fields.extend(vec![
+                    ("status", "started".to_string()),
+                    ("moves", moves.join(" ")),
+                    ("started_at", started_at.timestamp_millis().to_string()),
+                ]);
+
+                for (index, board) in boards.iter().enumerate() {
+                    let clocks: Vec<String> = board
+                        .clock
+                        .as_ref()
+                        .map(|clock| {
+                            clock
+                                .history()
+                                .iter()
+                                .map(|left| left.as_millis().to_string())
+                                .collect()
+                        })
+                        .unwrap_or_default();
+
+                    fields.push((FEN_FIELDS[index], board.position.fen()));
+                    fields.push((CLOCK_FIELDS[index], clocks.join(",")));
+                }
+            }
+        }
+
+        fields
+    }
+
+    /// Rebuilds a bughouse room from its Redis hash, finished or unreadable
+    /// rooms are ignored
+    pub fn restore(
+        room_id: String,
+        fields: &HashMap<String, String>,
+        room_manager: Addr<RoomManager>,
+        redis: Recipient<Command>,
+        db_pool: PgPool,
+    ) -> Option<BughouseRoom> {
+        let field = |name: &str| fields.get(name).map(String::as_str);
+        let uuid = |name: &str| field(name).and_then(|id| Uuid::parse_str(id).ok());
+        let player = |id: &str, name: &str| {
+            Some(PlayerInfo {
+                id: uuid(id)?,
+                username: field(name)
+                    .filter(|username| !username.is_empty())
+                    .map(String::from),
+            })
+        };
+        let date = |name: &str| {
+            field(name)
+                .and_then(|millis| millis.parse::<i64>().ok())
+                .map(|millis| {
+                    NaiveDateTime::from_timestamp(
+                        millis.div_euclid(1000),
+                        (millis.rem_euclid(1000) * 1_000_000) as u32,
+                    )
+                })
+        };
+
+        let time_control: Option<TimeControl> =
+            field("time_control").and_then(|json| serde_json::from_str(json).ok());
+
+        let mut room = BughouseRoom::new(
+            room_id,
+            player("creator", "creator_name")?,
+            time_control,
+            room_manager,
+            redis,
+            db_pool,
+        );
+        room.created_at = date("created_at")?;
+        room.creator_rating = field("creator_rating").and_then(|rating| rating.parse().ok());
+
+        let seats: Vec<Option<Player>> = SEAT_FIELDS
+            .iter()
+            .map(|(id, name)| player(id, name).map(Player::new))
+            .collect();
+
+        match field("status")? {
+            "waiting" => room.state = GameState::Waiting { seats },
+            "started" => {
+                let now = Instant::now();
+                let initial = Bughouse::new(Position::standard());
+                let mut boards = [
+                    Board::new(initial, None, now),
+                    Board::new(initial, None, now),
+                ];
+                let mut played = Vec::new();
+
+                for entry in field("moves")?.split_whitespace() {
+                    let (board, uci) = entry.split_once(':')?;
+                    let board: usize = board.parse().ok().filter(|board| *board < 2)?;
+                    let chess_move = parse_move(&boards[board].position, uci).ok()?;
+
+                    play(&mut boards, board, chess_move)?;
+                    played.push(board);
+                }
+
+                // Players get back the time spent while the server was down
+                for (board, clocks) in boards.iter_mut().zip(CLOCK_FIELDS.iter()) {
+                    let history = field(clocks)?
+                        .split(',')
+                        .filter(|millis| !millis.is_empty())
+                        .map(|millis| millis.parse().map(Duration::from_millis))
+                        .collect::<Result<Vec<_>, _>>()
+                        .ok()?;
+                    let turn = board.side_to_move();
+
+                    board.clock = time_control
+                        .map(|time_control| Clock::resume(time_control, history, turn, now));
+                }
+
+                room.state = GameState::Started {
+                    spectators: HashSet::new(),
+                    players: seats.into_iter().collect::<Option<Vec<Player>>>()?,
+                    boards,
+                    played,
+                    started_at: date("started_at")?,
+                };
+            }
+            _ => return None,
+        }
+
+        Some(room)
+    }
+}
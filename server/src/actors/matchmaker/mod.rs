@@ -0,0 +1,166 @@
+pub mod model;
+
+pub use model::*;
+
+use super::room::{ColorChoice, Speed};
+use super::room_manager::{self, RoomManager};
+use super::websocket::{self, model::ServerError, model::ServerMessage};
+use crate::app::ratings::glicko::DEFAULT_RATING;
+use crate::app::ratings::model::Rating;
+use actix::prelude::*;
+use chess::Color;
+use indexmap::IndexMap;
+use log::error;
+use rand::rngs::StdRng;
+use sqlx::PgPool;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+/// How often waiting seeks are matched again, as their rating windows widen
+const PAIRING_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Pairs players looking for a game.
+///
+/// Every node has its own pool, seeks are only paired with seeks made on
+/// the same node.
+pub struct Matchmaker {
+    /// Seeks by player, oldest first
+    seeks: IndexMap<Uuid, SeekData>,
+    room_manager: Addr<RoomManager>,
+    db_pool: PgPool,
+    /// Draws the colours when neither player chose one
+    rng: StdRng,
+}
+
+impl Matchmaker {
+    pub fn new(room_manager: Addr<RoomManager>, db_pool: PgPool, rng: StdRng) -> Self {
+        Self {
+            seeks: IndexMap::new(),
+            room_manager,
+            db_pool,
+            rng,
+        }
+    }
+}
+
+impl Matchmaker {
+    fn add(&mut self, seek: Seek, rating: f64) {
+        // A player only has one seek at a time
+        self.seeks.shift_remove(&seek.player.id);
+        self.seeks.insert(
+            seek.player.id,
+            SeekData {
+                seek,
+                rating,
+                created_at: Instant::now(),
+            },
+        );
+
+        self.pair();
+    }
+
+    /// Starts a game for every pair of compatible seeks, oldest seeks first
+    fn pair(&mut self) {
+        let now = Instant::now();
+
+        // Players who closed the lobby are not waiting anymore
+        self.seeks.retain(|_, data| data.seek.session.connected());
+
+        let mut i = 0;
+        while i < self.seeks.len() {
+            let (_, data) = self.seeks.get_index(i).unwrap();
+            let opponent = self
+                .seeks
+                .values()
+                .skip(i + 1)
+                .position(|other| data.accepts(other, now) && other.accepts(data, now));
+
+            match opponent {
+                Some(j) => {
+                    let (_, other) = self.seeks.shift_remove_index(i + 1 + j).unwrap();
+                    let (_, data) = self.seeks.shift_remove_index(i).unwrap();
+                    self.start_game(data, other);
+                }
+                None => i += 1,
+            }
+        }
+    }
+
+    fn start_game(&mut self, a: SeekData, b: SeekData) {
+        // Seeks are only paired when their choices agree
+        let a_color = match (a.seek.color, b.seek.color) {
+            (ColorChoice::Random, ColorChoice::White) => Color::Black,
+            (ColorChoice::Random, ColorChoice::Black) => Color::White,
+            (color, _) => color.resolve(&mut self.rng),
+        };
+        let (white, black) = match a_color {
+            Color::White => (a, b),
+            Color::Black => (b, a),
+        };
+
+        self.room_manager.do_send(room_manager::CreatePaired {
+            white_rating: white
+                .seek
+                .player
+                .username
+                .as_ref()
+                .map(|_| white.rating.round() as i32),
+            white: white.seek.player,
+            black: black.seek.player,
+            time_control: white.seek.time_control,
+            rated: white.seek.rated,
+            white_session: white.seek.session,
+            black_session: black.seek.session,
+        });
+    }
+}
+
+impl Actor for Matchmaker {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.run_interval(PAIRING_INTERVAL, |act, _ctx| act.pair());
+    }
+}
+
+impl Handler<Seek> for Matchmaker {
+    type Result = ();
+
+    fn handle(&mut self, msg: Seek, ctx: &mut Self::Context) -> Self::Result {
+        if msg.player.username.is_none() {
+            if msg.rated {
+                msg.session.do_send(websocket::Send(ServerMessage::Err {
+                    what: ServerError::LoginRequired,
+                }));
+            } else {
+                self.add(msg, DEFAULT_RATING);
+            }
+            return;
+        }
+
+        let db_pool = self.db_pool.clone();
+        let id = msg.player.id;
+        let speed = Speed::of(msg.time_control);
+
+        async move { Rating::find(&db_pool, id, speed).await }
+            .into_actor(self)
+            .map(move |res, act, _ctx| match res {
+                Ok(glicko) => act.add(msg, glicko.rating),
+                Err(e) => {
+                    error!("Could not find rating of {}: {:?}", id, e);
+                    msg.session.do_send(websocket::Send(ServerMessage::Err {
+                        what: ServerError::InternalError,
+                    }));
+                }
+            })
+            .spawn(ctx);
+    }
+}
+
+impl Handler<CancelSeek> for Matchmaker {
+    type Result = ();
+
+    fn handle(&mut self, msg: CancelSeek, _ctx: &mut Self::Context) -> Self::Result {
+        self.seeks.shift_remove(&msg.id);
+    }
+}
@@ -0,0 +1,171 @@
+use crate::actors::room::{ColorChoice, PlayerInfo, TimeControl};
+use crate::actors::websocket::WebsocketSession;
+
+use actix::prelude::*;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+/// Rating difference accepted on top of the range asked by the player, for
+/// every `RANGE_INTERVAL` spent waiting
+const RANGE_STEP: f64 = 50.0;
+const RANGE_INTERVAL: Duration = Duration::from_secs(5);
+/// The rating window stops widening past this difference
+const MAX_RANGE: f64 = 800.0;
+
+/// A player looking for an opponent
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Seek {
+    pub player: PlayerInfo,
+    pub session: Addr<WebsocketSession>,
+    pub time_control: Option<TimeControl>,
+    pub rated: bool,
+    /// Largest rating difference accepted with the opponent, any opponent
+    /// is accepted without one
+    pub rating_range: Option<u32>,
+    pub color: ColorChoice,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct CancelSeek {
+    pub id: Uuid,
+}
+
+pub struct SeekData {
+    pub seek: Seek,
+    /// Rating of the player in the speed of the seek
+    pub rating: f64,
+    pub created_at: Instant,
+}
+
+impl SeekData {
+    /// Largest rating difference accepted after waiting since `created_at`
+    fn window(&self, now: Instant) -> Option<f64> {
+        let waited = now.duration_since(self.created_at).as_secs() / RANGE_INTERVAL.as_secs();
+
+        self.seek.rating_range.map(|range| {
+            let range = range as f64;
+            // Ranges wider than the limit are kept as asked
+            (range + RANGE_STEP * waited as f64).min(range.max(MAX_RANGE))
+        })
+    }
+
+    /// Whether the player of this seek would play the one of `other`
+    pub fn accepts(&self, other: &SeekData, now: Instant) -> bool {
+        let colors = !matches!(
+            (self.seek.color, other.seek.color),
+            (ColorChoice::White, ColorChoice::White) | (ColorChoice::Black, ColorChoice::Black)
+        );
+
+        self.seek.player.id != other.seek.player.id
+            && self.seek.time_control == other.seek.time_control
+            && self.seek.rated == other.seek.rated
+            && colors
+            && self
+                .window(now)
+                .map_or(true, |window| (self.rating - other.rating).abs() <= window)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix::dev::channel;
+
+    fn seek(rating: f64, rating_range: Option<u32>, color: ColorChoice) -> SeekData {
+        // The session is never reached, its mailbox is enough
+        let (sender, _) = channel::channel(1);
+
+        SeekData {
+            seek: Seek {
+                player: PlayerInfo {
+                    id: Uuid::new_v4(),
+                    username: None,
+                },
+                session: Addr::new(sender),
+                time_control: Some(TimeControl {
+                    base: 300,
+                    increment: 3,
+                    delay: None,
+                }),
+                rated: false,
+                rating_range,
+                color,
+            },
+            rating,
+            created_at: Instant::now(),
+        }
+    }
+
+    #[test]
+    fn the_window_widens_while_waiting() {
+        let data = seek(1500.0, Some(100), ColorChoice::Random);
+        let after = |secs| data.created_at + Duration::from_secs(secs);
+
+        assert_eq!(data.window(after(0)), Some(100.0));
+        assert_eq!(data.window(after(4)), Some(100.0));
+        assert_eq!(data.window(after(5)), Some(150.0));
+        assert_eq!(data.window(after(60)), Some(700.0));
+        assert_eq!(data.window(after(3600)), Some(MAX_RANGE));
+
+        let wide = seek(1500.0, Some(1000), ColorChoice::Random);
+        assert_eq!(wide.window(after(3600)), Some(1000.0));
+
+        let open = seek(1500.0, None, ColorChoice::Random);
+        assert_eq!(open.window(after(0)), None);
+    }
+
+    #[test]
+    fn waiting_players_accept_farther_opponents() {
+        let data = seek(1500.0, Some(100), ColorChoice::Random);
+        let other = seek(1640.0, None, ColorChoice::Random);
+        let now = data.created_at;
+
+        assert!(!data.accepts(&other, now));
+        assert!(data.accepts(&other, now + RANGE_INTERVAL));
+        assert!(other.accepts(&data, now));
+    }
+
+    #[test]
+    fn colors_must_be_compatible() {
+        use ColorChoice::*;
+
+        let accepts = |a, b| {
+            let data = seek(1500.0, None, a);
+            data.accepts(&seek(1500.0, None, b), data.created_at)
+        };
+
+        assert!(!accepts(White, White));
+        assert!(!accepts(Black, Black));
+        assert!(accepts(White, Black));
+        assert!(accepts(Black, Random));
+        assert!(accepts(Random, Random));
+    }
+
+    #[test]
+    fn settings_must_match() {
+        let data = seek(1500.0, None, ColorChoice::Random);
+        let now = data.created_at;
+
+        let mut rated = seek(1500.0, None, ColorChoice::Random);
+        rated.seek.rated = true;
+        assert!(!data.accepts(&rated, now));
+
+        let mut slower = seek(1500.0, None, ColorChoice::Random);
+        slower.seek.time_control = Some(TimeControl {
+            base: 600,
+            increment: 3,
+            delay: None,
+        });
+        assert!(!data.accepts(&slower, now));
+
+        let mut unlimited = seek(1500.0, None, ColorChoice::Random);
+        unlimited.seek.time_control = None;
+        assert!(!data.accepts(&unlimited, now));
+
+        let mut same_player = seek(1500.0, None, ColorChoice::Random);
+        same_player.seek.player.id = data.seek.player.id;
+        assert!(!data.accepts(&same_player, now));
+    }
+}
@@ -1,3 +1,6 @@
+pub mod bughouse;
+pub mod matchmaker;
 pub mod room;
 pub mod room_manager;
+pub mod sweeper;
 pub mod websocket;
\ No newline at end of file
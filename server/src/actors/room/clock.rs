@@ -0,0 +1,250 @@
+use super::model::{ClockState, Delay, TimeControl};
+
+use chess::Color;
+use std::time::{Duration, Instant};
+
+/// A pair of chess clocks following a `TimeControl`.
+///
+/// Remaining times are only updated when the clock is pressed, the time
+/// spent on the current turn is derived from `turn_started`.
+#[derive(Debug, Clone)]
+pub struct Clock {
+    control: TimeControl,
+    white: Duration,
+    black: Duration,
+    turn: Color,
+    turn_started: Option<Instant>,
+    /// Time left to the mover after each move
+    history: Vec<Duration>,
+}
+
+impl Clock {
+    pub fn new(control: TimeControl) -> Self {
+        let base = Duration::from_secs(control.base);
+
+        Self {
+            control,
+            white: base,
+            black: base,
+            turn: Color::White,
+            turn_started: None,
+            history: Vec::new(),
+        }
+    }
+
+    /// Rebuilds a clock from the time left after each move, `turn` being the
+    /// side to move
+    pub fn resume(control: TimeControl, history: Vec<Duration>, turn: Color, now: Instant) -> Self {
+        let mut clock = Clock::new(control);
+
+        // The last entry belongs to the side that just moved
+        let mut entries = history.iter().rev();
+        if let Some(left) = entries.next() {
+            *clock.stored_mut(!turn) = *left;
+        }
+        if let Some(left) = entries.next() {
+            *clock.stored_mut(turn) = *left;
+        }
+
+        clock.history = history;
+        clock.start(turn, now);
+
+        clock
+    }
+
+    /// Starts the clock of `color`
+    pub fn start(&mut self, color: Color, now: Instant) {
+        self.turn = color;
+        self.turn_started = Some(now);
+    }
+
+    /// Ends the turn of the running side and starts the opponent's clock.
+    ///
+    /// Returns false if the side to move had already run out of time, in
+    /// which case the clock is left untouched.
+    pub fn press(&mut self, now: Instant) -> bool {
+        let started = match self.turn_started {
+            Some(started) => started,
+            None => {
+                self.start(!self.turn, now);
+                return true;
+            }
+        };
+
+        let elapsed = now.saturating_duration_since(started);
+        let charged = self.charged(elapsed);
+
+        if charged >= self.stored(self.turn) {
+            return false;
+        }
+
+        let refund = match self.control.delay {
+            Some(Delay::Bronstein { seconds }) => elapsed.min(Duration::from_secs(seconds)),
+            _ => Duration::ZERO,
+        };
+        let increment = Duration::from_secs(self.control.increment);
+
+        let stored = self.stored_mut(self.turn);
+        *stored = (*stored - charged)
+            .saturating_add(refund)
+            .saturating_add(increment);
+        let left = *stored;
+        self.history.push(left);
+
+        self.start(!self.turn, now);
+
+        true
+    }
+
+    /// Time left to `color` at `now`
+    pub fn remaining(&self, color: Color, now: Instant) -> Duration {
+        match self.turn_started {
+            Some(started) if color == self.turn => self
+                .stored(color)
+                .saturating_sub(self.charged(now.saturating_duration_since(started))),
+            _ => self.stored(color),
+        }
+    }
+
+    /// Time until the running side flags, if the clock is running
+    pub fn time_to_flag(&self, now: Instant) -> Option<Duration> {
+        self.turn_started.map(|started| {
+            self.stored(self.turn)
+                .saturating_add(self.simple_delay())
+                .saturating_sub(now.saturating_duration_since(started))
+        })
+    }
+
+    /// The side that ran out of time, if any
+    pub fn flagged(&self, now: Instant) -> Option<Color> {
+        match self.time_to_flag(now) {
+            Some(left) if left == Duration::ZERO => Some(self.turn),
+            _ => None,
+        }
+    }
+
+    pub fn history(&self) -> &[Duration] {
+        &self.history
+    }
+
+    pub fn state(&self, now: Instant) -> ClockState {
+        ClockState {
+            white: self.remaining(Color::White, now).as_millis() as u64,
+            black: self.remaining(Color::Black, now).as_millis() as u64,
+        }
+    }
+
+    fn charged(&self, elapsed: Duration) -> Duration {
+        elapsed.saturating_sub(self.simple_delay())
+    }
+
+    fn simple_delay(&self) -> Duration {
+        match self.control.delay {
+            Some(Delay::Simple { seconds }) => Duration::from_secs(seconds),
+            _ => Duration::ZERO,
+        }
+    }
+
+    fn stored(&self, color: Color) -> Duration {
+        match color {
+            Color::White => self.white,
+            Color::Black => self.black,
+        }
+    }
+
+    fn stored_mut(&mut self, color: Color) -> &mut Duration {
+        match color {
+            Color::White => &mut self.white,
+            Color::Black => &mut self.black,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECOND: Duration = Duration::from_secs(1);
+
+    fn clock(base: u64, increment: u64, delay: Option<Delay>, now: Instant) -> Clock {
+        let mut clock = Clock::new(TimeControl {
+            base,
+            increment,
+            delay,
+        });
+        clock.start(Color::White, now);
+        clock
+    }
+
+    #[test]
+    fn press_charges_the_mover_and_adds_the_increment() {
+        let now = Instant::now();
+        let mut clock = clock(60, 2, None, now);
+
+        assert!(clock.press(now + 10 * SECOND));
+        assert_eq!(
+            clock.remaining(Color::White, now + 10 * SECOND),
+            52 * SECOND
+        );
+        assert_eq!(
+            clock.remaining(Color::Black, now + 15 * SECOND),
+            55 * SECOND
+        );
+        assert_eq!(clock.history(), &[52 * SECOND]);
+    }
+
+    #[test]
+    fn bronstein_delay_refunds_up_to_its_length() {
+        let now = Instant::now();
+        let mut clock = clock(60, 0, Some(Delay::Bronstein { seconds: 3 }), now);
+
+        assert!(clock.press(now + 2 * SECOND));
+        assert!(clock.press(now + 12 * SECOND));
+        assert_eq!(clock.history(), &[60 * SECOND, 53 * SECOND]);
+    }
+
+    #[test]
+    fn simple_delay_passes_before_the_clock_runs() {
+        let now = Instant::now();
+        let clock = clock(60, 0, Some(Delay::Simple { seconds: 5 }), now);
+
+        assert_eq!(clock.remaining(Color::White, now + 4 * SECOND), 60 * SECOND);
+        assert_eq!(clock.remaining(Color::White, now + 8 * SECOND), 57 * SECOND);
+        assert_eq!(clock.time_to_flag(now), Some(65 * SECOND));
+    }
+
+    #[test]
+    fn late_press_flags_and_leaves_the_clock() {
+        let now = Instant::now();
+        let mut clock = clock(10, 5, None, now);
+
+        assert_eq!(clock.flagged(now + 9 * SECOND), None);
+        assert_eq!(clock.flagged(now + 10 * SECOND), Some(Color::White));
+        assert!(!clock.press(now + 11 * SECOND));
+        assert!(clock.history().is_empty());
+    }
+
+    #[test]
+    fn resume_restores_both_sides_from_history() {
+        let now = Instant::now();
+        let control = TimeControl {
+            base: 60,
+            increment: 0,
+            delay: None,
+        };
+        let clock = Clock::resume(control, vec![50 * SECOND, 40 * SECOND], Color::White, now);
+
+        assert_eq!(clock.remaining(Color::White, now), 50 * SECOND);
+        assert_eq!(clock.remaining(Color::Black, now), 40 * SECOND);
+        assert_eq!(clock.time_to_flag(now + 20 * SECOND), Some(30 * SECOND));
+    }
+
+    #[test]
+    fn huge_increments_saturate() {
+        let now = Instant::now();
+        let mut clock = clock(60, u64::MAX, None, now);
+
+        assert!(clock.press(now + SECOND));
+        assert_eq!(clock.history(), &[Duration::MAX]);
+    }
+}
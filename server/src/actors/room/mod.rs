@@ -1,68 +1,128 @@
+pub mod clock;
 pub mod model;
+mod snapshot;
 
+pub use clock::Clock;
 pub use model::*;
+pub use snapshot::room_key;
 
 use super::websocket::model::{ServerError, ServerMessage};
-use super::websocket::{self, Send};
+use super::websocket::Send;
 
-use crate::actors::room::PlayerColor::White;
-use crate::actors::room_manager::{RemoveRoom, RoomManager};
-use crate::actors::websocket::WebsocketSession;
-use crate::util::chess::{get_dests};
+use crate::actors::room_manager::{GameLobbyRoom, LobbyRoom, RemoveRoom, RoomManager, RoomStarted};
+use crate::app::games::model::{self as games, NewGame};
+use crate::app::ratings::model::{GameRatings, Rating};
+use crate::util::chess::{get_dests, get_san_moves, parse_move};
+use crate::util::position::STANDARD_POSITION;
+use crate::util::variant::{self, ChessGame, DrawOffer};
 use actix::prelude::*;
 use actix_redis::Command;
-use chess::{ChessMove, Color, Game, GameResult, MoveGen, Square, BitBoard};
-use color_eyre::owo_colors::OwoColorize;
-use log::info;
+use chess::Color;
+use chrono::{NaiveDateTime, Utc};
+use log::{error, info};
+use rand::rngs::StdRng;
 use rand::Rng;
-use redis_async::resp_array;
-use std::collections::{HashMap, HashSet};
-use std::str::FromStr;
+use sqlx::PgPool;
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
 use uuid::Uuid;
-use actix_web::web::get;
+
+/// How long a player can be gone before their opponent may claim the game
+const DISCONNECT_GRACE: Duration = Duration::from_secs(30);
 
 /*
    DISCLAIMER: THIS IS A MESS, I WILL FIX IT
 */
 
+#[allow(clippy::large_enum_variant)]
 pub enum GameState {
     Waiting,
     Started {
         spectators: HashSet<Spectator>,
         players: Players,
-        game: Game,
+        game: ChessGame,
+        clock: Option<Clock>,
+        /// Side allowed to claim the game, its opponent left
+        claim: Option<Color>,
+        started_at: NaiveDateTime,
     },
 }
 
 pub struct Room {
     room_id: String,
     creator: Player,
+    /// Rating of the creator when the room was created
+    creator_rating: Option<i32>,
+    time_control: Option<TimeControl>,
+    /// Whether the game changes the ratings of the players
+    rated: bool,
+    /// Side played by the creator
+    color: ColorChoice,
+    variant: Variant,
+    /// Number of the Chess960 start position, the standard one for other
+    /// variants
+    start_position: u16,
+    created_at: NaiveDateTime,
     state: GameState,
+    flag_timer: Option<SpawnHandle>,
+    /// Timers of the players who left the game, by color index
+    away_timers: [Option<SpawnHandle>; 2],
+    /// Since when no player has been connected, as last seen by a sweep
+    absent_since: Option<Instant>,
     room_manager: Addr<RoomManager>,
     redis: Recipient<Command>,
+    db_pool: PgPool,
+    /// Draws the colours of random colour choices and Chess960 positions
+    rng: StdRng,
 }
 
 impl Room {
     pub fn new(
         room_id: String,
-        creator: Uuid,
+        creator: PlayerInfo,
+        settings: RoomSettings,
         room_manager: Addr<RoomManager>,
         redis: Recipient<Command>,
+        db_pool: PgPool,
+        mut rng: StdRng,
     ) -> Self {
+        let start_position = match settings.variant {
+            Variant::Chess960 => rng.gen_range(0..960),
+            _ => STANDARD_POSITION,
+        };
+
         Self {
             room_id,
-            creator: Player {
-                id: creator,
-                session: None,
-            },
+            creator: Player::new(creator),
+            creator_rating: None,
+            time_control: settings.time_control,
+            rated: settings.rated,
+            color: settings.color,
+            variant: settings.variant,
+            start_position,
+            created_at: Utc::now().naive_utc(),
             room_manager,
             redis,
+            db_pool,
+            rng,
             state: GameState::Waiting,
+            flag_timer: None,
+            away_timers: [None, None],
+            absent_since: None,
         }
     }
 }
 
 impl Room {
+    pub fn with_creator_rating(mut self, rating: Option<i32>) -> Self {
+        self.creator_rating = rating;
+        self
+    }
+
+    fn initial_position(&self) -> Box<dyn variant::Variant> {
+        variant::initial_position(self.variant, self.start_position)
+    }
+
     fn send_message(&self, message: ServerMessage, to: UserType) {
         // TODO: Make more efficient
         match &self.state {
@@ -76,39 +136,353 @@ impl Room {
             } => match to {
                 UserType::Spectator => {
                     for spectator in spectators.iter() {
-                        &spectator.session.do_send(Send(message.clone()));
+                        spectator.session.do_send(Send(message.clone())).ok();
                     }
                 }
                 UserType::Player(color) => match color {
-                    PlayerColor::White => {
-                        if let Some(session) = &players.w.session {
-                            session.do_send(Send(message))
-                        }
-                    }
-                    PlayerColor::Black => {
-                        if let Some(session) = &players.b.session {
-                            session.do_send(Send(message))
-                        }
-                    }
+                    PlayerColor::White => players.w.send(&message),
+                    PlayerColor::Black => players.b.send(&message),
                     PlayerColor::All => {
-                        if let Some(session) = &players.w.session {
-                            session.do_send(Send(message.clone()))
-                        }
-                        if let Some(session) = &players.b.session {
-                            session.do_send(Send(message))
-                        }
+                        players.w.send(&message);
+                        players.b.send(&message);
                     }
                 },
             },
         }
     }
+
+    /// Arms a timer firing when the side to move runs out of time
+    fn schedule_flag(&mut self, ctx: &mut Context<Self>) {
+        if let Some(handle) = self.flag_timer.take() {
+            ctx.cancel_future(handle);
+        }
+
+        if let GameState::Started {
+            clock: Some(clock), ..
+        } = &self.state
+        {
+            if let Some(left) = clock.time_to_flag(Instant::now()) {
+                self.flag_timer = Some(ctx.run_later(left, |act, ctx| act.check_flag(ctx)));
+            }
+        }
+    }
+
+    fn check_flag(&mut self, ctx: &mut Context<Self>) {
+        self.flag_timer = None;
+
+        let flagged = match &self.state {
+            GameState::Started {
+                clock: Some(clock), ..
+            } => clock.flagged(Instant::now()),
+            _ => None,
+        };
+
+        match flagged {
+            Some(color) => self.end_game(GameEndResult::out_of_time(color), ctx),
+            None => self.schedule_flag(ctx),
+        }
+    }
+
+    /// Lets the opponent of `color` claim the game if the player doesn't
+    /// come back within the grace period
+    fn player_left(&mut self, color: Color, ctx: &mut Context<Self>) {
+        let index = color.to_index();
+        if let Some(handle) = self.away_timers[index].take() {
+            ctx.cancel_future(handle);
+        }
+
+        self.away_timers[index] = Some(ctx.run_later(DISCONNECT_GRACE, move |act, _ctx| {
+            act.away_timers[index] = None;
+            act.offer_claim(color);
+        }));
+    }
+
+    fn offer_claim(&mut self, gone: Color) {
+        if let GameState::Started { players, claim, .. } = &mut self.state {
+            let player = match gone {
+                Color::White => &players.w,
+                Color::Black => &players.b,
+            };
+
+            if !player.is_present() {
+                *claim = Some(!gone);
+                self.send_message(
+                    ServerMessage::ClaimAvailable,
+                    UserType::Player((!gone).into()),
+                );
+            }
+        }
+    }
+
+    /// Cancels the claims against a player who came back, telling the
+    /// others, and tells the new session whether the opponent is gone
+    fn player_joined(&mut self, color: Color, session: &Recipient<Send>, ctx: &mut Context<Self>) {
+        let timer = self.away_timers[color.to_index()].take();
+        let was_away = timer.is_some();
+        if let Some(handle) = timer {
+            ctx.cancel_future(handle);
+        }
+
+        if let GameState::Started { claim, .. } = &mut self.state {
+            if was_away || *claim == Some(!color) {
+                *claim = None;
+
+                let message = ServerMessage::PlayerReconnected {
+                    color: color.into(),
+                };
+                self.send_message(message.clone(), UserType::Spectator);
+                self.send_message(message, UserType::Player((!color).into()));
+            }
+        }
+
+        if let GameState::Started { players, claim, .. } = &self.state {
+            let opponent = match color {
+                Color::White => &players.b,
+                Color::Black => &players.w,
+            };
+
+            if !opponent.is_present() {
+                session
+                    .do_send(Send(ServerMessage::PlayerDisconnected {
+                        color: (!color).into(),
+                    }))
+                    .ok();
+            }
+
+            if *claim == Some(color) {
+                session.do_send(Send(ServerMessage::ClaimAvailable)).ok();
+            }
+        }
+    }
+
+    /// Arms the timers of the players who aren't connected yet, as players
+    /// of paired and restored games
+    fn watch_absent(&mut self, ctx: &mut Context<Self>) {
+        let absent: Vec<Color> = match &self.state {
+            GameState::Started { players, .. } => [Color::White, Color::Black]
+                .iter()
+                .copied()
+                .filter(|color| match color {
+                    Color::White => !players.w.is_present(),
+                    Color::Black => !players.b.is_present(),
+                })
+                .collect(),
+            GameState::Waiting => Vec::new(),
+        };
+
+        for color in absent {
+            self.player_left(color, ctx);
+        }
+    }
+
+    fn start(&mut self, players: Players, ctx: &mut Context<Self>) {
+        let (white, black) = (
+            players.w.public(&self.room_id),
+            players.b.public(&self.room_id),
+        );
+
+        let game = ChessGame::new(self.initial_position());
+        let now = Instant::now();
+
+        let clock = self.time_control.map(|time_control| {
+            let mut clock = Clock::new(time_control);
+            clock.start(Color::White, now);
+            clock
+        });
+        let clock_state = clock.as_ref().map(|clock| clock.state(now));
+
+        let fen = game.current_position().fen();
+        let dests = get_dests(game.current_position());
+
+        self.state = GameState::Started {
+            spectators: HashSet::new(),
+            players,
+            game,
+            clock,
+            claim: None,
+            started_at: Utc::now().naive_utc(),
+        };
+
+        // TODO: make shorter
+        self.send_message(
+            ServerMessage::Start {
+                color: PlayerColor::Black,
+                white: white.clone(),
+                black: black.clone(),
+                fen: fen.clone(),
+                dests: None,
+                clock: clock_state,
+            },
+            UserType::Player(PlayerColor::Black),
+        );
+
+        self.send_message(
+            ServerMessage::Start {
+                color: PlayerColor::White,
+                white,
+                black,
+                fen,
+                dests: Some(dests),
+                clock: clock_state,
+            },
+            UserType::Player(PlayerColor::White),
+        );
+
+        self.save();
+        self.room_manager.do_send(RoomStarted {
+            room_id: self.room_id.clone(),
+        });
+
+        self.schedule_flag(ctx);
+        self.watch_absent(ctx);
+    }
+
+    /// Whether the room waits for players
+    pub fn is_open(&self) -> bool {
+        matches!(self.state, GameState::Waiting)
+    }
+
+    /// How the room is shown in the lobby
+    pub fn lobby_room(&self) -> LobbyRoom {
+        LobbyRoom::Game(GameLobbyRoom {
+            room_id: self.room_id.clone(),
+            creator: self.creator.public(&self.room_id),
+            creator_rating: self.creator_rating,
+            time_control: self.time_control,
+            variant: self.variant,
+            rated: self.rated,
+            color: self.color,
+            created_at: self.created_at,
+        })
+    }
+
+    /// Game to store in the database once it is over
+    fn new_game(&self, termination: GameEndResult) -> Option<NewGame> {
+        match &self.state {
+            GameState::Waiting => None,
+            GameState::Started {
+                players,
+                game,
+                clock,
+                started_at,
+                ..
+            } => {
+                let initial = game.initial_position();
+
+                Some(NewGame {
+                    white_id: players.w.id,
+                    black_id: players.b.id,
+                    white_name: players.w.username.clone(),
+                    black_name: players.b.username.clone(),
+                    variant: self.variant,
+                    initial_fen: initial.fen(),
+                    moves_uci: game.uci_moves(),
+                    moves_san: get_san_moves(initial, game.moves()),
+                    pockets: game
+                        .current_position()
+                        .pockets()
+                        .map(|pockets| pockets.to_string()),
+                    termination,
+                    time_control: self.time_control,
+                    clocks: clock
+                        .as_ref()
+                        .map(|clock| {
+                            clock
+                                .history()
+                                .iter()
+                                .map(|left| left.as_millis() as i64)
+                                .collect()
+                        })
+                        .unwrap_or_default(),
+                    started_at: *started_at,
+                    rated: false,
+                    white_rating: None,
+                    black_rating: None,
+                })
+            }
+        }
+    }
+
+    /// Stores the game in the database, updating the ratings of the players
+    /// of rated games
+    async fn archive(
+        db_pool: PgPool,
+        room_id: String,
+        new_game: NewGame,
+        speed: Option<Speed>,
+    ) -> Option<GameRatings> {
+        let white_score = new_game.termination.white_score();
+
+        let archived = match (speed, white_score) {
+            (Some(speed), Some(white_score)) => {
+                Rating::record_game(&db_pool, new_game, speed, white_score)
+                    .await
+                    .map(Some)
+            }
+            _ => games::Game::create(&db_pool, new_game).await.map(|_| None),
+        };
+
+        archived.unwrap_or_else(|e| {
+            error!("Could not archive game of room {}: {:?}", room_id, e);
+            None
+        })
+    }
+
+    fn end_game(&mut self, result: GameEndResult, ctx: &mut Context<Self>) {
+        if let GameState::Started { game, .. } = &mut self.state {
+            game.end(result.clone());
+        }
+        self.save_closed(result.score());
+
+        let new_game = self.new_game(result.clone());
+        let db_pool = self.db_pool.clone();
+        let room_id = self.room_id.clone();
+        // Ratings follow standard games only
+        let speed = Some(Speed::of(self.time_control))
+            .filter(|_| self.rated && self.variant == Variant::Standard);
+
+        // The room stops handling messages until the ratings are known
+        async move {
+            match new_game {
+                Some(new_game) => Room::archive(db_pool, room_id, new_game, speed).await,
+                None => None,
+            }
+        }
+        .into_actor(self)
+        .map(move |ratings, act, ctx| {
+            act.send_message(
+                ServerMessage::GameEnd {
+                    result: result.clone(),
+                    ratings: ratings.clone(),
+                },
+                UserType::Spectator,
+            );
+
+            act.send_message(
+                ServerMessage::GameEnd { result, ratings },
+                UserType::Player(PlayerColor::All),
+            );
+
+            act.room_manager.do_send(RemoveRoom {
+                room_id: act.room_id.clone(),
+            });
+
+            ctx.stop();
+        })
+        .wait(ctx);
+    }
 }
 
 impl Actor for Room {
     type Context = Context<Self>;
 
-    fn started(&mut self, _ctx: &mut Self::Context) {
+    fn started(&mut self, ctx: &mut Self::Context) {
         info!("Started room !");
+
+        self.save();
+        // Restored rooms may already have a running clock
+        self.schedule_flag(ctx);
+        self.watch_absent(ctx);
     }
 }
 
@@ -119,102 +493,96 @@ impl Handler<Join> for Room {
         match &mut self.state {
             GameState::Waiting => {
                 if msg.id != self.creator.id {
-                    let mut players;
-                    // Decide who gets to play which color
-                    if rand::thread_rng().gen_bool(0.5) {
-                        players = Players {
-                            w: Player {
-                                id: msg.id,
-                                session: Some(msg.session),
-                            },
-                            b: self.creator.clone(),
-                        };
-                    } else {
-                        players = Players {
-                            w: self.creator.clone(),
-                            b: Player {
-                                id: msg.id,
-                                session: Some(msg.session),
-                            },
-                        };
+                    if self.rated && msg.username.is_none() {
+                        msg.session
+                            .do_send(Send(ServerMessage::Err {
+                                what: ServerError::LoginRequired,
+                            }))
+                            .ok();
+                        return;
                     }
 
-                    let game = Game::new();
-
-                    self.state = GameState::Started {
-                        spectators: HashSet::new(),
-                        players,
-                        game: Game::new(),
-                    };
-
-                    let board = game.current_position();
+                    let mut player = Player::new(PlayerInfo {
+                        id: msg.id,
+                        username: msg.username,
+                    });
+                    player.sessions.insert(msg.session);
 
-                    // TODO: make shorter
-                    self.send_message(
-                        ServerMessage::Start {
-                            color: PlayerColor::Black,
-                            dests: None,
+                    let players = match self.color.resolve(&mut self.rng) {
+                        Color::White => Players {
+                            w: self.creator.clone(),
+                            b: player,
                         },
-                        UserType::Player(PlayerColor::Black),
-                    );
-
-                    self.send_message(
-                        ServerMessage::Start {
-                            color: PlayerColor::White,
-                            dests: Some(get_dests(&board)),
+                        Color::Black => Players {
+                            w: player,
+                            b: self.creator.clone(),
                         },
-                        UserType::Player(PlayerColor::White),
-                    );
+                    };
 
-                    // TODO: turn into a function
-                    self.redis
-                        .do_send(Command(resp_array![
-                            "HSET",
-                            format!("rc:room:{}", &self.room_id),
-                            "fen",
-                            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1"
-                        ]))
-                        .ok();
+                    self.start(players, ctx);
                 } else {
-                    self.creator.session = Some(msg.session);
+                    self.creator.sessions.insert(msg.session);
                 }
             }
             GameState::Started {
                 players,
                 game,
                 spectators,
+                clock,
+                ..
             } => {
-                let board = game.current_position();
-                let fen = board.to_string();
-                let turn = match game.side_to_move() {
-                    Color::White => PlayerColor::White,
-                    Color::Black => PlayerColor::Black,
-                };
-                if msg.id == players.w.id {
-                    players.w.session = Some(msg.session);
-                    self.send_message(
-                        ServerMessage::Reconnect {
-                            color: PlayerColor::White,
-                            dests: Some(get_dests(&board)),
-                            check: board.checkers().popcnt() != 0,
-                            fen,
-                            turn,
-                        },
-                        UserType::Player(PlayerColor::White),
-                    );
-                } else if msg.id == players.b.id {
-                    players.b.session = Some(msg.session);
-                    self.send_message(
-                        ServerMessage::Reconnect {
-                            color: PlayerColor::Black,
-                            dests: Some(get_dests(&board)),
-                            check: board.checkers().popcnt() != 0,
+                let position = game.current_position();
+                let fen = position.fen();
+                let check = position.in_check();
+                let dests = get_dests(position);
+                let moves = game.uci_moves();
+                let turn = PlayerColor::from(game.side_to_move());
+                let clock = clock.as_ref().map(|clock| clock.state(Instant::now()));
+                let draw_offer = game.draw_offer().map(PlayerColor::from);
+                let pockets = position.pockets();
+                let (white, black) = (
+                    players.w.public(&self.room_id),
+                    players.b.public(&self.room_id),
+                );
+
+                if let Some(color) = players.color_of(msg.id) {
+                    let player = match color {
+                        Color::White => &mut players.w,
+                        Color::Black => &mut players.b,
+                    };
+                    player.sessions.insert(msg.session.clone());
+
+                    // Only the new session needs the state, the other ones
+                    // of the player are up to date
+                    msg.session
+                        .do_send(Send(ServerMessage::Reconnect {
+                            color: color.into(),
+                            white,
+                            black,
+                            dests: Some(dests),
+                            check,
                             fen,
                             turn,
-                        },
-                        UserType::Player(PlayerColor::Black),
-                    );
+                            clock,
+                            draw_offer,
+                            pockets,
+                        }))
+                        .ok();
+                    self.player_joined(color, &msg.session, ctx);
                 } else {
+                    msg.session.do_send(Send(ServerMessage::Spectate {
+                        white,
+                        black,
+                        turn,
+                        check,
+                        fen,
+                        moves,
+                        clock,
+                        draw_offer,
+                        pockets,
+                    }))
+                    .ok();
+
                     spectators.insert(Spectator {
                         id: msg.id,
                         session: msg.session,
@@ -225,29 +593,59 @@ impl Handler<Join> for Room {
     }
 }
 
+impl Handler<Start> for Room {
+    type Result = ();
+
+    fn handle(&mut self, msg: Start, ctx: &mut Self::Context) -> Self::Result {
+        if let GameState::Waiting = self.state {
+            let players = Players {
+                w: Player::new(msg.white),
+                b: Player::new(msg.black),
+            };
+
+            self.start(players, ctx);
+        }
+    }
+}
+
 impl Handler<Leave> for Room {
     type Result = ();
 
-    fn handle(&mut self, msg: Leave, _ctx: &mut Self::Context) -> Self::Result {
-        match &mut self.state {
+    fn handle(&mut self, msg: Leave, ctx: &mut Self::Context) -> Self::Result {
+        // Players are gone once their last session left
+        let leave = |player: &mut Player| {
+            player.id == msg.id && player.sessions.remove(&msg.session) && !player.is_present()
+        };
+
+        let left = match &mut self.state {
             GameState::Waiting => {
-                if msg.id == self.creator.id {
-                    self.creator.session = None;
-                }
+                leave(&mut self.creator);
+                None
             }
             GameState::Started {
                 players,
                 spectators,
                 ..
             } => {
-                if players.w.id == msg.id {
-                    players.w.session = None;
-                } else if players.b.id == msg.id {
-                    players.w.session = None;
+                if leave(&mut players.w) {
+                    Some(Color::White)
+                } else if leave(&mut players.b) {
+                    Some(Color::Black)
                 } else {
-                    spectators.remove(&msg.id);
+                    spectators.remove(&msg.session);
+                    None
                 }
             }
+        };
+
+        if let Some(color) = left {
+            let message = ServerMessage::PlayerDisconnected {
+                color: color.into(),
+            };
+            self.send_message(message.clone(), UserType::Spectator);
+            self.send_message(message, UserType::Player((!color).into()));
+
+            self.player_left(color, ctx);
         }
     }
 }
@@ -256,22 +654,67 @@ impl Handler<Move> for Room {
     type Result = ();
 
     fn handle(&mut self, msg: Move, ctx: &mut Self::Context) -> Self::Result {
+        let now = Instant::now();
+
         if let GameState::Started {
             players,
-            spectators,
             game,
+            clock,
+            ..
         } = &mut self.state
         {
-            let player_color = match game.side_to_move() {
-                chess::Color::Black => PlayerColor::White,
-                chess::Color::White => PlayerColor::Black,
+            let color = match players.color_of(msg.id) {
+                Some(color) => color,
+                None => return,
             };
 
-            let opp_color = match game.side_to_move() {
-                chess::Color::Black => PlayerColor::Black,
-                chess::Color::White => PlayerColor::White,
+            if color != game.side_to_move() {
+                self.send_message(
+                    ServerMessage::Err {
+                        what: ServerError::NotYourTurn,
+                    },
+                    UserType::Player(color.into()),
+                );
+                return;
+            }
+
+            if let Some(flagged) = clock.as_ref().and_then(|clock| clock.flagged(now)) {
+                self.end_game(GameEndResult::out_of_time(flagged), ctx);
+                return;
+            }
+
+            let chess_move = match parse_move(game.current_position(), &msg.uci) {
+                Ok(chess_move) => chess_move,
+                Err(e) => {
+                    self.send_message(
+                        ServerMessage::Err { what: e.into() },
+                        UserType::Player(color.into()),
+                    );
+                    return;
+                }
             };
 
+            // Castling is sent to everyone the same way, however it was played
+            let uci = game.current_position().uci(chess_move);
+            // Making a move cancels any pending draw offer
+            if !game.make_move(chess_move) {
+                self.send_message(
+                    ServerMessage::Err {
+                        what: ServerError::GameOver,
+                    },
+                    UserType::Player(color.into()),
+                );
+                return;
+            }
+
+            if let Some(clock) = clock {
+                clock.press(now);
+            }
+
+            // The side that has to play next
+            let player_color = PlayerColor::from(!color);
+            let opp_color = PlayerColor::from(color);
+
             let side = match player_color {
                 PlayerColor::White => "white",
                 PlayerColor::Black => "black",
@@ -279,83 +722,335 @@ impl Handler<Move> for Room {
             }
             .to_string();
 
-            match ChessMove::from_str(&msg.uci) {
-                Ok(chess_move) => {
-                    if game.make_move(chess_move) {
-                        let result = game.result();
-                        let board = game.current_position();
-                        let fen = board.to_string();
-                        let check = board.checkers().popcnt() != 0;
+            let position = game.current_position();
+            let counters = game.draw_counters();
+            let fen = position.fen();
+            let check = position.in_check();
+            let dests = get_dests(position);
+            let pockets = position.pockets();
+            let result = position
+                .result()
+                .or_else(|| GameEndResult::automatic_draw(position, &counters));
+            let clock = clock.as_ref().map(|clock| clock.state(now));
 
-                        self.send_message(
-                            ServerMessage::Move {
-                                uci: msg.uci.clone(),
-                                side: side.clone(),
-                                fen: fen.clone(),
-                                dests: Some(get_dests(&board)),
-                                check,
-                            },
-                            UserType::Player(player_color),
-                        );
+            self.send_message(
+                ServerMessage::Move {
+                    uci: uci.clone(),
+                    side: side.clone(),
+                    fen: fen.clone(),
+                    dests: Some(dests),
+                    check,
+                    clock,
+                    pockets,
+                },
+                UserType::Player(player_color),
+            );
 
-                        self.send_message(
-                            ServerMessage::Move {
-                                uci: msg.uci,
-                                side,
-                                fen: fen.clone(),
-                                dests: None,
-                                check,
-                            },
-                            UserType::Player(opp_color),
-                        );
+            self.send_message(
+                ServerMessage::Move {
+                    uci: uci.clone(),
+                    side: side.clone(),
+                    fen: fen.clone(),
+                    dests: None,
+                    check,
+                    clock,
+                    pockets,
+                },
+                UserType::Player(opp_color),
+            );
 
-                        self.redis
-                            .do_send(Command(resp_array![
-                                "HSET",
-                                format!("rc:room:{}", &self.room_id),
-                                "fen",
-                                fen
-                            ]))
-                            .ok();
+            self.send_message(
+                ServerMessage::Move {
+                    uci,
+                    side,
+                    fen: fen.clone(),
+                    dests: None,
+                    check,
+                    clock,
+                    pockets,
+                },
+                UserType::Spectator,
+            );
 
-                        if let Some(result) = result.clone() {
-                            let result = match result {
-                                GameResult::WhiteCheckmates => GameEndResult::WhiteCheckmates,
-                                GameResult::WhiteResigns => GameEndResult::WhiteResigns,
-                                GameResult::BlackCheckmates => GameEndResult::BlackCheckmates,
-                                GameResult::BlackResigns => GameEndResult::BlackResigns,
-                                GameResult::Stalemate => GameEndResult::Stalemate,
-                                GameResult::DrawAccepted => GameEndResult::DrawAccepted,
-                                GameResult::DrawDeclared => GameEndResult::Stalemate,
-                            };
-
-                            self.send_message(
-                                ServerMessage::GameEnd { result },
-                                UserType::Player(PlayerColor::All),
-                            );
-
-                            self.room_manager.do_send(RemoveRoom {
-                                room_id: self.room_id.clone(),
-                            });
-
-                            ctx.stop();
-                        }
-                    } else {
+            self.save();
+
+            match result {
+                Some(result) => self.end_game(result, ctx),
+                None => {
+                    if GameEndResult::claimable_draw(&counters).is_some() {
                         self.send_message(
-                            ServerMessage::Err {
-                                what: ServerError::IllegalMove,
-                            },
-                            UserType::Player(player_color),
-                        )
+                            ServerMessage::DrawClaimable,
+                            UserType::Player(PlayerColor::All),
+                        );
                     }
+
+                    self.schedule_flag(ctx);
                 }
-                Err(_e) => self.send_message(
-                    ServerMessage::Err {
-                        what: ServerError::IllegalMove,
-                    },
-                    UserType::Player(player_color),
+            }
+        }
+    }
+}
+
+impl Handler<Resign> for Room {
+    type Result = ();
+
+    fn handle(&mut self, msg: Resign, ctx: &mut Self::Context) -> Self::Result {
+        if let GameState::Started { players, game, .. } = &self.state {
+            let color = match players.color_of(msg.id) {
+                Some(color) => color,
+                None => return,
+            };
+
+            match game.resign(color) {
+                Ok(result) => self.end_game(result, ctx),
+                Err(e) => self.send_message(
+                    ServerMessage::Err { what: e.into() },
+                    UserType::Player(color.into()),
+                ),
+            }
+        }
+    }
+}
+
+impl Handler<OfferDraw> for Room {
+    type Result = ();
+
+    fn handle(&mut self, msg: OfferDraw, ctx: &mut Self::Context) -> Self::Result {
+        if let GameState::Started { players, game, .. } = &mut self.state {
+            let color = match players.color_of(msg.id) {
+                Some(color) => color,
+                None => return,
+            };
+
+            match game.offer_draw(color) {
+                Ok(DrawOffer::Made) => {
+                    self.save();
+                    self.send_message(
+                        ServerMessage::DrawOffer {
+                            color: color.into(),
+                        },
+                        UserType::Player(PlayerColor::All),
+                    );
+                }
+                Ok(DrawOffer::Pending) => (),
+                Ok(DrawOffer::Accepted) => self.end_game(GameEndResult::DrawAccepted, ctx),
+                Err(e) => self.send_message(
+                    ServerMessage::Err { what: e.into() },
+                    UserType::Player(color.into()),
+                ),
+            }
+        }
+    }
+}
+
+impl Handler<AcceptDraw> for Room {
+    type Result = ();
+
+    fn handle(&mut self, msg: AcceptDraw, ctx: &mut Self::Context) -> Self::Result {
+        if let GameState::Started { players, game, .. } = &self.state {
+            let color = match players.color_of(msg.id) {
+                Some(color) => color,
+                None => return,
+            };
+
+            match game.accept_draw(color) {
+                Ok(result) => self.end_game(result, ctx),
+                Err(e) => self.send_message(
+                    ServerMessage::Err { what: e.into() },
+                    UserType::Player(color.into()),
+                ),
+            }
+        }
+    }
+}
+
+impl Handler<DeclineDraw> for Room {
+    type Result = ();
+
+    fn handle(&mut self, msg: DeclineDraw, _ctx: &mut Self::Context) -> Self::Result {
+        if let GameState::Started { players, game, .. } = &mut self.state {
+            let color = match players.color_of(msg.id) {
+                Some(color) => color,
+                None => return,
+            };
+
+            match game.decline_draw(color) {
+                Ok(()) => {
+                    self.save();
+                    self.send_message(
+                        ServerMessage::DrawDeclined {
+                            color: color.into(),
+                        },
+                        UserType::Player(PlayerColor::All),
+                    );
+                }
+                Err(e) => self.send_message(
+                    ServerMessage::Err { what: e.into() },
+                    UserType::Player(color.into()),
                 ),
             }
         }
     }
 }
+
+impl Handler<Abort> for Room {
+    type Result = ();
+
+    fn handle(&mut self, msg: Abort, ctx: &mut Self::Context) -> Self::Result {
+        if let GameState::Started { players, game, .. } = &self.state {
+            let color = match players.color_of(msg.id) {
+                Some(color) => color,
+                None => return,
+            };
+
+            match game.abort() {
+                Ok(result) => self.end_game(result, ctx),
+                Err(e) => self.send_message(
+                    ServerMessage::Err { what: e.into() },
+                    UserType::Player(color.into()),
+                ),
+            }
+        }
+    }
+}
+
+impl Room {
+    /// Side of the sender of a claim, if the claim can be made
+    fn claimant(&self, id: Uuid) -> Option<Color> {
+        if let GameState::Started { players, claim, .. } = &self.state {
+            let color = players.color_of(id)?;
+
+            if *claim == Some(color) {
+                return Some(color);
+            }
+
+            self.send_message(
+                ServerMessage::Err {
+                    what: ServerError::NoClaim,
+                },
+                UserType::Player(color.into()),
+            );
+        }
+
+        None
+    }
+}
+
+impl Handler<ClaimVictory> for Room {
+    type Result = ();
+
+    fn handle(&mut self, msg: ClaimVictory, ctx: &mut Self::Context) -> Self::Result {
+        if let Some(color) = self.claimant(msg.id) {
+            self.end_game(GameEndResult::abandons(!color), ctx);
+        }
+    }
+}
+
+impl Handler<ClaimDraw> for Room {
+    type Result = ();
+
+    fn handle(&mut self, msg: ClaimDraw, ctx: &mut Self::Context) -> Self::Result {
+        if let GameState::Started { players, game, .. } = &self.state {
+            let counters = game.draw_counters();
+
+            if let (Some(_), Some(result)) = (
+                players.color_of(msg.id),
+                GameEndResult::claimable_draw(&counters),
+            ) {
+                self.end_game(result, ctx);
+                return;
+            }
+        }
+
+        if self.claimant(msg.id).is_some() {
+            self.end_game(GameEndResult::AbandonedDraw, ctx);
+        }
+    }
+}
+
+impl Handler<Sweep> for Room {
+    type Result = ();
+
+    fn handle(&mut self, msg: Sweep, ctx: &mut Self::Context) -> Self::Result {
+        // Correspondence games may be left for days between moves
+        if self.time_control.is_none() && !self.is_open() {
+            return;
+        }
+
+        let connected = |player: &Player| player.sessions.iter().any(|session| session.connected());
+
+        let (present, grace) = match &self.state {
+            GameState::Waiting => (connected(&self.creator), msg.waiting_grace),
+            GameState::Started { players, .. } => (
+                connected(&players.w) || connected(&players.b),
+                msg.abandoned_grace,
+            ),
+        };
+
+        if present {
+            self.absent_since = None;
+            return;
+        }
+
+        let now = Instant::now();
+        let absent_since = *self.absent_since.get_or_insert(now);
+
+        if now.duration_since(absent_since) < grace {
+            return;
+        }
+
+        info!("Closing abandoned room {}", self.room_id);
+
+        match &self.state {
+            GameState::Waiting => {
+                self.save_closed("*");
+                self.room_manager.do_send(RemoveRoom {
+                    room_id: self.room_id.clone(),
+                });
+                ctx.stop();
+            }
+            GameState::Started { game, .. } => {
+                // Both players left, neither of them wins
+                let result = game.abort().unwrap_or(GameEndResult::AbandonedDraw);
+                self.end_game(result, ctx);
+            }
+        }
+    }
+}
+
+impl Handler<MergePlayer> for Room {
+    type Result = ();
+
+    fn handle(&mut self, msg: MergePlayer, _ctx: &mut Self::Context) -> Self::Result {
+        let merge = |player: &mut Player| {
+            if player.id == msg.from {
+                player.id = msg.to.id;
+                player.username = msg.to.username.clone();
+            }
+        };
+
+        match &mut self.state {
+            GameState::Waiting => merge(&mut self.creator),
+            GameState::Started {
+                players,
+                spectators,
+                ..
+            } => {
+                merge(&mut players.w);
+                merge(&mut players.b);
+
+                *spectators = spectators
+                    .drain()
+                    .map(|mut spectator| {
+                        if spectator.id == msg.from {
+                            spectator.id = msg.to.id;
+                        }
+                        spectator
+                    })
+                    .collect();
+            }
+        }
+
+        self.save();
+    }
+}
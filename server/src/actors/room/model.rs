@@ -1,12 +1,17 @@
-use super::websocket::WebsocketSession;
+use crate::actors::room_manager::RoomAddr;
+use crate::actors::websocket::{self, ServerMessage};
+use crate::util::chess::DrawCounters;
+use crate::util::variant;
 
 use actix::prelude::*;
-use serde::Serialize;
+use chess::Color;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 use std::hash::{Hash, Hasher};
 use std::borrow::Borrow;
-use std::ops::{Index, IndexMut};
-use chess::{Game, Color};
+use std::collections::HashSet;
+use std::time::Duration;
 
 // Types
 
@@ -15,7 +20,7 @@ pub enum UserType {
     Player(PlayerColor),
 }
 
-#[derive(Debug, Serialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(rename_all = "snake_case")]
 pub enum PlayerColor {
     White,
@@ -23,8 +28,51 @@ pub enum PlayerColor {
     All,
 }
 
-#[derive(Debug, Serialize, Clone)]
+/// Side a player asks to play
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
 #[serde(rename_all = "snake_case")]
+pub enum ColorChoice {
+    White,
+    Black,
+    Random,
+}
+
+impl Default for ColorChoice {
+    fn default() -> Self {
+        ColorChoice::Random
+    }
+}
+
+impl ColorChoice {
+    /// Side of the player who made the choice, random choices are drawn
+    /// from `rng`
+    pub fn resolve<R: Rng + ?Sized>(self, rng: &mut R) -> Color {
+        match self {
+            ColorChoice::White => Color::White,
+            ColorChoice::Black => Color::Black,
+            ColorChoice::Random => {
+                if rng.gen_bool(0.5) {
+                    Color::White
+                } else {
+                    Color::Black
+                }
+            }
+        }
+    }
+}
+
+impl From<Color> for PlayerColor {
+    fn from(color: Color) -> Self {
+        match color {
+            Color::White => PlayerColor::White,
+            Color::Black => PlayerColor::Black,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "varchar", rename_all = "snake_case")]
 pub enum GameEndResult {
     WhiteCheckmates,
     WhiteResigns,
@@ -32,6 +80,297 @@ pub enum GameEndResult {
     BlackResigns,
     Stalemate,
     DrawAccepted,
+    /// Draw claimed by a player, the position occurred three times
+    ThreefoldRepetition,
+    /// Draw claimed by a player, no capture or pawn move for fifty moves
+    FiftyMoves,
+    FivefoldRepetition,
+    SeventyFiveMoves,
+    /// Neither side can checkmate anymore
+    InsufficientMaterial,
+    WhiteOutOfTime,
+    BlackOutOfTime,
+    /// The opponent of white claimed the win after white left the game
+    WhiteAbandons,
+    BlackAbandons,
+    /// A player claimed a draw after their opponent left the game, or both
+    /// players left
+    AbandonedDraw,
+    /// The white king reached the center in King of the Hill
+    WhiteKingInCenter,
+    BlackKingInCenter,
+    /// White gave a third check in Three-check
+    WhiteThreeChecks,
+    BlackThreeChecks,
+    /// The partner of white won on the other board of bughouse
+    WhitePartnerWins,
+    BlackPartnerWins,
+    Aborted,
+}
+
+impl GameEndResult {
+    /// Score of the game as written in PGN
+    pub fn score(&self) -> &'static str {
+        match self {
+            GameEndResult::WhiteCheckmates
+            | GameEndResult::BlackResigns
+            | GameEndResult::BlackOutOfTime
+            | GameEndResult::BlackAbandons
+            | GameEndResult::WhiteKingInCenter
+            | GameEndResult::WhiteThreeChecks
+            | GameEndResult::WhitePartnerWins => "1-0",
+            GameEndResult::BlackCheckmates
+            | GameEndResult::WhiteResigns
+            | GameEndResult::WhiteOutOfTime
+            | GameEndResult::WhiteAbandons
+            | GameEndResult::BlackKingInCenter
+            | GameEndResult::BlackThreeChecks
+            | GameEndResult::BlackPartnerWins => "0-1",
+            GameEndResult::Stalemate
+            | GameEndResult::DrawAccepted
+            | GameEndResult::ThreefoldRepetition
+            | GameEndResult::FiftyMoves
+            | GameEndResult::FivefoldRepetition
+            | GameEndResult::SeventyFiveMoves
+            | GameEndResult::InsufficientMaterial
+            | GameEndResult::AbandonedDraw => "1/2-1/2",
+            GameEndResult::Aborted => "*",
+        }
+    }
+
+    /// Points scored by white, aborted games have no winner
+    pub fn white_score(&self) -> Option<f64> {
+        match self.score() {
+            "1-0" => Some(1.0),
+            "0-1" => Some(0.0),
+            "1/2-1/2" => Some(0.5),
+            _ => None,
+        }
+    }
+
+    pub fn checkmates(color: Color) -> Self {
+        match color {
+            Color::White => GameEndResult::WhiteCheckmates,
+            Color::Black => GameEndResult::BlackCheckmates,
+        }
+    }
+
+    pub fn king_in_center(color: Color) -> Self {
+        match color {
+            Color::White => GameEndResult::WhiteKingInCenter,
+            Color::Black => GameEndResult::BlackKingInCenter,
+        }
+    }
+
+    pub fn three_checks(color: Color) -> Self {
+        match color {
+            Color::White => GameEndResult::WhiteThreeChecks,
+            Color::Black => GameEndResult::BlackThreeChecks,
+        }
+    }
+
+    pub fn partner_wins(color: Color) -> Self {
+        match color {
+            Color::White => GameEndResult::WhitePartnerWins,
+            Color::Black => GameEndResult::BlackPartnerWins,
+        }
+    }
+
+    pub fn resigns(color: Color) -> Self {
+        match color {
+            Color::White => GameEndResult::WhiteResigns,
+            Color::Black => GameEndResult::BlackResigns,
+        }
+    }
+
+    pub fn out_of_time(color: Color) -> Self {
+        match color {
+            Color::White => GameEndResult::WhiteOutOfTime,
+            Color::Black => GameEndResult::BlackOutOfTime,
+        }
+    }
+
+    pub fn abandons(color: Color) -> Self {
+        match color {
+            Color::White => GameEndResult::WhiteAbandons,
+            Color::Black => GameEndResult::BlackAbandons,
+        }
+    }
+
+    /// Draw a player may claim in a position
+    pub fn claimable_draw(counters: &DrawCounters) -> Option<Self> {
+        if counters.repetitions >= 3 {
+            Some(GameEndResult::ThreefoldRepetition)
+        } else if counters.halfmoves >= 100 {
+            Some(GameEndResult::FiftyMoves)
+        } else {
+            None
+        }
+    }
+
+    /// Draw ending the game as soon as `position` is reached
+    pub fn automatic_draw(
+        position: &dyn variant::Variant,
+        counters: &DrawCounters,
+    ) -> Option<Self> {
+        if position.is_insufficient_material() {
+            Some(GameEndResult::InsufficientMaterial)
+        } else if counters.repetitions >= 5 {
+            Some(GameEndResult::FivefoldRepetition)
+        } else if counters.halfmoves >= 150 {
+            Some(GameEndResult::SeventyFiveMoves)
+        } else {
+            None
+        }
+    }
+}
+
+/// Rules a game is played with
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "varchar", rename_all = "snake_case")]
+pub enum Variant {
+    Standard,
+    /// Back rank pieces shuffled into one of 960 start positions
+    Chess960,
+    /// Bringing the king to one of the four center squares wins
+    KingOfTheHill,
+    /// Checking the opponent three times wins
+    ThreeCheck,
+    /// Captured pieces can be dropped back on the board
+    Crazyhouse,
+    /// Crazyhouse by teams of two on two boards, captured pieces go to the
+    /// partner
+    Bughouse,
+}
+
+impl Default for Variant {
+    fn default() -> Self {
+        Variant::Standard
+    }
+}
+
+/// What the creator of a room chose for its game
+#[derive(Debug, Clone, Copy)]
+pub struct RoomSettings {
+    pub time_control: Option<TimeControl>,
+    /// Whether the game changes the ratings of the players
+    pub rated: bool,
+    /// Side played by the creator
+    pub color: ColorChoice,
+    pub variant: Variant,
+}
+
+/// Time control of a game, all durations are in seconds
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub struct TimeControl {
+    pub base: u64,
+    #[serde(default)]
+    pub increment: u64,
+    #[serde(default)]
+    pub delay: Option<Delay>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum Delay {
+    /// Time spent on a move is given back, up to `seconds`
+    Bronstein { seconds: u64 },
+    /// The clock only starts running after `seconds`
+    Simple { seconds: u64 },
+}
+
+/// Longest base time a game may start with
+const MAX_BASE: u64 = 3 * 60 * 60;
+/// Longest increment or delay added to each move
+const MAX_PER_MOVE: u64 = 3 * 60;
+
+impl TimeControl {
+    /// Whether the durations are within the bounds accepted from clients.
+    /// Without a base time or an increment, White would flag on the first
+    /// move.
+    pub fn is_valid(&self) -> bool {
+        (self.base > 0 || self.increment > 0)
+            && self.base <= MAX_BASE
+            && self.increment <= MAX_PER_MOVE
+            && !matches!(self.delay, Some(delay) if delay.seconds() > MAX_PER_MOVE)
+    }
+}
+
+impl Delay {
+    pub fn seconds(&self) -> u64 {
+        match self {
+            Delay::Bronstein { seconds } | Delay::Simple { seconds } => *seconds,
+        }
+    }
+}
+
+/// Speed category of a game, players have a separate rating for each of them
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "varchar", rename_all = "snake_case")]
+pub enum Speed {
+    Bullet,
+    Blitz,
+    Rapid,
+    Classical,
+    Correspondence,
+}
+
+impl Speed {
+    /// Categorizes timed games by their expected duration for 40 moves,
+    /// untimed games are played by correspondence
+    pub fn of(time_control: Option<TimeControl>) -> Self {
+        let time_control = match time_control {
+            Some(time_control) => time_control,
+            None => return Speed::Correspondence,
+        };
+
+        let per_move = time_control
+            .increment
+            .saturating_add(time_control.delay.map_or(0, |delay| delay.seconds()));
+
+        match time_control.base.saturating_add(per_move.saturating_mul(40)) {
+            0..=179 => Speed::Bullet,
+            180..=479 => Speed::Blitz,
+            480..=1499 => Speed::Rapid,
+            _ => Speed::Classical,
+        }
+    }
+}
+
+/// Remaining time of both sides, in milliseconds
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+pub struct ClockState {
+    pub white: u64,
+    pub black: u64,
+}
+
+/// Public information about a player, anonymous players have no username
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PlayerInfo {
+    pub id: Uuid,
+    pub username: Option<String>,
+}
+
+impl PlayerInfo {
+    /// The player as shown to other clients in `room_id`
+    pub fn public(&self, room_id: &str) -> PublicPlayer {
+        PublicPlayer {
+            handle: Uuid::new_v5(&self.id, room_id.as_bytes()),
+            username: self.username.clone(),
+        }
+    }
+}
+
+/// A player as shown to other clients. The id of an anonymous player is all
+/// the server knows them by, so clients get a handle derived from it that is
+/// different in every room instead.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PublicPlayer {
+    pub handle: Uuid,
+    pub username: Option<String>,
 }
 
 // User storage data structures
@@ -41,34 +380,82 @@ pub struct Players {
     pub b: Player,
 }
 
+impl Players {
+    pub fn color_of(&self, id: Uuid) -> Option<Color> {
+        if self.w.id == id {
+            Some(Color::White)
+        } else if self.b.id == id {
+            Some(Color::Black)
+        } else {
+            None
+        }
+    }
+}
+
+/// A session watching the game, spectators are told apart by their session
+/// as they can watch from several tabs
 #[derive(Clone, Eq)]
 pub struct Spectator {
     pub id: Uuid,
-    pub session: Addr<WebsocketSession>,
+    pub session: Recipient<websocket::Send>,
 }
 
 impl PartialEq for Spectator {
     fn eq(&self, other: &Spectator) -> bool {
-        self.id == other.id
+        self.session == other.session
     }
 }
 
 impl Hash for Spectator {
     fn hash<H: Hasher>(&self, state: &mut H) {
-        self.id.hash(state);
+        self.session.hash(state);
     }
 }
 
-impl Borrow<Uuid> for Spectator {
-    fn borrow(&self) -> &Uuid {
-        &self.id
+impl Borrow<Recipient<websocket::Send>> for Spectator {
+    fn borrow(&self) -> &Recipient<websocket::Send> {
+        &self.session
     }
 }
 
 #[derive(Clone)]
 pub struct Player {
     pub id: Uuid,
-    pub session: Option<Addr<WebsocketSession>>,
+    pub username: Option<String>,
+    /// Open sessions of the player, one for each tab or device
+    pub sessions: HashSet<Recipient<websocket::Send>>,
+}
+
+impl Player {
+    pub fn new(info: PlayerInfo) -> Self {
+        Self {
+            id: info.id,
+            username: info.username,
+            sessions: HashSet::new(),
+        }
+    }
+
+    /// Whether the player has a session in the room
+    pub fn is_present(&self) -> bool {
+        !self.sessions.is_empty()
+    }
+
+    pub fn send(&self, message: &ServerMessage) {
+        for session in self.sessions.iter() {
+            session.do_send(websocket::Send(message.clone())).ok();
+        }
+    }
+
+    pub fn info(&self) -> PlayerInfo {
+        PlayerInfo {
+            id: self.id,
+            username: self.username.clone(),
+        }
+    }
+
+    pub fn public(&self, room_id: &str) -> PublicPlayer {
+        self.info().public(room_id)
+    }
 }
 
 // Actor messages
@@ -77,13 +464,26 @@ pub struct Player {
 #[rtype(result = "()")]
 pub struct Join {
     pub id: Uuid,
-    pub session: Addr<WebsocketSession>,
+    pub username: Option<String>,
+    pub session: Recipient<websocket::Send>,
+}
+
+/// Starts the game of a room with players chosen beforehand, they get the
+/// game state once they join
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Start {
+    pub white: PlayerInfo,
+    pub black: PlayerInfo,
 }
 
+/// A session of a player or spectator closed, the player stays in the game
+/// while another of their sessions is in the room
 #[derive(Message)]
 #[rtype(result = "()")]
 pub struct Leave {
     pub id: Uuid,
+    pub session: Recipient<websocket::Send>,
 }
 
 #[derive(Message)]
@@ -95,4 +495,220 @@ pub struct Move {
 
 #[derive(Message)]
 #[rtype(result = "()")]
-pub struct Start;
+pub struct Resign {
+    pub id: Uuid,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct OfferDraw {
+    pub id: Uuid,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct AcceptDraw {
+    pub id: Uuid,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct DeclineDraw {
+    pub id: Uuid,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Abort {
+    pub id: Uuid,
+}
+
+/// Ends the game as won by the sender, whose opponent left it
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct ClaimVictory {
+    pub id: Uuid,
+}
+
+/// Ends the game as drawn, by repetition, by the fifty-move rule or because
+/// the opponent of the sender left it
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct ClaimDraw {
+    pub id: Uuid,
+}
+
+/// Closes the room if nobody has been playing in it for longer than the
+/// grace period of its state
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Sweep {
+    pub waiting_grace: Duration,
+    pub abandoned_grace: Duration,
+}
+
+/// An anonymous player logged in, `from` becomes their account
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct MergePlayer {
+    pub from: Uuid,
+    pub to: PlayerInfo,
+}
+
+/// A player action, in a form that can be forwarded to a room living on
+/// another node
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum RoomCommand {
+    Move { uci: String },
+    Resign,
+    OfferDraw,
+    AcceptDraw,
+    DeclineDraw,
+    Abort,
+    ClaimVictory,
+    ClaimDraw,
+}
+
+impl RoomCommand {
+    /// Sends the matching actor message to a local room
+    pub fn send_to(self, room: &RoomAddr, id: Uuid) {
+        match self {
+            RoomCommand::Move { uci } => room.do_send(Move { id, uci }),
+            RoomCommand::Resign => room.do_send(Resign { id }),
+            RoomCommand::OfferDraw => room.do_send(OfferDraw { id }),
+            RoomCommand::AcceptDraw => room.do_send(AcceptDraw { id }),
+            RoomCommand::DeclineDraw => room.do_send(DeclineDraw { id }),
+            RoomCommand::Abort => room.do_send(Abort { id }),
+            RoomCommand::ClaimVictory => room.do_send(ClaimVictory { id }),
+            RoomCommand::ClaimDraw => room.do_send(ClaimDraw { id }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::position::Position;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn time_controls_are_bounded() {
+        let control = |base, increment, delay| TimeControl {
+            base,
+            increment,
+            delay,
+        };
+
+        let simple = Some(Delay::Simple {
+            seconds: MAX_PER_MOVE,
+        });
+        let bronstein = Some(Delay::Bronstein { seconds: u64::MAX });
+
+        assert!(control(MAX_BASE, MAX_PER_MOVE, None).is_valid());
+        assert!(control(1, 0, simple).is_valid());
+        assert!(!control(MAX_BASE + 1, 0, None).is_valid());
+        assert!(!control(60, MAX_PER_MOVE + 1, None).is_valid());
+        assert!(!control(60, 0, bronstein).is_valid());
+    }
+
+    #[test]
+    fn time_controls_need_some_time() {
+        let control = |base, increment, delay| TimeControl {
+            base,
+            increment,
+            delay,
+        };
+
+        assert!(!control(0, 0, None).is_valid());
+        assert!(!control(0, 0, Some(Delay::Simple { seconds: 5 })).is_valid());
+        assert!(control(0, 1, None).is_valid());
+        assert!(control(1, 0, None).is_valid());
+    }
+
+    #[test]
+    fn speeds_follow_the_estimated_duration() {
+        let speed = |base, increment| {
+            Speed::of(Some(TimeControl {
+                base,
+                increment,
+                delay: None,
+            }))
+        };
+
+        assert_eq!(Speed::of(None), Speed::Correspondence);
+        assert_eq!(speed(60, 0), Speed::Bullet);
+        assert_eq!(speed(120, 2), Speed::Blitz);
+        assert_eq!(speed(600, 0), Speed::Rapid);
+        assert_eq!(speed(900, 15), Speed::Classical);
+        assert_eq!(speed(u64::MAX, u64::MAX), Speed::Classical);
+    }
+
+    #[test]
+    fn fixed_choices_ignore_the_rng() {
+        let mut rng = StdRng::seed_from_u64(0);
+
+        assert_eq!(ColorChoice::White.resolve(&mut rng), Color::White);
+        assert_eq!(ColorChoice::Black.resolve(&mut rng), Color::Black);
+    }
+
+    #[test]
+    fn random_choice_follows_the_seed() {
+        let draw = |seed| ColorChoice::Random.resolve(&mut StdRng::seed_from_u64(seed));
+
+        for seed in 0..16 {
+            assert_eq!(draw(seed), draw(seed));
+        }
+        assert_eq!(draw(42), Color::Black);
+        assert!((0..16).any(|seed| draw(seed) == Color::White));
+        assert!((0..16).any(|seed| draw(seed) == Color::Black));
+    }
+
+    #[test]
+    fn draws_can_be_claimed_before_they_are_automatic() {
+        let position = Position::standard();
+        let counters = |repetitions, halfmoves| DrawCounters {
+            repetitions,
+            halfmoves,
+        };
+        let claim = |repetitions, halfmoves| {
+            GameEndResult::claimable_draw(&counters(repetitions, halfmoves))
+        };
+        let automatic = |repetitions, halfmoves| {
+            GameEndResult::automatic_draw(&position, &counters(repetitions, halfmoves))
+        };
+
+        assert!(claim(2, 99).is_none());
+        assert!(matches!(
+            claim(3, 0),
+            Some(GameEndResult::ThreefoldRepetition)
+        ));
+        assert!(matches!(claim(1, 100), Some(GameEndResult::FiftyMoves)));
+
+        assert!(automatic(4, 149).is_none());
+        assert!(matches!(
+            automatic(5, 0),
+            Some(GameEndResult::FivefoldRepetition)
+        ));
+        assert!(matches!(
+            automatic(1, 150),
+            Some(GameEndResult::SeventyFiveMoves)
+        ));
+    }
+
+    #[test]
+    fn bare_kings_draw_at_once() {
+        let position = Position::from_fen("4k3/8/8/8/8/8/8/4K3 w - - 0 1", false).unwrap();
+        let counters = DrawCounters {
+            repetitions: 1,
+            halfmoves: 0,
+        };
+
+        assert!(matches!(
+            GameEndResult::automatic_draw(&position, &counters),
+            Some(GameEndResult::InsufficientMaterial)
+        ));
+    }
+}
@@ -0,0 +1,451 @@
+use super::{
+    Clock, ColorChoice, GameState, Player, PlayerInfo, Players, Room, RoomSettings, TimeControl,
+    Variant,
+};
+
+use crate::actors::room_manager::RoomManager;
+use crate::util::chess::parse_move;
+use crate::util::redis::{expire, hset, persist};
+use crate::util::variant::ChessGame;
+use actix::prelude::*;
+use actix_redis::Command;
+use chess::Color;
+use chrono::NaiveDateTime;
+use rand::rngs::StdRng;
+use sqlx::PgPool;
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+/*
+   The whole room is stored in the `rc:room:{id}` hash so it can be rebuilt
+   when the server restarts:
+
+   status        waiting, started or finished
+   creator       id of the player who created the room
+   creator_name  username of the creator, empty for anonymous players
+   creator_rating
+                 rating of the creator, missing for anonymous players
+   variant       rules of the game: standard, chess960, king_of_the_hill,
+                 three_check or crazyhouse
+   start_position
+                 number of the Chess960 start position, 518 for standard
+   created_at    creation date, in milliseconds since the epoch
+   time_control  JSON time control, missing for untimed games
+   rated         whether the game changes the ratings of the players
+   color         side played by the creator: white, black or random
+   white, black  ids of the players
+   white_name,   usernames of the players
+   black_name
+   fen           current position, in X-FEN
+   pockets       pieces each side can drop in crazyhouse, white ones in
+                 uppercase as in `QNpp`
+   moves         UCI moves separated by spaces, castling written as the
+                 king moving onto its rook in Chess960
+   clocks        time left to the mover after each move, in milliseconds
+   draw_offer    side with a pending draw offer
+   started_at    start date, in milliseconds since the epoch
+   result        result of a finished game
+*/
+
+/// Rooms untouched for this long are gone from Redis, in case their node
+/// never comes back
+const ROOM_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+/// Closed rooms stay readable for a while
+const CLOSED_ROOM_TTL: Duration = Duration::from_secs(10 * 60);
+
+pub fn room_key(room_id: &str) -> String {
+    format!("rc:room:{}", room_id)
+}
+
+impl Room {
+    /// Writes the whole room to Redis
+    pub(super) fn save(&self) {
+        self.redis
+            .do_send(hset(room_key(&self.room_id), self.snapshot()))
+            .ok();
+
+        // Correspondence games may be left for days between moves, they are
+        // kept until they end
+        let key = room_key(&self.room_id);
+        let command = if self.time_control.is_none() && !self.is_open() {
+            persist(key)
+        } else {
+            expire(key, ROOM_TTL)
+        };
+        self.redis.do_send(command).ok();
+    }
+
+    /// Marks the room as finished, it won't be restored anymore
+    pub(super) fn save_closed(&self, result: &str) {
+        self.redis
+            .do_send(hset(
+                room_key(&self.room_id),
+                vec![
+                    ("status", "finished".to_string()),
+                    ("result", result.to_string()),
+                ],
+            ))
+            .ok();
+        self.redis
+            .do_send(expire(room_key(&self.room_id), CLOSED_ROOM_TTL))
+            .ok();
+    }
+
+    fn snapshot(&self) -> Vec<(&'static str, String)> {
+        let mut fields = vec![
+            ("creator", self.creator.id.to_string()),
+            ("creator_name", self.creator.username.clone().unwrap_or_default()),
+            (
+                "variant",
+                match self.variant {
+                    Variant::Standard => "standard",
+                    Variant::Chess960 => "chess960",
+                    Variant::KingOfTheHill => "king_of_the_hill",
+                    Variant::ThreeCheck => "three_check",
+                    Variant::Crazyhouse => "crazyhouse",
+                    Variant::Bughouse => "bughouse",
+                }
+                .to_string(),
+            ),
+            ("start_position", self.start_position.to_string()),
+            ("created_at", self.created_at.timestamp_millis().to_string()),
+            ("rated", self.rated.to_string()),
+            (
+                "color",
+                match self.color {
+                    ColorChoice::White => "white",
+                    ColorChoice::Black => "black",
+                    ColorChoice::Random => "random",
+                }
+                .to_string(),
+            ),
+        ];
+
+        if let Some(rating) = self.creator_rating {
+            fields.push(("creator_rating", rating.to_string()));
+        }
+
+        if let Some(time_control) = &self.time_control {
+            if let Ok(time_control) = serde_json::to_string(time_control) {
+                fields.push(("time_control", time_control));
+            }
+        }
+
+        match &self.state {
+            GameState::Waiting => fields.push(("status", "waiting".to_string())),
+            GameState::Started {
+                players,
+                game,
+                clock,
+                started_at,
+                ..
+            } => {
+                let clocks: Vec<String> = clock
+                    .as_ref()
+                    .map(|clock| {
+                        clock
+                            .history()
+                            .iter()
+                            .map(|left| left.as_millis().to_string())
+                            .collect()
+                    })
+                    .unwrap_or_default();
+
+                fields.extend(vec![
+                    ("status", "started".to_string()),
+                    ("white", players.w.id.to_string()),
+                    ("black", players.b.id.to_string()),
+                    ("white_name", players.w.username.clone().unwrap_or_default()),
+                    ("black_name", players.b.username.clone().unwrap_or_default()),
+                    ("fen", game.current_position().fen()),
+                    ("moves", game.uci_moves().join(" ")),
+                    ("clocks", clocks.join(",")),
+                    (
+                        "draw_offer",
+                        match game.draw_offer() {
+                            Some(Color::White) => "white",
+                            Some(Color::Black) => "black",
+                            None => "",
+                        }
+                        .to_string(),
+                    ),
+                    ("started_at", started_at.timestamp_millis().to_string()),
+                ]);
+
+                if let Some(pockets) = game.current_position().pockets() {
+                    fields.push(("pockets", pockets.to_string()));
+                }
+            }
+        }
+
+        fields
+    }
+
+    /// Rebuilds a room from its Redis hash, finished or unreadable rooms
+    /// are ignored
+    pub fn restore(
+        room_id: String,
+        fields: &HashMap<String, String>,
+        room_manager: Addr<RoomManager>,
+        redis: Recipient<Command>,
+        db_pool: PgPool,
+        rng: StdRng,
+    ) -> Option<Room> {
+        let field = |name: &str| fields.get(name).map(String::as_str);
+        let uuid = |name: &str| field(name).and_then(|id| Uuid::parse_str(id).ok());
+        let player = |id: &str, name: &str| {
+            Some(PlayerInfo {
+                id: uuid(id)?,
+                username: field(name)
+                    .filter(|username| !username.is_empty())
+                    .map(String::from),
+            })
+        };
+        let date = |name: &str| {
+            field(name)
+                .and_then(|millis| millis.parse::<i64>().ok())
+                .map(|millis| {
+                    NaiveDateTime::from_timestamp(
+                        millis.div_euclid(1000),
+                        (millis.rem_euclid(1000) * 1_000_000) as u32,
+                    )
+                })
+        };
+
+        let time_control: Option<TimeControl> =
+            field("time_control").and_then(|json| serde_json::from_str(json).ok());
+
+        let settings = RoomSettings {
+            time_control,
+            rated: field("rated") == Some("true"),
+            color: match field("color") {
+                Some("white") => ColorChoice::White,
+                Some("black") => ColorChoice::Black,
+                _ => ColorChoice::Random,
+            },
+            variant: match field("variant") {
+                Some("chess960") => Variant::Chess960,
+                Some("king_of_the_hill") => Variant::KingOfTheHill,
+                Some("three_check") => Variant::ThreeCheck,
+                Some("crazyhouse") => Variant::Crazyhouse,
+                Some("bughouse") => Variant::Bughouse,
+                _ => Variant::Standard,
+            },
+        };
+
+        let mut room = Room::new(
+            room_id,
+            player("creator", "creator_name")?,
+            settings,
+            room_manager,
+            redis,
+            db_pool,
+            rng,
+        );
+        room.created_at = date("created_at")?;
+        room.creator_rating = field("creator_rating").and_then(|rating| rating.parse().ok());
+        if let Some(start_position) = field("start_position").and_then(|n| n.parse().ok()) {
+            room.start_position = start_position;
+        }
+
+        match field("status")? {
+            "waiting" => (),
+            "started" => {
+                let mut game = ChessGame::new(room.initial_position());
+                for uci in field("moves")?.split_whitespace() {
+                    if !game.make_move(parse_move(game.current_position(), uci).ok()?) {
+                        return None;
+                    }
+                }
+
+                let draw_offer = match field("draw_offer") {
+                    Some("white") => Some(Color::White),
+                    Some("black") => Some(Color::Black),
+                    _ => None,
+                };
+                if let Some(color) = draw_offer {
+                    game.offer_draw(color).ok()?;
+                }
+
+                let history = field("clocks")?
+                    .split(',')
+                    .filter(|millis| !millis.is_empty())
+                    .map(|millis| millis.parse().map(Duration::from_millis))
+                    .collect::<Result<Vec<_>, _>>()
+                    .ok()?;
+
+                // Players get back the time spent while the server was down
+                let clock = time_control.map(|time_control| {
+                    Clock::resume(time_control, history, game.side_to_move(), Instant::now())
+                });
+
+                room.state = GameState::Started {
+                    spectators: HashSet::new(),
+                    players: Players {
+                        w: Player::new(player("white", "white_name")?),
+                        b: Player::new(player("black", "black_name")?),
+                    },
+                    game,
+                    clock,
+                    claim: None,
+                    started_at: date("started_at")?,
+                };
+            }
+            _ => return None,
+        }
+
+        Some(room)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::actors::room::Delay;
+    use actix::dev::channel;
+    use actix_redis::RedisActor;
+    use rand::SeedableRng;
+
+    const SECOND: Duration = Duration::from_secs(1);
+
+    fn player(username: &str) -> PlayerInfo {
+        PlayerInfo {
+            id: Uuid::new_v4(),
+            username: Some(username.to_string()),
+        }
+    }
+
+    /// A room whose actors are never reached, their mailboxes are enough
+    fn room(creator: PlayerInfo, settings: RoomSettings) -> Room {
+        let (room_manager, _) = channel::channel(1);
+        let (redis, _) = channel::channel::<RedisActor>(1);
+
+        Room::new(
+            "snapshot".to_string(),
+            creator,
+            settings,
+            Addr::new(room_manager),
+            Addr::new(redis).recipient(),
+            PgPool::connect_lazy("postgres://localhost/rechess").unwrap(),
+            StdRng::seed_from_u64(7),
+        )
+    }
+
+    fn restore(fields: Vec<(&'static str, String)>) -> Option<Room> {
+        let fields = fields
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), value))
+            .collect();
+        let (room_manager, _) = channel::channel(1);
+        let (redis, _) = channel::channel::<RedisActor>(1);
+
+        Room::restore(
+            "snapshot".to_string(),
+            &fields,
+            Addr::new(room_manager),
+            Addr::new(redis).recipient(),
+            PgPool::connect_lazy("postgres://localhost/rechess").unwrap(),
+            StdRng::seed_from_u64(7),
+        )
+    }
+
+    #[test]
+    fn rooms_are_restored_mid_game() {
+        System::new().block_on(async {
+            let (white, black) = (player("alice"), player("bob"));
+            let time_control = TimeControl {
+                base: 300,
+                increment: 2,
+                delay: Some(Delay::Bronstein { seconds: 1 }),
+            };
+            let mut room = room(
+                white.clone(),
+                RoomSettings {
+                    time_control: Some(time_control),
+                    rated: false,
+                    color: ColorChoice::White,
+                    variant: Variant::Chess960,
+                },
+            );
+
+            let now = Instant::now();
+            let mut game = ChessGame::new(room.initial_position());
+            let mut clock = Clock::new(time_control);
+            clock.start(Color::White, now);
+            for (ply, uci) in ["e2e4", "e7e5", "d2d4"].iter().enumerate() {
+                assert!(game.make_move(parse_move(game.current_position(), uci).unwrap()));
+                assert!(clock.press(now + (ply as u32 + 1) * 10 * SECOND));
+            }
+            game.offer_draw(Color::White).unwrap();
+
+            room.state = GameState::Started {
+                spectators: HashSet::new(),
+                players: Players {
+                    w: Player::new(white.clone()),
+                    b: Player::new(black.clone()),
+                },
+                game,
+                clock: Some(clock),
+                claim: None,
+                started_at: room.created_at,
+            };
+
+            let restored = restore(room.snapshot()).unwrap();
+            assert_eq!(restored.start_position, room.start_position);
+            assert_eq!(restored.time_control, Some(time_control));
+
+            match (&room.state, &restored.state) {
+                (
+                    GameState::Started {
+                        game,
+                        clock: Some(clock),
+                        ..
+                    },
+                    GameState::Started {
+                        players,
+                        game: restored_game,
+                        clock: Some(restored_clock),
+                        ..
+                    },
+                ) => {
+                    assert_eq!(players.w.id, white.id);
+                    assert_eq!(players.b.username, black.username);
+                    assert_eq!(restored_game.uci_moves(), game.uci_moves());
+                    assert_eq!(restored_game.side_to_move(), Color::Black);
+                    assert_eq!(restored_clock.history(), clock.history());
+                    assert_eq!(
+                        restored_clock.remaining(Color::White, Instant::now()),
+                        clock.remaining(Color::White, now + 30 * SECOND)
+                    );
+                    assert_eq!(restored_game.draw_offer(), Some(Color::White));
+                }
+                _ => panic!("the game should be restored as started"),
+            }
+        });
+    }
+
+    #[test]
+    fn finished_and_broken_rooms_are_not_restored() {
+        System::new().block_on(async {
+            let settings = RoomSettings {
+                time_control: None,
+                rated: false,
+                color: ColorChoice::Random,
+                variant: Variant::Standard,
+            };
+            let room = room(player("alice"), settings);
+            let mut fields = room.snapshot();
+            assert!(restore(fields.clone()).is_some());
+
+            fields.retain(|(name, _)| *name != "status");
+            fields.push(("status", "finished".to_string()));
+            assert!(restore(fields.clone()).is_none());
+
+            fields.retain(|(name, _)| *name != "status");
+            fields.push(("status", "started".to_string()));
+            fields.push(("moves", "e2e5".to_string()));
+            assert!(restore(fields).is_none());
+        });
+    }
+}
@@ -0,0 +1,332 @@
+//! Cooperation between server instances sharing the same Redis.
+//!
+//! Every room is owned by the node that created it, the `rc:rooms` hash maps
+//! room ids to their lobby entry and node. Each node listens on its own channel for commands
+//! aimed at its rooms and for messages to its sessions, lobby updates go
+//! through a channel every node listens on.
+//!
+//! Every run of a node has an instance id it keeps alive in `rc:alive:<node>`.
+//! Rooms registered by a run whose heartbeat is gone are orphaned, the first
+//! node to claim one restores it from its snapshot.
+
+use super::{LobbyEntry, RoomManager};
+use crate::actors::room::{self, room_key, PlayerInfo, RoomCommand};
+use crate::actors::websocket::{self, ServerMessage};
+use crate::util::redis::hgetall;
+use actix::prelude::*;
+use actix_redis::{Command, RedisActor};
+use log::{error, info, warn};
+use redis_async::resp::RespValue;
+use redis_async::resp_array;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+use uuid::Uuid;
+
+/// Hash mapping every live room to its lobby entry, holding the node owning
+/// it
+pub const REGISTRY_KEY: &str = "rc:rooms";
+pub const LOBBY_CHANNEL: &str = "rc:lobby";
+
+const RESUBSCRIBE_INTERVAL: Duration = Duration::from_secs(1);
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+/// A node missing its heartbeats for this long is considered dead
+const NODE_TTL: Duration = Duration::from_secs(15);
+/// Time a claimer has to take over a room before others may try again
+const CLAIM_TTL: Duration = Duration::from_secs(60);
+
+pub fn node_channel(node: &str) -> String {
+    format!("rc:node:{}", node)
+}
+
+/// Key holding the instance id of the running `node`
+fn alive_key(node: &str) -> String {
+    format!("rc:alive:{}", node)
+}
+
+/// Key taken by the node claiming `room_id` from the run `owner`, or from
+/// nobody when the room has no owner
+fn claim_key(room_id: &str, owner: Option<Uuid>) -> String {
+    match owner {
+        Some(owner) => format!("rc:claim:{}:{}", room_id, owner),
+        None => format!("rc:claim:{}", room_id),
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum NodeMessage {
+    /// A session of `node` joins a room of the receiving node, `session`
+    /// identifies it on its node
+    Join {
+        room_id: String,
+        id: Uuid,
+        username: Option<String>,
+        node: String,
+        instance: Uuid,
+        session: Uuid,
+    },
+    /// A session joined with `Join` left its room
+    Leave {
+        room_id: String,
+        id: Uuid,
+        session: Uuid,
+    },
+    /// A player action for a room of the receiving node
+    Command {
+        room_id: String,
+        id: Uuid,
+        command: RoomCommand,
+    },
+    /// A message for a session of the receiving node
+    Send { session: Uuid, message: ServerMessage },
+    /// An anonymous player of `node` logged in
+    LinkAccount {
+        from: Uuid,
+        to: PlayerInfo,
+        node: String,
+    },
+    RoomCreated { entry: LobbyEntry },
+    RoomStarted { room_id: String },
+    RoomRemoved { room_id: String },
+}
+
+/// Publishes `message` on `channel`
+pub fn publish(redis: &Addr<RedisActor>, channel: &str, message: &NodeMessage) {
+    match serde_json::to_string(message) {
+        Ok(payload) => redis.do_send(Command(resp_array!["PUBLISH", channel, payload])),
+        Err(e) => error!("Could not serialize node message: {:?}", e),
+    }
+}
+
+/// Stands in for a websocket session connected to another node, relaying
+/// what a room sends to it
+pub struct RemoteSession {
+    node: String,
+    session: Uuid,
+    redis: Addr<RedisActor>,
+}
+
+impl RemoteSession {
+    pub fn new(node: String, session: Uuid, redis: Addr<RedisActor>) -> Self {
+        Self {
+            node,
+            session,
+            redis,
+        }
+    }
+}
+
+impl Actor for RemoteSession {
+    type Context = Context<Self>;
+}
+
+/// A session of another node playing in a room of this node
+pub struct HostedSession {
+    pub node: String,
+    /// Run of `node` the session is connected to
+    pub instance: Uuid,
+    pub room_id: String,
+    pub id: Uuid,
+    pub addr: Addr<RemoteSession>,
+}
+
+impl Handler<websocket::Send> for RemoteSession {
+    type Result = ();
+
+    fn handle(&mut self, msg: websocket::Send, _ctx: &mut Self::Context) -> Self::Result {
+        publish(
+            &self.redis,
+            &node_channel(&self.node),
+            &NodeMessage::Send {
+                session: self.session,
+                message: msg.0,
+            },
+        );
+    }
+}
+
+impl RoomManager {
+    /// Listens to the channel of this node and to the lobby channel
+    pub(super) fn subscribe(&self, ctx: &mut Context<Self>) {
+        let pubsub = self.pubsub.clone();
+        let channel = node_channel(&self.node_id);
+
+        async move {
+            let node = pubsub.subscribe(&channel).await?;
+            let lobby = pubsub.subscribe(LOBBY_CHANNEL).await?;
+
+            Ok::<_, redis_async::error::Error>(futures::stream::select(node, lobby))
+        }
+        .into_actor(self)
+        .map(|res, _act, ctx| match res {
+            Ok(stream) => {
+                ctx.add_stream(stream);
+            }
+            Err(e) => {
+                error!("Could not subscribe to node channels: {:?}", e);
+                ctx.run_later(RESUBSCRIBE_INTERVAL, |act, ctx| act.subscribe(ctx));
+            }
+        })
+        .wait(ctx);
+    }
+
+    /// Keeps the heartbeat of this node and checks the ones of the nodes
+    /// owning rooms or sessions known here
+    pub(super) fn heartbeat(&self, ctx: &mut Context<Self>) {
+        self.beat();
+        ctx.run_interval(HEARTBEAT_INTERVAL, |act, ctx| {
+            act.beat();
+            act.check_nodes(ctx);
+        });
+    }
+
+    fn beat(&self) {
+        self.redis.do_send(Command(resp_array![
+            "SET",
+            alive_key(&self.node_id),
+            self.instance.to_string(),
+            "EX",
+            NODE_TTL.as_secs().to_string()
+        ]));
+    }
+
+    fn check_nodes(&self, ctx: &mut Context<Self>) {
+        let redis = self.redis.clone();
+        let nodes: HashSet<String> = self
+            .lobby
+            .values()
+            .map(|entry| &entry.node)
+            .chain(self.hosted_sessions.values().map(|hosted| &hosted.node))
+            .filter(|node| **node != self.node_id)
+            .cloned()
+            .collect();
+
+        async move {
+            let mut alive = HashMap::new();
+
+            for node in nodes {
+                let instance = match redis
+                    .send(Command(resp_array!["GET", alive_key(&node)]))
+                    .await??
+                {
+                    RespValue::BulkString(instance) => {
+                        Uuid::parse_str(&String::from_utf8_lossy(&instance)).ok()
+                    }
+                    _ => None,
+                };
+                alive.insert(node, instance);
+            }
+
+            Ok::<_, eyre::Report>(alive)
+        }
+        .into_actor(self)
+        .map(|res, act, ctx| match res {
+            Ok(alive) => act.drop_dead(&alive, ctx),
+            Err(e) => error!("Could not check nodes: {:?}", e),
+        })
+        .spawn(ctx);
+    }
+
+    /// Forgets the sessions of the runs missing from `alive` and claims their
+    /// rooms
+    fn drop_dead(&mut self, alive: &HashMap<String, Option<Uuid>>, ctx: &mut Context<Self>) {
+        let (node_id, own) = (&self.node_id, self.instance);
+        let is_alive = |node: &String, instance: Uuid| {
+            if node == node_id {
+                return instance == own;
+            }
+            alive.get(node).map_or(true, |live| *live == Some(instance))
+        };
+
+        let dead: Vec<Uuid> = self
+            .hosted_sessions
+            .iter()
+            .filter(|(_, hosted)| !is_alive(&hosted.node, hosted.instance))
+            .map(|(session, _)| *session)
+            .collect();
+
+        for session in dead {
+            if let Some(hosted) = self.hosted_sessions.remove(&session) {
+                if let Some(room) = self.rooms.get(&hosted.room_id) {
+                    room.addr.do_send(room::Leave {
+                        id: hosted.id,
+                        session: hosted.addr.recipient(),
+                    });
+                }
+            }
+        }
+
+        let orphans: Vec<(String, Uuid)> = self
+            .lobby
+            .iter()
+            .filter(|(_, entry)| !is_alive(&entry.node, entry.instance))
+            .map(|(room_id, entry)| (room_id.clone(), entry.instance))
+            .collect();
+
+        for (room_id, owner) in orphans {
+            self.claim_room(room_id, Some(owner), ctx);
+        }
+    }
+
+    /// Takes over a room left by the run `owner`, or by no node at all. Only
+    /// the first node to claim it restores it.
+    pub(super) fn claim_room(&self, room_id: String, owner: Option<Uuid>, ctx: &mut Context<Self>) {
+        let redis = self.redis.clone();
+        let key = room_key(&room_id);
+        let claim = Command(resp_array![
+            "SET",
+            claim_key(&room_id, owner),
+            self.node_id.clone(),
+            "NX",
+            "EX",
+            CLAIM_TTL.as_secs().to_string()
+        ]);
+
+        async move {
+            match redis.send(claim).await?? {
+                RespValue::SimpleString(_) => Ok(Some(hgetall(&redis, &key).await?)),
+                _ => Ok::<_, eyre::Report>(None),
+            }
+        }
+        .into_actor(self)
+        .map(move |res, act, ctx| match res {
+            Ok(Some(fields)) => {
+                info!("Claimed room {}", room_id);
+                act.adopt_room(room_id, &fields, ctx);
+            }
+            Ok(None) => (),
+            Err(e) => error!("Could not claim room {}: {:?}", room_id, e),
+        })
+        .spawn(ctx);
+    }
+}
+
+impl StreamHandler<Result<RespValue, redis_async::error::Error>> for RoomManager {
+    fn handle(&mut self, msg: Result<RespValue, redis_async::error::Error>, _ctx: &mut Self::Context) {
+        let payload = match msg {
+            Ok(RespValue::BulkString(payload)) => payload,
+            Ok(other) => {
+                warn!("Unexpected pub/sub message: {:?}", other);
+                return;
+            }
+            Err(e) => {
+                error!("Pub/sub error: {:?}", e);
+                return;
+            }
+        };
+
+        match serde_json::from_slice(&payload) {
+            Ok(msg) => self.handle_node_message(msg),
+            Err(e) => warn!("Invalid node message: {:?}", e),
+        }
+    }
+
+    /// The connection to Redis was lost, subscriptions have to be renewed
+    /// once it is back
+    fn finished(&mut self, ctx: &mut Self::Context) {
+        warn!("Lost pub/sub connection, resubscribing");
+        ctx.run_later(RESUBSCRIBE_INTERVAL, |act, ctx| act.subscribe(ctx));
+    }
+}
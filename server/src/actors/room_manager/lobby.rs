@@ -0,0 +1,321 @@
+//! Listing of the rooms of the cluster.
+//!
+//! A lobby session gets a page of the rooms matching its query, then only
+//! the rooms added or removed since.
+
+use crate::actors::room::{ColorChoice, PublicPlayer, Speed, TimeControl, Variant};
+
+use chrono::{NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Most rooms sent at once
+const MAX_ITEMS: usize = 50;
+/// Last page a session can ask for
+const MAX_PAGE: usize = 1000;
+
+/// A room as shown in the lobby, `kind` telling the types apart
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "kind")]
+#[serde(rename_all = "snake_case")]
+pub enum LobbyRoom {
+    Game(GameLobbyRoom),
+    Bughouse(BughouseLobbyRoom),
+}
+
+/// A room for a game between two players
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct GameLobbyRoom {
+    pub room_id: String,
+    pub creator: PublicPlayer,
+    /// Rating of the creator in the speed of the game, anonymous players
+    /// have none
+    pub creator_rating: Option<i32>,
+    pub time_control: Option<TimeControl>,
+    pub variant: Variant,
+    pub rated: bool,
+    /// Side played by the creator
+    pub color: ColorChoice,
+    pub created_at: NaiveDateTime,
+}
+
+/// A bughouse room, filling its four seats
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BughouseLobbyRoom {
+    pub room_id: String,
+    pub creator: PublicPlayer,
+    /// Rating of the creator in the speed of the game, anonymous players
+    /// have none
+    pub creator_rating: Option<i32>,
+    pub time_control: Option<TimeControl>,
+    pub created_at: NaiveDateTime,
+}
+
+/// A room of the cluster with the node owning it, stored as JSON in the
+/// registry
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LobbyEntry {
+    pub node: String,
+    /// Run of `node` that registered the room
+    pub instance: Uuid,
+    pub room: LobbyRoom,
+    /// Whether the room waits for players, only open rooms are listed.
+    /// Started rooms are kept for sessions of other nodes to find them.
+    pub open: bool,
+}
+
+/// A room as sent to a session
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LobbyListing {
+    #[serde(flatten)]
+    pub room: LobbyRoom,
+    /// Time since the room was created, in seconds
+    pub age: i64,
+}
+
+/// Rooms a session is interested in, every field left out matches any room
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct LobbyFilter {
+    pub speed: Option<Speed>,
+    pub variant: Option<Variant>,
+    pub rated: Option<bool>,
+    /// Side played by the creator
+    pub color: Option<ColorChoice>,
+    /// Bounds on the rating of the creator, excluding anonymous creators
+    pub min_rating: Option<i32>,
+    pub max_rating: Option<i32>,
+}
+
+#[derive(Debug, Clone)]
+pub struct LobbyQuery {
+    pub filter: LobbyFilter,
+    pub page: usize,
+    pub items: usize,
+}
+
+impl LobbyRoom {
+    pub fn room_id(&self) -> &str {
+        match self {
+            LobbyRoom::Game(room) => &room.room_id,
+            LobbyRoom::Bughouse(room) => &room.room_id,
+        }
+    }
+
+    pub fn created_at(&self) -> NaiveDateTime {
+        match self {
+            LobbyRoom::Game(room) => room.created_at,
+            LobbyRoom::Bughouse(room) => room.created_at,
+        }
+    }
+
+    pub fn listing(&self) -> LobbyListing {
+        LobbyListing {
+            room: self.clone(),
+            age: (Utc::now().naive_utc() - self.created_at())
+                .num_seconds()
+                .max(0),
+        }
+    }
+}
+
+impl LobbyFilter {
+    pub fn matches(&self, room: &LobbyRoom) -> bool {
+        let (creator_rating, time_control, variant, rated, color) = match room {
+            LobbyRoom::Game(room) => (
+                room.creator_rating,
+                room.time_control,
+                room.variant,
+                room.rated,
+                room.color,
+            ),
+            // Bughouse games are casual and seat players in join order
+            LobbyRoom::Bughouse(room) => (
+                room.creator_rating,
+                room.time_control,
+                Variant::Bughouse,
+                false,
+                ColorChoice::Random,
+            ),
+        };
+
+        let rating = |bound: Option<i32>, within: fn(i32, i32) -> bool| match bound {
+            Some(bound) => creator_rating.map_or(false, |rating| within(rating, bound)),
+            None => true,
+        };
+
+        self.speed
+            .map_or(true, |speed| Speed::of(time_control) == speed)
+            && self.variant.map_or(true, |filter| variant == filter)
+            && self.rated.map_or(true, |filter| rated == filter)
+            && self.color.map_or(true, |filter| color == filter)
+            && rating(self.min_rating, |rating, min| rating >= min)
+            && rating(self.max_rating, |rating, max| rating <= max)
+    }
+}
+
+impl LobbyQuery {
+    pub fn new(filter: LobbyFilter, page: usize, items: usize) -> Self {
+        Self {
+            filter,
+            page: page.min(MAX_PAGE),
+            items: items.clamp(1, MAX_ITEMS),
+        }
+    }
+
+    /// Rooms of the page, newest first, and the number of matching rooms
+    pub fn page<'a>(
+        &self,
+        rooms: impl Iterator<Item = &'a LobbyRoom>,
+    ) -> (Vec<LobbyListing>, usize) {
+        let matching: Vec<&LobbyRoom> = rooms.filter(|room| self.filter.matches(room)).collect();
+
+        let listings = matching
+            .iter()
+            .skip(self.page.saturating_mul(self.items))
+            .take(self.items)
+            .map(|room| room.listing())
+            .collect();
+
+        (listings, matching.len())
+    }
+
+    /// Whether a new room shows up on the page, new rooms only push the
+    /// others down so only the first page gets them
+    pub fn wants(&self, room: &LobbyRoom) -> bool {
+        self.page == 0 && self.filter.matches(room)
+    }
+}
+
+impl Default for LobbyQuery {
+    fn default() -> Self {
+        Self::new(LobbyFilter::default(), 0, 12)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn creator() -> PublicPlayer {
+        PublicPlayer {
+            handle: Uuid::nil(),
+            username: None,
+        }
+    }
+
+    fn game(room_id: &str, base: u64, variant: Variant, rated: bool) -> LobbyRoom {
+        LobbyRoom::Game(GameLobbyRoom {
+            room_id: room_id.to_string(),
+            creator: creator(),
+            creator_rating: Some(1500),
+            time_control: Some(TimeControl {
+                base,
+                increment: 0,
+                delay: None,
+            }),
+            variant,
+            rated,
+            color: ColorChoice::Random,
+            created_at: Utc::now().naive_utc(),
+        })
+    }
+
+    fn bughouse(room_id: &str) -> LobbyRoom {
+        LobbyRoom::Bughouse(BughouseLobbyRoom {
+            room_id: room_id.to_string(),
+            creator: creator(),
+            creator_rating: None,
+            time_control: None,
+            created_at: Utc::now().naive_utc(),
+        })
+    }
+
+    #[test]
+    fn filters_by_variant_speed_and_rating() {
+        let blitz = game("blitz", 300, Variant::Standard, true);
+        let chess960 = game("chess960", 60, Variant::Chess960, false);
+        let bughouse = bughouse("bughouse");
+
+        let filter = |filter: LobbyFilter, room: &LobbyRoom| filter.matches(room);
+
+        assert!(filter(LobbyFilter::default(), &blitz));
+        assert!(filter(LobbyFilter::default(), &bughouse));
+
+        let variant = |variant| LobbyFilter {
+            variant: Some(variant),
+            ..LobbyFilter::default()
+        };
+        assert!(filter(variant(Variant::Chess960), &chess960));
+        assert!(!filter(variant(Variant::Chess960), &blitz));
+        assert!(filter(variant(Variant::Bughouse), &bughouse));
+
+        let speed = |speed| LobbyFilter {
+            speed: Some(speed),
+            ..LobbyFilter::default()
+        };
+        assert!(filter(speed(Speed::Blitz), &blitz));
+        assert!(!filter(speed(Speed::Blitz), &chess960));
+        assert!(filter(speed(Speed::Correspondence), &bughouse));
+
+        let rated = |rated| LobbyFilter {
+            rated: Some(rated),
+            ..LobbyFilter::default()
+        };
+        assert!(filter(rated(true), &blitz));
+        assert!(!filter(rated(true), &chess960));
+        assert!(!filter(rated(true), &bughouse));
+        assert!(filter(rated(false), &bughouse));
+    }
+
+    #[test]
+    fn rating_bounds_leave_out_anonymous_creators() {
+        let filter = LobbyFilter {
+            min_rating: Some(1400),
+            max_rating: Some(1500),
+            ..LobbyFilter::default()
+        };
+
+        assert!(filter.matches(&game("rated", 300, Variant::Standard, true)));
+        assert!(!filter.matches(&bughouse("anonymous")));
+    }
+
+    #[test]
+    fn pages_stop_at_the_last_room() {
+        let rooms: Vec<LobbyRoom> = (0..5)
+            .map(|i| game(&i.to_string(), 300, Variant::Standard, false))
+            .collect();
+        let page = |page| {
+            let (listings, total) =
+                LobbyQuery::new(LobbyFilter::default(), page, 2).page(rooms.iter());
+            let ids: Vec<String> = listings
+                .iter()
+                .map(|listing| listing.room.room_id().to_string())
+                .collect();
+            (ids, total)
+        };
+
+        assert_eq!(page(0), (vec!["0".to_string(), "1".to_string()], 5));
+        assert_eq!(page(2), (vec!["4".to_string()], 5));
+        assert_eq!(page(3), (vec![], 5));
+        assert_eq!(page(usize::MAX).0, Vec::<String>::new());
+    }
+
+    #[test]
+    fn pages_count_matching_rooms_only() {
+        let rooms = [
+            game("standard", 300, Variant::Standard, false),
+            game("chess960", 300, Variant::Chess960, false),
+        ];
+        let filter = LobbyFilter {
+            variant: Some(Variant::Chess960),
+            ..LobbyFilter::default()
+        };
+
+        let (listings, total) = LobbyQuery::new(filter, 0, 10).page(rooms.iter());
+        assert_eq!(listings.len(), 1);
+        assert_eq!(total, 1);
+
+        let query = LobbyQuery::new(LobbyFilter::default(), 0, 0);
+        assert_eq!(query.items, 1);
+    }
+}
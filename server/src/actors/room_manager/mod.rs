@@ -1,45 +1,512 @@
+pub mod cluster;
+pub mod lobby;
 pub mod model;
 
+pub use lobby::*;
 pub use model::*;
 
-use super::room::{self, Room};
+use super::bughouse::BughouseRoom;
+use super::room::{
+    self, room_key, ColorChoice, PlayerColor, PlayerInfo, Room, RoomSettings, Speed, Variant,
+};
 use super::websocket;
 use super::websocket::model::ServerMessage;
+use crate::app::ratings::model::Rating;
+use crate::util::redis::{hgetall, scan_keys};
 use actix::prelude::*;
-use actix_redis::RedisActor;
+use actix_redis::{Command, RedisActor};
+use cluster::{
+    node_channel, publish, HostedSession, NodeMessage, RemoteSession, LOBBY_CHANNEL, REGISTRY_KEY,
+};
 use indexmap::IndexMap;
-use log::info;
+use log::{error, info};
 use rand::distributions::Alphanumeric;
-use rand::Rng;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use redis_async::client::PubsubConnection;
+use redis_async::resp::RespValue;
+use redis_async::resp_array;
+use sqlx::PgPool;
 use std::collections::HashMap;
-use std::time::Instant;
 use uuid::Uuid;
 
 pub struct RoomManager {
-    sessions: HashMap<Uuid, Recipient<websocket::Send>>,
+    node_id: String,
+    /// Identifies this run of the node, rooms registered by an earlier run
+    /// are orphaned
+    instance: Uuid,
+    /// Open sessions of every player, one for each of their tabs
+    sessions: HashMap<Uuid, Vec<Addr<websocket::WebsocketSession>>>,
+    /// Rooms owned by this node
     rooms: IndexMap<String, RoomData>,
+    /// Every room of the cluster with the node owning it, oldest first
+    lobby: IndexMap<String, LobbyEntry>,
+    /// Lobby sessions with the rooms they are shown
+    lobby_sessions: HashMap<Recipient<websocket::Send>, LobbyQuery>,
+    /// Local sessions playing in a room of another node
+    remote_sessions: HashMap<Uuid, Recipient<websocket::Send>>,
+    /// Sessions of other nodes playing in a room of this node
+    hosted_sessions: HashMap<Uuid, HostedSession>,
     redis: Addr<RedisActor>,
+    pubsub: PubsubConnection,
+    db_pool: PgPool,
+    /// Seeds the generators of the rooms and draws their ids
+    rng: StdRng,
 }
 
 impl RoomManager {
-    pub fn new(redis: Addr<RedisActor>) -> Self {
+    pub fn new(
+        node_id: String,
+        redis: Addr<RedisActor>,
+        pubsub: PubsubConnection,
+        db_pool: PgPool,
+        rng: StdRng,
+    ) -> Self {
         Self {
+            node_id,
+            instance: Uuid::new_v4(),
             sessions: HashMap::new(),
             rooms: IndexMap::new(),
+            lobby: IndexMap::new(),
+            lobby_sessions: HashMap::new(),
+            remote_sessions: HashMap::new(),
+            hosted_sessions: HashMap::new(),
             redis,
+            pubsub,
+            db_pool,
+            rng,
+        }
+    }
+}
+
+impl RoomManager {
+    /// Loads the lobby of the cluster and claims the rooms left by an earlier
+    /// run of this node or by no node at all, so that games survive a restart
+    fn restore_rooms(&self, ctx: &mut Context<Self>) {
+        let redis = self.redis.clone();
+
+        async move {
+            let registry = hgetall(&redis, REGISTRY_KEY).await?;
+            let rooms: Vec<String> = scan_keys(&redis, &room_key("*"))
+                .await?
+                .iter()
+                .map(|key| key.trim_start_matches(&room_key("")).to_string())
+                .collect();
+
+            Ok::<_, eyre::Report>((registry, rooms))
+        }
+        .into_actor(self)
+        .map(|res, act, ctx| match res {
+            Ok((registry, rooms)) => {
+                act.lobby.extend(registry.into_iter().filter_map(|(room_id, entry)| {
+                    Some((room_id, serde_json::from_str(&entry).ok()?))
+                }));
+                act.lobby.sort_by(|_, a: &LobbyEntry, _, b| {
+                    a.room.created_at().cmp(&b.room.created_at())
+                });
+
+                // Rooms of other nodes are claimed once their node is dead
+                let own: Vec<(String, Uuid)> = act
+                    .lobby
+                    .iter()
+                    .filter(|(_, entry)| entry.node == act.node_id)
+                    .map(|(room_id, entry)| (room_id.clone(), entry.instance))
+                    .collect();
+
+                for (room_id, owner) in own {
+                    act.claim_room(room_id, Some(owner), ctx);
+                }
+
+                for room_id in rooms {
+                    if !act.lobby.contains_key(&room_id) {
+                        act.claim_room(room_id, None, ctx);
+                    }
+                }
+            }
+            Err(e) => error!("Could not restore rooms: {:?}", e),
+        })
+        .wait(ctx);
+    }
+
+    /// Restores a room claimed by this node from its snapshot, a room that
+    /// is over leaves the registry
+    fn adopt_room(
+        &mut self,
+        room_id: String,
+        fields: &HashMap<String, String>,
+        ctx: &mut Context<Self>,
+    ) {
+        let room = match fields.get("kind").map(String::as_str) {
+            Some("bughouse") => BughouseRoom::restore(
+                room_id.clone(),
+                fields,
+                ctx.address(),
+                self.redis.clone().recipient(),
+                self.db_pool.clone(),
+            )
+            .map(|room| {
+                (
+                    room.lobby_room(),
+                    room.is_open(),
+                    RoomAddr::Bughouse(room.start()),
+                )
+            }),
+            _ => Room::restore(
+                room_id.clone(),
+                fields,
+                ctx.address(),
+                self.redis.clone().recipient(),
+                self.db_pool.clone(),
+                self.room_rng(),
+            )
+            .map(|room| {
+                (
+                    room.lobby_room(),
+                    room.is_open(),
+                    RoomAddr::Game(room.start()),
+                )
+            }),
+        };
+
+        match room {
+            Some((lobby_room, open, addr)) => {
+                info!("Restoring room with id: {}", room_id);
+
+                self.rooms.insert(room_id, RoomData { addr });
+                self.register(lobby_room, open);
+            }
+            None => self.unregister(&room_id),
+        }
+    }
+
+    /// Announces a room of this node to the cluster, it is listed in the
+    /// lobby while `open`
+    fn register(&mut self, room: LobbyRoom, open: bool) {
+        let entry = LobbyEntry {
+            node: self.node_id.clone(),
+            instance: self.instance,
+            room,
+            open,
+        };
+
+        self.store_entry(&entry);
+
+        publish(
+            &self.redis,
+            LOBBY_CHANNEL,
+            &NodeMessage::RoomCreated {
+                entry: entry.clone(),
+            },
+        );
+
+        if entry.open {
+            self.room_added(&entry.room);
+        }
+        self.lobby.insert(entry.room.room_id().to_string(), entry);
+    }
+
+    fn store_entry(&self, entry: &LobbyEntry) {
+        match serde_json::to_string(entry) {
+            Ok(json) => self.redis.do_send(Command(resp_array![
+                "HSET",
+                REGISTRY_KEY,
+                entry.room.room_id(),
+                json
+            ])),
+            Err(e) => error!("Could not serialize lobby entry: {:?}", e),
+        }
+    }
+
+    /// Takes a room out of the lobby listing, returns whether it was listed
+    fn close(&mut self, room_id: &str) -> bool {
+        match self.lobby.get_mut(room_id) {
+            Some(entry) if entry.open => {
+                entry.open = false;
+                self.room_removed(room_id);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn unregister(&mut self, room_id: &str) {
+        self.redis
+            .do_send(Command(resp_array!["HDEL", REGISTRY_KEY, room_id]));
+
+        publish(
+            &self.redis,
+            LOBBY_CHANNEL,
+            &NodeMessage::RoomRemoved {
+                room_id: room_id.to_string(),
+            },
+        );
+
+        if let Some(entry) = self.lobby.shift_remove(room_id) {
+            if entry.open {
+                self.room_removed(room_id);
+            }
+        }
+    }
+
+    /// Unregisters the rooms said to be owned by this node that it doesn't
+    /// have, the ones of dead nodes are claimed instead
+    fn remove_stale(&mut self) {
+        let stale: Vec<String> = self
+            .lobby
+            .iter()
+            .filter(|(room_id, entry)| {
+                entry.node == self.node_id
+                    && entry.instance == self.instance
+                    && !self.rooms.contains_key(*room_id)
+            })
+            .map(|(room_id, _)| room_id.clone())
+            .collect();
+
+        for room_id in stale {
+            self.unregister(&room_id);
+        }
+    }
+
+    /// Sends a new room to the lobby sessions it shows up for
+    fn room_added(&mut self, room: &LobbyRoom) {
+        let listing = room.listing();
+
+        self.lobby_sessions.retain(|session, query| {
+            !query.wants(room)
+                || session
+                    .do_send(websocket::Send(ServerMessage::RoomAdded {
+                        room: listing.clone(),
+                    }))
+                    .is_ok()
+        });
+    }
+
+    fn room_removed(&mut self, room_id: &str) {
+        self.lobby_sessions.retain(|session, _| {
+            session
+                .do_send(websocket::Send(ServerMessage::RoomRemoved {
+                    room_id: room_id.to_string(),
+                }))
+                .is_ok()
+        });
+    }
+
+    /// Generator of a new room, seeded from the one of the manager
+    fn room_rng(&mut self) -> StdRng {
+        StdRng::from_rng(&mut self.rng).unwrap_or_else(|_| StdRng::from_entropy())
+    }
+
+    /// Joins a room owned by `node`, messages of the room reach the session
+    /// through the channel of this node
+    fn join_remote(&mut self, msg: Join, node: String) {
+        let session = Uuid::new_v4();
+
+        self.remote_sessions
+            .insert(session, msg.session.clone().recipient());
+
+        publish(
+            &self.redis,
+            &node_channel(&node),
+            &NodeMessage::Join {
+                room_id: msg.room_id.clone(),
+                id: msg.id,
+                username: msg.username.clone(),
+                node: self.node_id.clone(),
+                instance: self.instance,
+                session,
+            },
+        );
+
+        msg.session.do_send(websocket::JoinedRoom(RoomHandle::Remote {
+            room_id: msg.room_id,
+            node,
+        }));
+    }
+
+    /// Starts a new room owned by this node, `listed` rooms are shown in the
+    /// lobby until their game starts
+    fn add_room(
+        &mut self,
+        creator: PlayerInfo,
+        creator_rating: Option<i32>,
+        settings: RoomSettings,
+        listed: bool,
+        ctx: &mut Context<Self>,
+    ) -> String {
+        let room_id: String = (&mut self.rng)
+            .sample_iter(&Alphanumeric)
+            .take(12)
+            .map(char::from)
+            .collect();
+
+        info!("Creating new room with id: {}", room_id);
+
+        // Bughouse games have a room type of their own
+        let (lobby_room, addr) = match settings.variant {
+            Variant::Bughouse => {
+                let room = BughouseRoom::new(
+                    room_id.clone(),
+                    creator,
+                    settings.time_control,
+                    ctx.address(),
+                    self.redis.clone().recipient(),
+                    self.db_pool.clone(),
+                )
+                .with_creator_rating(creator_rating);
+
+                (room.lobby_room(), RoomAddr::Bughouse(room.start()))
+            }
+            _ => {
+                let room = Room::new(
+                    room_id.clone(),
+                    creator,
+                    settings,
+                    ctx.address(),
+                    self.redis.clone().recipient(),
+                    self.db_pool.clone(),
+                    self.room_rng(),
+                )
+                .with_creator_rating(creator_rating);
+
+                (room.lobby_room(), RoomAddr::Game(room.start()))
+            }
+        };
+
+        self.rooms.insert(room_id.clone(), RoomData { addr });
+        self.register(lobby_room, listed);
+
+        room_id
+    }
+
+    /// Moves the rooms and sessions of this node from `from` to `to`
+    fn link_account(&mut self, from: Uuid, to: PlayerInfo) {
+        for room in self.rooms.values() {
+            room.addr.do_send(room::MergePlayer {
+                from,
+                to: to.clone(),
+            });
+        }
+
+        if let Some(sessions) = self.sessions.remove(&from) {
+            for session in sessions.iter() {
+                session.do_send(websocket::Identify {
+                    id: to.id,
+                    username: to.username.clone(),
+                });
+            }
+            self.sessions.entry(to.id).or_default().extend(sessions);
+        }
+    }
+
+    fn handle_node_message(&mut self, msg: NodeMessage) {
+        match msg {
+            NodeMessage::Join {
+                room_id,
+                id,
+                username,
+                node,
+                instance,
+                session,
+            } => {
+                if let Some(room) = self.rooms.get(&room_id) {
+                    let remote =
+                        RemoteSession::new(node.clone(), session, self.redis.clone()).start();
+
+                    room.addr.do_send(room::Join {
+                        id,
+                        username,
+                        session: remote.clone().recipient(),
+                    });
+                    self.hosted_sessions.insert(
+                        session,
+                        HostedSession {
+                            node,
+                            instance,
+                            room_id,
+                            id,
+                            addr: remote,
+                        },
+                    );
+                }
+            }
+            NodeMessage::Leave {
+                room_id,
+                id,
+                session,
+            } => {
+                if let Some(hosted) = self.hosted_sessions.remove(&session) {
+                    if let Some(room) = self.rooms.get(&room_id) {
+                        room.addr.do_send(room::Leave {
+                            id,
+                            session: hosted.addr.recipient(),
+                        });
+                    }
+                }
+            }
+            NodeMessage::Command {
+                room_id,
+                id,
+                command,
+            } => {
+                if let Some(room) = self.rooms.get(&room_id) {
+                    command.send_to(&room.addr, id);
+                }
+            }
+            NodeMessage::Send { session, message } => {
+                if let Some(recipient) = self.remote_sessions.get(&session) {
+                    if recipient.do_send(websocket::Send(message)).is_err() {
+                        self.remote_sessions.remove(&session);
+                    }
+                }
+            }
+            NodeMessage::LinkAccount { from, to, node } => {
+                if node != self.node_id {
+                    self.link_account(from, to);
+                }
+            }
+            // Rooms claimed from a dead node keep their place in the lobby
+            NodeMessage::RoomCreated { entry } => {
+                if entry.node != self.node_id {
+                    let listed = self
+                        .lobby
+                        .get(entry.room.room_id())
+                        .map_or(false, |listed| listed.open);
+
+                    if entry.open && !listed {
+                        self.room_added(&entry.room);
+                    }
+                    self.lobby.insert(entry.room.room_id().to_string(), entry);
+                }
+            }
+            NodeMessage::RoomStarted { room_id } => {
+                self.close(&room_id);
+            }
+            NodeMessage::RoomRemoved { room_id } => {
+                if let Some(entry) = self.lobby.shift_remove(&room_id) {
+                    if entry.open {
+                        self.room_removed(&room_id);
+                    }
+                }
+            }
         }
     }
 }
 
 impl Actor for RoomManager {
     type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.heartbeat(ctx);
+        self.restore_rooms(ctx);
+        self.subscribe(ctx);
+    }
 }
 
 impl Handler<Connect> for RoomManager {
     type Result = ();
 
-    fn handle(&mut self, msg: Connect, ctx: &mut Self::Context) -> Self::Result {
-        self.sessions.insert(msg.id, msg.session);
+    fn handle(&mut self, msg: Connect, _ctx: &mut Self::Context) -> Self::Result {
+        self.sessions.entry(msg.id).or_default().push(msg.session);
     }
 }
 
@@ -47,87 +514,273 @@ impl Handler<Join> for RoomManager {
     type Result = ();
 
     fn handle(&mut self, msg: Join, ctx: &mut Self::Context) -> Self::Result {
-        match self.rooms.get(&msg.room_id) {
-            Some(room) => {
-                room.addr.do_send(room::Join {
-                    session: msg.session.clone(),
-                    id: msg.id,
-                });
-                msg.session
-                    .do_send(websocket::JoinedRoom(room.addr.clone()));
+        if let Some(room) = self.rooms.get(&msg.room_id) {
+            room.addr.do_send(room::Join {
+                session: msg.session.clone().recipient(),
+                id: msg.id,
+                username: msg.username.clone(),
+            });
+            msg.session
+                .do_send(websocket::JoinedRoom(RoomHandle::Local(room.addr.clone())));
+            return;
+        }
+
+        if let Some(entry) = self.lobby.get(&msg.room_id) {
+            let node = entry.node.clone();
+            self.join_remote(msg, node);
+            return;
+        }
+
+        // The room may have been created on another node too recently for
+        // its announcement to have reached us
+        let redis = self.redis.clone();
+        let room_id = msg.room_id.clone();
+
+        async move {
+            redis
+                .send(Command(resp_array!["HGET", REGISTRY_KEY, room_id]))
+                .await
+        }
+        .into_actor(self)
+        .map(|res, act, _ctx| {
+            if let Ok(Ok(RespValue::BulkString(entry))) = res {
+                if let Ok(entry) = serde_json::from_slice::<LobbyEntry>(&entry) {
+                    if entry.node != act.node_id {
+                        act.join_remote(msg, entry.node);
+                    }
+                }
             }
-            None => (),
-        };
+        })
+        .spawn(ctx);
+    }
+}
+
+impl Handler<Forward> for RoomManager {
+    type Result = ();
+
+    fn handle(&mut self, msg: Forward, _ctx: &mut Self::Context) -> Self::Result {
+        publish(
+            &self.redis,
+            &node_channel(&msg.node),
+            &NodeMessage::Command {
+                room_id: msg.room_id,
+                id: msg.id,
+                command: msg.command,
+            },
+        );
+    }
+}
+
+impl Handler<LinkAccount> for RoomManager {
+    type Result = ();
+
+    fn handle(&mut self, msg: LinkAccount, _ctx: &mut Self::Context) -> Self::Result {
+        publish(
+            &self.redis,
+            LOBBY_CHANNEL,
+            &NodeMessage::LinkAccount {
+                from: msg.from,
+                to: msg.to.clone(),
+                node: self.node_id.clone(),
+            },
+        );
+
+        self.link_account(msg.from, msg.to);
     }
 }
 
 impl Handler<Create> for RoomManager {
-    type Result = String;
+    type Result = ();
 
     fn handle(&mut self, msg: Create, ctx: &mut Self::Context) -> Self::Result {
-        let room_id: String = rand::thread_rng()
-            .sample_iter(&Alphanumeric)
-            .take(12)
-            .map(char::from)
-            .collect();
+        let db_pool = self.db_pool.clone();
+        let id = msg.id;
+        let registered = msg.username.is_some();
+        let speed = Speed::of(msg.settings.time_control);
 
-        info!("Creating new room with id: {}", room_id);
+        // The lobby shows the rating of registered creators
+        async move {
+            if registered {
+                Rating::find(&db_pool, id, speed)
+                    .await
+                    .map(|glicko| Some(glicko.rating.round() as i32))
+            } else {
+                Ok(None)
+            }
+        }
+        .into_actor(self)
+        .map(move |res, act, ctx| {
+            let rating = res.unwrap_or_else(|e| {
+                error!("Could not find rating of {}: {:?}", msg.id, e);
+                None
+            });
 
-        let room = RoomData {
-            addr: Room::new(
-                room_id.clone(),
-                msg.id,
-                ctx.address(),
-                self.redis.clone().recipient(),
-            )
-            .start(),
-            created_at: Instant::now(),
+            let room_id = act.add_room(
+                PlayerInfo {
+                    id: msg.id,
+                    username: msg.username,
+                },
+                rating,
+                msg.settings,
+                true,
+                ctx,
+            );
+
+            msg.session
+                .do_send(websocket::Send(ServerMessage::Create { room_id }));
+        })
+        .spawn(ctx);
+    }
+}
+
+impl Handler<CreatePaired> for RoomManager {
+    type Result = ();
+
+    fn handle(&mut self, msg: CreatePaired, ctx: &mut Self::Context) -> Self::Result {
+        let settings = RoomSettings {
+            time_control: msg.time_control,
+            rated: msg.rated,
+            color: ColorChoice::White,
+            variant: Variant::Standard,
         };
+        // Paired players are seated right away, nobody else may take the room
+        let room_id = self.add_room(msg.white.clone(), msg.white_rating, settings, false, ctx);
 
-        self.rooms.insert(room_id.clone(), room.clone());
+        if let Some(RoomData {
+            addr: RoomAddr::Game(room),
+            ..
+        }) = self.rooms.get(&room_id)
+        {
+            room.do_send(room::Start {
+                white: msg.white,
+                black: msg.black,
+            });
+        }
 
-        msg.session.do_send(websocket::Send(ServerMessage::Create {
+        msg.white_session.do_send(websocket::Send(ServerMessage::Paired {
             room_id: room_id.clone(),
+            color: PlayerColor::White,
         }));
+        msg.black_session.do_send(websocket::Send(ServerMessage::Paired {
+            room_id,
+            color: PlayerColor::Black,
+        }));
+    }
+}
+
+impl Handler<Disconnect> for RoomManager {
+    type Result = ();
 
-        // TODO: Only send new room
-        for session in self.sessions.values() {
-            session.do_send(websocket::Send(ServerMessage::List {
-                rooms: self.rooms.keys().cloned().rev().take(12).collect(),
-            }));
+    fn handle(&mut self, msg: Disconnect, _ctx: &mut Self::Context) -> Self::Result {
+        if let Some(sessions) = self.sessions.get_mut(&msg.id) {
+            sessions.retain(|session| *session != msg.session);
+            if sessions.is_empty() {
+                self.sessions.remove(&msg.id);
+            }
         }
 
-        room_id
+        let recipient = msg.session.recipient();
+        self.lobby_sessions.remove(&recipient);
+
+        match msg.room {
+            Some(RoomHandle::Local(room)) => room.do_send(room::Leave {
+                id: msg.id,
+                session: recipient,
+            }),
+            Some(RoomHandle::Remote { room_id, node }) => {
+                let left: Vec<Uuid> = self
+                    .remote_sessions
+                    .iter()
+                    .filter(|(_, session)| **session == recipient)
+                    .map(|(session, _)| *session)
+                    .collect();
+
+                for session in left {
+                    self.remote_sessions.remove(&session);
+                    publish(
+                        &self.redis,
+                        &node_channel(&node),
+                        &NodeMessage::Leave {
+                            room_id: room_id.clone(),
+                            id: msg.id,
+                            session,
+                        },
+                    );
+                }
+            }
+            None => (),
+        }
     }
 }
 
-impl Handler<Disconnect> for RoomManager {
+impl Handler<List> for RoomManager {
     type Result = ();
 
-    fn handle(&mut self, msg: Disconnect, ctx: &mut Self::Context) -> Self::Result {}
+    fn handle(&mut self, msg: List, _ctx: &mut Self::Context) -> Self::Result {
+        let (rooms, total) = msg
+            .query
+            .page(
+                self.lobby
+                    .values()
+                    .rev()
+                    .filter(|entry| entry.open)
+                    .map(|entry| &entry.room),
+            );
+
+        msg.session
+            .do_send(websocket::Send(ServerMessage::List {
+                rooms,
+                page: msg.query.page,
+                total,
+            }))
+            .ok();
+
+        self.lobby_sessions.insert(msg.session, msg.query);
+    }
 }
 
-impl Handler<List> for RoomManager {
+impl Handler<Sweep> for RoomManager {
     type Result = ();
 
-    fn handle(&mut self, msg: List, ctx: &mut Self::Context) -> Self::Result {
-        msg.session.do_send(websocket::Send(ServerMessage::List {
-            rooms: self.rooms.keys().cloned().rev().take(msg.items).collect(),
-        }));
+    fn handle(&mut self, msg: Sweep, _ctx: &mut Self::Context) -> Self::Result {
+        for room in self.rooms.values() {
+            room.addr.do_send(room::Sweep {
+                waiting_grace: msg.waiting_grace,
+                abandoned_grace: msg.abandoned_grace,
+            });
+        }
+
+        self.remove_stale();
     }
 }
 
-impl Handler<RemoveRoom> for RoomManager {
+impl Handler<RoomStarted> for RoomManager {
     type Result = ();
 
-    fn handle(&mut self, msg: RemoveRoom, ctx: &mut Self::Context) -> Self::Result {
-        self.rooms.remove(&msg.room_id);
+    fn handle(&mut self, msg: RoomStarted, _ctx: &mut Self::Context) -> Self::Result {
+        if !self.close(&msg.room_id) {
+            return;
+        }
 
-        // TODO: Only send new room
-        for session in self.sessions.values() {
-            session.do_send(websocket::Send(ServerMessage::List {
-                rooms: self.rooms.keys().cloned().rev().take(12).collect(),
-            }));
+        if let Some(entry) = self.lobby.get(&msg.room_id) {
+            self.store_entry(entry);
         }
+
+        publish(
+            &self.redis,
+            LOBBY_CHANNEL,
+            &NodeMessage::RoomStarted {
+                room_id: msg.room_id,
+            },
+        );
+    }
+}
+
+impl Handler<RemoveRoom> for RoomManager {
+    type Result = ();
+
+    fn handle(&mut self, msg: RemoveRoom, _ctx: &mut Self::Context) -> Self::Result {
+        self.rooms.remove(&msg.room_id);
+        self.unregister(&msg.room_id);
     }
 }
@@ -1,46 +1,101 @@
 use super::websocket::{self, WebsocketSession};
-use super::room::Room;
+use super::room::{PlayerInfo, Room, RoomCommand, RoomSettings, TimeControl};
+use super::LobbyQuery;
+use crate::actors::bughouse::BughouseRoom;
 use actix::prelude::*;
 use uuid::Uuid;
-use std::time::Instant;
+use std::time::Duration;
 
 #[derive(Message)]
 #[rtype(result = "()")]
 pub struct Connect {
     pub id: Uuid,
-    pub session: Recipient<websocket::Send>,
+    pub session: Addr<WebsocketSession>,
 }
 
 #[derive(Message)]
 #[rtype(result = "()")]
 pub struct Join {
     pub id: Uuid,
+    pub username: Option<String>,
     pub room_id: String,
     pub session: Addr<WebsocketSession>,
 }
 
+/// A websocket session closed, leaving the room it joined
 #[derive(Message)]
 #[rtype(result = "()")]
 pub struct Disconnect {
     pub id: Uuid,
-    pub session: Recipient<websocket::Send>,
+    pub session: Addr<WebsocketSession>,
+    pub room: Option<RoomHandle>,
 }
 
 #[derive(Message)]
-#[rtype(result = "String")]
+#[rtype(result = "()")]
 pub struct Create {
     pub id: Uuid,
+    pub username: Option<String>,
+    pub settings: RoomSettings,
     pub session: Addr<WebsocketSession>,
 }
 
+/// Creates a room for two players paired by the matchmaker
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct CreatePaired {
+    pub white: PlayerInfo,
+    /// Rating of white, who is the creator of the room
+    pub white_rating: Option<i32>,
+    pub black: PlayerInfo,
+    pub time_control: Option<TimeControl>,
+    pub rated: bool,
+    pub white_session: Addr<WebsocketSession>,
+    pub black_session: Addr<WebsocketSession>,
+}
+
+/// Sends a page of the lobby to a session, which then gets the changes
+/// matching its query
 #[derive(Message)]
 #[rtype(result = "()")]
 pub struct List {
-    pub id: Uuid,
-    pub items: usize,
+    pub query: LobbyQuery,
     pub session: Recipient<websocket::Send>,
 }
 
+/// Sends a command to a room owned by another node
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Forward {
+    pub id: Uuid,
+    pub room_id: String,
+    pub node: String,
+    pub command: RoomCommand,
+}
+
+/// Hands over everything of the anonymous player `from` to an account
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct LinkAccount {
+    pub from: Uuid,
+    pub to: PlayerInfo,
+}
+
+/// Closes the rooms of this node left for longer than their grace period
+#[derive(Message, Clone, Copy)]
+#[rtype(result = "()")]
+pub struct Sweep {
+    pub waiting_grace: Duration,
+    pub abandoned_grace: Duration,
+}
+
+/// The game of a room started, the room leaves the lobby
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct RoomStarted {
+    pub room_id: String,
+}
+
 #[derive(Message)]
 #[rtype(result = "()")]
 pub struct RemoveRoom {
@@ -49,7 +104,35 @@ pub struct RemoveRoom {
 
 #[derive(Clone)]
 pub struct RoomData {
-    pub created_at: Instant,
-    pub addr: Addr<Room>,
+    pub addr: RoomAddr,
+}
+
+/// A room of this node, of either type
+#[derive(Clone)]
+pub enum RoomAddr {
+    Game(Addr<Room>),
+    Bughouse(Addr<BughouseRoom>),
 }
 
+impl RoomAddr {
+    /// Sends a message both types of rooms handle
+    pub fn do_send<M>(&self, msg: M)
+    where
+        M: Message + Send + 'static,
+        M::Result: Send,
+        Room: Handler<M>,
+        BughouseRoom: Handler<M>,
+    {
+        match self {
+            RoomAddr::Game(room) => room.do_send(msg),
+            RoomAddr::Bughouse(room) => room.do_send(msg),
+        }
+    }
+}
+
+/// Where the room joined by a session lives
+#[derive(Clone)]
+pub enum RoomHandle {
+    Local(RoomAddr),
+    Remote { room_id: String, node: String },
+}
@@ -0,0 +1,41 @@
+use super::room_manager::{self, RoomManager};
+use actix::prelude::*;
+use std::time::Duration;
+
+const SWEEP_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Periodically closes the rooms of this node nobody is in anymore
+pub struct Sweeper {
+    room_manager: Addr<RoomManager>,
+    /// How long a waiting room stays open once its creator left
+    waiting_grace: Duration,
+    /// How long a game goes on once both players left
+    abandoned_grace: Duration,
+}
+
+impl Sweeper {
+    pub fn new(
+        room_manager: Addr<RoomManager>,
+        waiting_grace: Duration,
+        abandoned_grace: Duration,
+    ) -> Self {
+        Self {
+            room_manager,
+            waiting_grace,
+            abandoned_grace,
+        }
+    }
+}
+
+impl Actor for Sweeper {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.run_interval(SWEEP_INTERVAL, |act, _ctx| {
+            act.room_manager.do_send(room_manager::Sweep {
+                waiting_grace: act.waiting_grace,
+                abandoned_grace: act.abandoned_grace,
+            });
+        });
+    }
+}
@@ -2,8 +2,9 @@ pub mod model;
 
 pub use model::{ClientMessage, ServerError, ServerMessage};
 
-use super::room::{self, Room};
-use super::room_manager;
+use super::matchmaker::{self, Matchmaker};
+use super::room::{PlayerInfo, RoomCommand, RoomSettings, Variant};
+use super::room_manager::{self, LobbyQuery, RoomHandle};
 
 use actix::prelude::*;
 use actix_web_actors::ws;
@@ -25,26 +26,40 @@ pub struct Send(pub ServerMessage);
 
 #[derive(Message)]
 #[rtype(result = "()")]
-pub struct JoinedRoom(pub Addr<Room>);
+pub struct JoinedRoom(pub RoomHandle);
+
+/// The player behind the session logged in
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Identify {
+    pub id: Uuid,
+    pub username: Option<String>,
+}
 
 pub struct WebsocketSession {
     pub id: Uuid,
+    pub username: Option<String>,
     pub hb: Instant,
     pub room_manager: Addr<room_manager::RoomManager>,
-    pub room: Option<Addr<Room>>,
+    pub matchmaker: Addr<Matchmaker>,
+    pub room: Option<RoomHandle>,
     pub connection: Connection,
 }
 
 impl WebsocketSession {
     pub fn new(
         id: Uuid,
+        username: Option<String>,
         connection: Connection,
         room_manager: Addr<room_manager::RoomManager>,
+        matchmaker: Addr<Matchmaker>,
     ) -> Self {
         Self {
             id,
+            username,
             connection,
             room_manager,
+            matchmaker,
             room: None,
             hb: Instant::now(),
         }
@@ -53,6 +68,21 @@ impl WebsocketSession {
     fn create_err(err: ServerError) -> String {
         serde_json::to_string(&ServerMessage::Err { what: err }).unwrap()
     }
+
+    fn send_room(&self, command: RoomCommand) {
+        match &self.room {
+            Some(RoomHandle::Local(room)) => command.send_to(room, self.id),
+            Some(RoomHandle::Remote { room_id, node }) => {
+                self.room_manager.do_send(room_manager::Forward {
+                    id: self.id,
+                    room_id: room_id.clone(),
+                    node: node.clone(),
+                    command,
+                })
+            }
+            None => (),
+        }
+    }
 }
 
 impl Actor for WebsocketSession {
@@ -63,8 +93,8 @@ impl Actor for WebsocketSession {
 
         self.room_manager
             .send(room_manager::Connect {
-                id: self.id.clone(),
-                session: ctx.address().recipient(),
+                id: self.id,
+                session: ctx.address(),
             })
             .into_actor(self)
             .then(|res, _act, ctx| {
@@ -79,24 +109,26 @@ impl Actor for WebsocketSession {
 
         match &self.connection {
             Connection::Play(room_id) => self.room_manager.do_send(room_manager::Join {
-                id: self.id.clone(),
+                id: self.id,
+                username: self.username.clone(),
                 room_id: room_id.clone(),
                 session: ctx.address(),
             }),
             Connection::Lobby => self.room_manager.do_send(room_manager::List {
-                id: self.id.clone(),
-                items: 12,
+                query: LobbyQuery::default(),
                 session: ctx.address().recipient(),
             }),
         }
     }
 
-    /*
-    fn stopping(&mut self, _: &mut Self::Context) -> Running {
-        self.addr.do_send(room_manager::Disconnect { id: self.id });
+    fn stopping(&mut self, ctx: &mut Self::Context) -> Running {
+        self.room_manager.do_send(room_manager::Disconnect {
+            id: self.id,
+            session: ctx.address(),
+            room: self.room.take(),
+        });
         Running::Stop
     }
-     */
 }
 
 impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WebsocketSession {
@@ -120,25 +152,87 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WebsocketSession
             }
             ws::Message::Text(text) => match serde_json::from_str::<ClientMessage>(&text) {
                 Ok(msg) => match &self.connection {
-                    Connection::Play(string) => match msg {
-                        ClientMessage::Move { uci, fen } => {
-                            if let Some(room) = &self.room {
-                                room.do_send(room::Move {
-                                    id: self.id,
-                                    uci
-                                })
-                            }
-                        }
+                    Connection::Play(_) => match msg {
+                        ClientMessage::Move { uci } => self.send_room(RoomCommand::Move { uci }),
+                        ClientMessage::Resign => self.send_room(RoomCommand::Resign),
+                        ClientMessage::OfferDraw => self.send_room(RoomCommand::OfferDraw),
+                        ClientMessage::AcceptDraw => self.send_room(RoomCommand::AcceptDraw),
+                        ClientMessage::DeclineDraw => self.send_room(RoomCommand::DeclineDraw),
+                        ClientMessage::Abort => self.send_room(RoomCommand::Abort),
+                        ClientMessage::ClaimVictory => self.send_room(RoomCommand::ClaimVictory),
+                        ClientMessage::ClaimDraw => self.send_room(RoomCommand::ClaimDraw),
                         _ => ctx.text(WebsocketSession::create_err(ServerError::OutOfContext)),
                     },
                     Connection::Lobby => match msg {
-                        ClientMessage::Create => {
+                        ClientMessage::Create {
+                            time_control,
+                            rated,
+                            color,
+                            variant,
+                        } => {
+                            if rated && self.username.is_none() {
+                                ctx.text(WebsocketSession::create_err(ServerError::LoginRequired));
+                                return;
+                            }
+
+                            // Ratings are kept per speed for standard chess,
+                            // games of other variants are casual
+                            if rated && variant != Variant::Standard {
+                                ctx.text(WebsocketSession::create_err(ServerError::InvalidInput));
+                                return;
+                            }
+
+                            if matches!(time_control, Some(tc) if !tc.is_valid()) {
+                                ctx.text(WebsocketSession::create_err(ServerError::InvalidInput));
+                                return;
+                            }
+
                             self.room_manager.do_send(room_manager::Create {
                                 id: self.id,
+                                username: self.username.clone(),
+                                settings: RoomSettings {
+                                    time_control,
+                                    rated,
+                                    color,
+                                    variant,
+                                },
                                 session: ctx.address(),
                             })
                         }
-                        ClientMessage::List(items) => {}
+                        ClientMessage::List {
+                            filter,
+                            page,
+                            items,
+                        } => self.room_manager.do_send(room_manager::List {
+                            query: LobbyQuery::new(filter, page, items),
+                            session: ctx.address().recipient(),
+                        }),
+                        ClientMessage::Seek {
+                            time_control,
+                            rated,
+                            rating_range,
+                            color,
+                        } => {
+                            if matches!(time_control, Some(tc) if !tc.is_valid()) {
+                                ctx.text(WebsocketSession::create_err(ServerError::InvalidInput));
+                                return;
+                            }
+
+                            self.matchmaker.do_send(matchmaker::Seek {
+                                player: PlayerInfo {
+                                    id: self.id,
+                                    username: self.username.clone(),
+                                },
+                                session: ctx.address(),
+                                time_control,
+                                rated,
+                                rating_range,
+                                color,
+                            })
+                        }
+                        ClientMessage::CancelSeek => {
+                            self.matchmaker.do_send(matchmaker::CancelSeek { id: self.id })
+                        }
                         _ => ctx.text(WebsocketSession::create_err(ServerError::OutOfContext)),
                     },
                 },
@@ -168,12 +262,7 @@ impl WebsocketSession {
                 // heartbeat timed out
                 println!("Websocket Client heartbeat failed, disconnecting!");
 
-                // notify chat room_manager
-                /*
-                act.addr.do_send(room_manager::Disconnect { id: act.id });
-                 */
-
-                // stop actor
+                // stop actor, the room manager is notified when stopping
                 ctx.stop();
 
                 // don't try to send a ping
@@ -199,7 +288,16 @@ impl Handler<Send> for WebsocketSession {
 impl Handler<JoinedRoom> for WebsocketSession {
     type Result = ();
 
-    fn handle(&mut self, msg: JoinedRoom, ctx: &mut Self::Context) -> Self::Result {
+    fn handle(&mut self, msg: JoinedRoom, _ctx: &mut Self::Context) -> Self::Result {
         self.room = Some(msg.0);
     }
 }
+
+impl Handler<Identify> for WebsocketSession {
+    type Result = ();
+
+    fn handle(&mut self, msg: Identify, _ctx: &mut Self::Context) -> Self::Result {
+        self.id = msg.id;
+        self.username = msg.username;
+    }
+}
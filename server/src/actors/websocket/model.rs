@@ -1,18 +1,60 @@
+use crate::actors::bughouse;
 use crate::actors::room;
+use crate::actors::room_manager::{LobbyFilter, LobbyListing};
+use crate::app::ratings::model::GameRatings;
+use crate::util::chess::{LegalMove, MoveError};
+use crate::util::variant::{GameError, Pockets};
 
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
 
 #[derive(Debug, Deserialize)]
 #[serde(tag = "type")]
 #[serde(rename_all = "snake_case")]
 pub enum ClientMessage {
-    Move { uci: String, fen: String },
-    Create,
-    List(usize),
+    Move { uci: String },
+    Create {
+        #[serde(default)]
+        time_control: Option<room::TimeControl>,
+        #[serde(default)]
+        rated: bool,
+        #[serde(default)]
+        color: room::ColorChoice,
+        #[serde(default)]
+        variant: room::Variant,
+    },
+    List {
+        #[serde(default)]
+        filter: LobbyFilter,
+        #[serde(default)]
+        page: usize,
+        #[serde(default = "default_items")]
+        items: usize,
+    },
+    Seek {
+        #[serde(default)]
+        time_control: Option<room::TimeControl>,
+        #[serde(default)]
+        rated: bool,
+        #[serde(default)]
+        rating_range: Option<u32>,
+        #[serde(default)]
+        color: room::ColorChoice,
+    },
+    CancelSeek,
+    Resign,
+    OfferDraw,
+    AcceptDraw,
+    DeclineDraw,
+    Abort,
+    ClaimVictory,
+    ClaimDraw,
 }
 
-#[derive(Debug, Serialize, Clone)]
+fn default_items() -> usize {
+    12
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(tag = "type")]
 #[serde(rename_all = "snake_case")]
 pub enum ServerMessage {
@@ -20,11 +62,17 @@ pub enum ServerMessage {
         uci: String,
         side: String,
         fen: String,
-        dests: Option<HashMap<String, String>>,
+        dests: Option<Vec<LegalMove>>,
         check: bool,
+        clock: Option<room::ClockState>,
+        /// Pieces each side can drop, in crazyhouse
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pockets: Option<Pockets>,
     },
     GameEnd {
         result: room::GameEndResult,
+        /// New ratings of the players, for rated games
+        ratings: Option<GameRatings>,
     },
     Err {
         what: ServerError,
@@ -32,27 +80,143 @@ pub enum ServerMessage {
     Create {
         room_id: String,
     },
+    /// The seek of the player was paired, the game waits in `room_id`
+    Paired {
+        room_id: String,
+        color: room::PlayerColor,
+    },
     Start {
         color: room::PlayerColor,
-        dests: Option<HashMap<String, String>>,
+        white: room::PublicPlayer,
+        black: room::PublicPlayer,
+        /// Start position of the game, in X-FEN
+        fen: String,
+        dests: Option<Vec<LegalMove>>,
+        clock: Option<room::ClockState>,
     },
     Reconnect {
         color: room::PlayerColor,
+        white: room::PublicPlayer,
+        black: room::PublicPlayer,
+        turn: room::PlayerColor,
+        fen: String,
+        dests: Option<Vec<LegalMove>>,
+        check: bool,
+        clock: Option<room::ClockState>,
+        draw_offer: Option<room::PlayerColor>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pockets: Option<Pockets>,
+    },
+    Spectate {
+        white: room::PublicPlayer,
+        black: room::PublicPlayer,
         turn: room::PlayerColor,
         fen: String,
-        dests: Option<HashMap<String, String>>,
+        moves: Vec<String>,
         check: bool,
+        clock: Option<room::ClockState>,
+        draw_offer: Option<room::PlayerColor>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pockets: Option<Pockets>,
+    },
+    DrawOffer {
+        color: room::PlayerColor,
+    },
+    DrawDeclined {
+        color: room::PlayerColor,
+    },
+    PlayerDisconnected {
+        color: room::PlayerColor,
+    },
+    PlayerReconnected {
+        color: room::PlayerColor,
+    },
+    /// The opponent has been gone for too long, the game can be claimed
+    /// as won or drawn
+    ClaimAvailable,
+    /// The position occurred three times or nothing happened for fifty
+    /// moves, either player can claim a draw
+    DrawClaimable,
+    /// Players seated so far in a bughouse room, by seat
+    BughouseSeats {
+        seats: Vec<Option<room::PublicPlayer>>,
+    },
+    /// Both boards of a bughouse game, sent when it starts and to the
+    /// sessions joining it, spectators have no seat
+    BughouseState {
+        seat: Option<bughouse::SeatInfo>,
+        /// Players by seat
+        players: Vec<room::PublicPlayer>,
+        boards: Vec<bughouse::BoardState>,
+        /// UCI moves of each board
+        moves: Vec<Vec<String>>,
+    },
+    /// A move on one board of a bughouse game, with both boards as a capture
+    /// fills a pocket of the other one
+    BughouseMove {
+        board: usize,
+        uci: String,
+        boards: Vec<bughouse::BoardState>,
     },
-    List{
-        rooms: Vec<String>,
+    /// The game on `board` ended, ending the other one with it
+    BughouseEnd {
+        board: usize,
+        /// Results of both boards
+        results: Vec<room::GameEndResult>,
+    },
+    /// A page of the lobby
+    List {
+        rooms: Vec<LobbyListing>,
+        page: usize,
+        /// Number of rooms matching the filter
+        total: usize,
+    },
+    /// A room showing up on the page of the lobby
+    RoomAdded {
+        room: LobbyListing,
+    },
+    RoomRemoved {
+        room_id: String,
     },
 }
 
-#[derive(Debug, Serialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(rename_all = "snake_case")]
 pub enum ServerError {
     InternalError,
     InvalidInput,
     IllegalMove,
+    /// A pawn reaches the last rank but the move doesn't say what it
+    /// promotes to
+    PromotionRequired,
+    NotYourTurn,
+    /// The game has already ended
+    GameOver,
+    NoDrawOffer,
+    AbortNotAllowed,
+    /// Nothing to claim: the opponent is still there, or hasn't been gone
+    /// for long enough, and the position isn't drawn
+    NoClaim,
     OutOfContext,
+    /// Rated games are reserved to registered players
+    LoginRequired,
+}
+
+impl From<MoveError> for ServerError {
+    fn from(error: MoveError) -> Self {
+        match error {
+            MoveError::Illegal => ServerError::IllegalMove,
+            MoveError::PromotionRequired => ServerError::PromotionRequired,
+        }
+    }
+}
+
+impl From<GameError> for ServerError {
+    fn from(error: GameError) -> Self {
+        match error {
+            GameError::GameOver => ServerError::GameOver,
+            GameError::NoDrawOffer => ServerError::NoDrawOffer,
+            GameError::AbortNotAllowed => ServerError::AbortNotAllowed,
+        }
+    }
 }
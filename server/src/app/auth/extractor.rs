@@ -0,0 +1,92 @@
+use super::model::AuthError;
+use crate::app::users::crypto::CryptoService;
+
+use actix_web::{
+    dev::Payload, http::header, http::StatusCode, web, FromRequest, HttpRequest, HttpResponse,
+    ResponseError,
+};
+use futures::future::{ready, Ready};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use uuid::Uuid;
+
+/// A user authenticated by the token of the request.
+///
+/// The token is read from the `Authorization: Bearer` header, or from the
+/// `token` query parameter since browsers can't set headers on websocket
+/// upgrades.
+#[derive(Debug, Clone, Serialize)]
+pub struct AuthUser {
+    pub id: Uuid,
+    pub username: String,
+}
+
+#[derive(Deserialize)]
+struct TokenQuery {
+    token: String,
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuthError::InvalidCredentials => write!(f, "invalid credentials"),
+            AuthError::MissingToken => write!(f, "missing token"),
+            AuthError::InvalidToken => write!(f, "invalid token"),
+            AuthError::InternalError => write!(f, "internal error"),
+        }
+    }
+}
+
+impl ResponseError for AuthError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AuthError::InternalError => StatusCode::INTERNAL_SERVER_ERROR,
+            _ => StatusCode::UNAUTHORIZED,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(self)
+    }
+}
+
+fn token(req: &HttpRequest) -> Option<String> {
+    let bearer = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+
+    match bearer {
+        Some(token) => Some(token.trim().to_string()),
+        None => web::Query::<TokenQuery>::from_query(req.query_string())
+            .ok()
+            .map(|query| query.into_inner().token),
+    }
+}
+
+impl FromRequest for AuthUser {
+    type Error = AuthError;
+    type Future = Ready<Result<Self, Self::Error>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let crypto = match req.app_data::<web::Data<CryptoService>>() {
+            Some(crypto) => crypto,
+            None => return ready(Err(AuthError::InternalError)),
+        };
+
+        let result = match token(req) {
+            Some(token) => crypto
+                .verify_jwt(&token)
+                .map(|claims| AuthUser {
+                    id: claims.sub,
+                    username: claims.username,
+                })
+                .map_err(|_| AuthError::InvalidToken),
+            None => Err(AuthError::MissingToken),
+        };
+
+        ready(result)
+    }
+}
@@ -1,22 +1,57 @@
-use super::model::Auth;
+use super::extractor::AuthUser;
+use super::link::link_anonymous;
+use super::model::{Auth, AuthError, Token};
+use crate::actors::room_manager::RoomManager;
+use crate::app::users::crypto::CryptoService;
 
-use actix_web::{post, web, web::ServiceConfig, HttpResponse, Responder};
+use actix::prelude::*;
+use actix_session::Session;
+use actix_web::{get, post, web, web::ServiceConfig, HttpResponse, Responder};
+use log::error;
 use sqlx::PgPool;
-use uuid::Uuid;
 use validator::Validate;
 
-// TODO: Proper error handling
-
 pub fn config(config: &mut ServiceConfig) {
-    config.service(login);
+    config.service(login).service(current_user);
 }
 
 #[post("/")]
-pub async fn login(data: web::Json<Auth>, db_pool: web::Data<PgPool>) -> impl Responder {
+pub async fn login(
+    data: web::Json<Auth>,
+    db_pool: web::Data<PgPool>,
+    crypto: web::Data<CryptoService>,
+    room_manager: web::Data<Addr<RoomManager>>,
+    session: Session,
+) -> impl Responder {
     match data.validate() {
         Ok(_) => (),
         Err(e) => return HttpResponse::BadRequest().body(format!("{}", e)),
     };
 
-    HttpResponse::Ok().body("aaabbbccc")
+    let user = match data.validate_creds(&db_pool, &crypto).await {
+        Ok(Some(user)) => user,
+        Ok(None) => return HttpResponse::Unauthorized().json(AuthError::InvalidCredentials),
+        Err(e) => {
+            error!("Could not check credentials: {:?}", e);
+            return HttpResponse::InternalServerError().json(AuthError::InternalError);
+        }
+    };
+
+    if let Err(e) = link_anonymous(&session, &db_pool, &room_manager, &user).await {
+        error!("Could not link anonymous player to {}: {:?}", user.username, e);
+    }
+
+    match crypto.generate_jwt(user.id, user.username) {
+        Ok(token) => HttpResponse::Ok().json(Token { token }),
+        Err(e) => {
+            error!("Could not generate token: {:?}", e);
+            HttpResponse::InternalServerError().json(AuthError::InternalError)
+        }
+    }
+}
+
+/// The user the token belongs to
+#[get("/")]
+pub async fn current_user(user: AuthUser) -> impl Responder {
+    HttpResponse::Ok().json(user)
 }
@@ -0,0 +1,70 @@
+use crate::actors::room::PlayerInfo;
+use crate::actors::room_manager::{LinkAccount, RoomManager};
+use crate::app::games::model::Game;
+use crate::app::users::model::User;
+
+use actix::prelude::*;
+use actix_session::Session;
+use color_eyre::Result;
+use log::info;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// Session key of the secret anonymous players are known by
+const ANONYMOUS_SECRET: &str = "rc-secret";
+/// Namespace the ids of anonymous players are derived in
+const ANONYMOUS_NAMESPACE: Uuid = Uuid::from_u128(0x6a1f_0c3e_94b2_4d58_8e07_53c9_d2a6_71fb);
+
+/// Id of the anonymous player of this session, if it has one.
+///
+/// Sessions hold a secret the id is derived from rather than the id itself,
+/// as ids are stored with games: knowing one is no proof of being its player.
+pub fn anonymous_id(session: &Session) -> Result<Option<Uuid>, actix_web::Error> {
+    Ok(session.get::<Uuid>(ANONYMOUS_SECRET)?.map(derive_id))
+}
+
+/// Id of the anonymous player of this session, a new player is made for
+/// sessions without one
+pub fn anonymous_id_or_new(session: &Session) -> Result<Uuid, actix_web::Error> {
+    if let Some(id) = anonymous_id(session)? {
+        return Ok(id);
+    }
+
+    let secret = Uuid::new_v4();
+    session.insert(ANONYMOUS_SECRET, secret)?;
+
+    Ok(derive_id(secret))
+}
+
+fn derive_id(secret: Uuid) -> Uuid {
+    Uuid::new_v5(&ANONYMOUS_NAMESPACE, secret.as_bytes())
+}
+
+/// Hands the games and rooms of the anonymous player of this session over
+/// to `user`, the anonymous id is forgotten afterwards
+pub async fn link_anonymous(
+    session: &Session,
+    db_pool: &PgPool,
+    room_manager: &Addr<RoomManager>,
+    user: &User,
+) -> Result<()> {
+    let anonymous = match anonymous_id(session) {
+        Ok(Some(id)) if id != user.id => id,
+        _ => return Ok(()),
+    };
+
+    let games = Game::transfer_player(db_pool, anonymous, user.id, &user.username).await?;
+    info!("Linked {} to {}, {} games moved", anonymous, user.username, games);
+
+    room_manager.do_send(LinkAccount {
+        from: anonymous,
+        to: PlayerInfo {
+            id: user.id,
+            username: Some(user.username.clone()),
+        },
+    });
+
+    session.remove(ANONYMOUS_SECRET);
+
+    Ok(())
+}
@@ -1,4 +1,7 @@
+pub mod extractor;
 pub mod handlers;
+pub mod link;
 pub mod model;
 
+pub use extractor::AuthUser;
 pub use handlers::config;
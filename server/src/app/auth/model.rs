@@ -1,6 +1,10 @@
+use crate::app::users::crypto::{CryptoService, Verification};
+use crate::app::users::model::User;
+
 use color_eyre::Result;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use sqlx::PgPool;
+use uuid::Uuid;
 use validator_derive::Validate;
 
 #[derive(Debug, Deserialize, Validate)]
@@ -11,10 +15,57 @@ pub struct Auth {
     pub password: String,
 }
 
-/*
-impl AuthData {
-    pub async fn validate_creds(pool: &PgPool, data: AuthData) -> Result<bool> {
+/// Content of the tokens handed out on login
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Claims {
+    pub sub: Uuid,
+    pub username: String,
+    /// Expiration, as a unix timestamp
+    pub exp: i64,
+}
+
+#[derive(Serialize)]
+pub struct Token {
+    pub token: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum AuthError {
+    InvalidCredentials,
+    MissingToken,
+    InvalidToken,
+    InternalError,
+}
+
+impl Auth {
+    /// Returns the user matching these credentials, if any.
+    ///
+    /// Passwords hashed with outdated parameters are rehashed on the way.
+    pub async fn validate_creds(
+        &self,
+        pool: &PgPool,
+        crypto: &CryptoService,
+    ) -> Result<Option<User>> {
+        let user = match User::find_by_username(pool, &self.username).await? {
+            Some(user) => user,
+            None => {
+                crypto.verify_dummy(self.password.clone()).await?;
+                return Ok(None);
+            }
+        };
 
+        match crypto
+            .verify_password(self.password.clone(), user.password_hash.clone())
+            .await?
+        {
+            Verification::Invalid => Ok(None),
+            Verification::Valid => Ok(Some(user)),
+            Verification::Rehashed(hash) => {
+                User::update_password_hash(pool, user.id, &hash).await?;
+                Ok(Some(user))
+            }
+        }
     }
 }
-*/
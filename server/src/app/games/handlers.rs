@@ -0,0 +1,154 @@
+use super::model::{Game, ImportedGame, Pagination};
+use super::pgn::{parse_pgn, write_pgn};
+use crate::app::auth::AuthUser;
+use crate::util::chess::get_san_moves;
+use crate::util::variant::ChessGame;
+
+use actix_web::{error, get, post, web, web::Bytes, web::ServiceConfig, HttpResponse, Responder};
+use futures::stream;
+use sqlx::PgPool;
+use uuid::Uuid;
+use validator::Validate;
+
+// TODO: Proper error handling
+
+const PGN_CONTENT_TYPE: &str = "application/x-chess-pgn";
+
+/// Number of games fetched at once when exporting every game of a user
+const EXPORT_BATCH_SIZE: i64 = 50;
+
+pub fn config(config: &mut ServiceConfig) {
+    config
+        .service(import_game)
+        .service(get_user_games_pgn)
+        .service(get_user_games)
+        .service(get_game_pgn)
+        .service(get_game);
+}
+
+#[get("/{id}")]
+pub async fn get_game(
+    id: web::Path<Uuid>,
+    _user: AuthUser,
+    db_pool: web::Data<PgPool>,
+) -> impl Responder {
+    match Game::find_by_id(&db_pool, *id).await {
+        Ok(Some(data)) => HttpResponse::Ok().json(data),
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(e) => HttpResponse::BadRequest().body(format!("got error {:?}", e)),
+    }
+}
+
+#[get("/{id}.pgn")]
+pub async fn get_game_pgn(
+    id: web::Path<Uuid>,
+    _user: AuthUser,
+    db_pool: web::Data<PgPool>,
+) -> impl Responder {
+    match Game::find_by_id(&db_pool, *id).await {
+        Ok(Some(data)) => HttpResponse::Ok()
+            .content_type(PGN_CONTENT_TYPE)
+            .body(write_pgn(&data)),
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(e) => HttpResponse::BadRequest().body(format!("got error {:?}", e)),
+    }
+}
+
+#[get("/user/{id}")]
+pub async fn get_user_games(
+    id: web::Path<Uuid>,
+    pagination: web::Query<Pagination>,
+    _user: AuthUser,
+    db_pool: web::Data<PgPool>,
+) -> impl Responder {
+    match pagination.validate() {
+        Ok(_) => (),
+        Err(e) => return HttpResponse::BadRequest().body(format!("{}", e)),
+    };
+
+    match Game::find_by_player(&db_pool, *id, &pagination).await {
+        Ok(data) => HttpResponse::Ok().json(data),
+        Err(e) => HttpResponse::BadRequest().body(format!("got error {:?}", e)),
+    }
+}
+
+/// Streams every game of a user in PGN, fetching them in batches
+#[get("/user/{id}.pgn")]
+pub async fn get_user_games_pgn(
+    id: web::Path<Uuid>,
+    _user: AuthUser,
+    db_pool: web::Data<PgPool>,
+) -> impl Responder {
+    let player_id = *id;
+    let db_pool = db_pool.get_ref().clone();
+
+    let games = stream::unfold(Some(0), move |offset| {
+        let db_pool = db_pool.clone();
+
+        async move {
+            let offset = offset?;
+
+            match Game::find_range_by_player(&db_pool, player_id, EXPORT_BATCH_SIZE, offset).await {
+                Ok(games) if games.is_empty() => None,
+                Ok(games) => {
+                    let next = if (games.len() as i64) < EXPORT_BATCH_SIZE {
+                        None
+                    } else {
+                        Some(offset + EXPORT_BATCH_SIZE)
+                    };
+                    let pgn: String = games.iter().map(write_pgn).collect();
+
+                    Some((Ok(Bytes::from(pgn)), next))
+                }
+                Err(e) => Some((Err(error::ErrorInternalServerError(e)), None)),
+            }
+        }
+    });
+
+    HttpResponse::Ok()
+        .content_type(PGN_CONTENT_TYPE)
+        .streaming(Box::pin(games))
+}
+
+/// Imports a game from a PGN sent as the request body
+#[post("/import")]
+pub async fn import_game(
+    body: String,
+    _user: AuthUser,
+    db_pool: web::Data<PgPool>,
+) -> impl Responder {
+    let parsed = match parse_pgn(&body) {
+        Ok(parsed) => parsed,
+        Err(e) => return HttpResponse::BadRequest().json(e),
+    };
+
+    let mut game = ChessGame::new(parsed.initial.clone());
+    for (ply, chess_move) in parsed.moves.iter().enumerate() {
+        if !game.make_move(*chess_move) {
+            return HttpResponse::BadRequest().json(parsed.illegal_move(ply));
+        }
+    }
+
+    let termination = game.current_position().result();
+
+    let imported_game = ImportedGame {
+        white_name: parsed.tag("White").map(String::from),
+        black_name: parsed.tag("Black").map(String::from),
+        variant: parsed.variant,
+        initial_fen: parsed.initial.fen(),
+        moves_uci: game.uci_moves(),
+        moves_san: get_san_moves(parsed.initial.as_ref(), &parsed.moves),
+        pockets: game
+            .current_position()
+            .pockets()
+            .map(|pockets| pockets.to_string()),
+        result: parsed.result,
+        termination,
+        pgn: body,
+    };
+
+    match Game::import(&db_pool, imported_game).await {
+        Ok(data) => HttpResponse::Ok().json(data),
+        Err(e) => HttpResponse::BadRequest().body(format!("{}", e)),
+    }
+}
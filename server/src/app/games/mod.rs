@@ -0,0 +1,5 @@
+pub mod handlers;
+pub mod model;
+pub mod pgn;
+
+pub use handlers::config;
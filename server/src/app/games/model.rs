@@ -0,0 +1,237 @@
+use crate::actors::room::{GameEndResult, TimeControl, Variant};
+
+use chrono::NaiveDateTime;
+use color_eyre::Result;
+use serde::{Deserialize, Serialize};
+use sqlx::types::Json;
+use sqlx::{Executor, PgPool, Postgres};
+use uuid::Uuid;
+use validator_derive::Validate;
+
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct Game {
+    pub id: Uuid,
+    pub white_id: Option<Uuid>,
+    pub black_id: Option<Uuid>,
+    pub variant: Variant,
+    /// Start position, in X-FEN
+    pub initial_fen: String,
+    pub moves_uci: Vec<String>,
+    pub moves_san: Vec<String>,
+    /// Pieces each side could drop at the end of crazyhouse games, white
+    /// ones in uppercase
+    pub pockets: Option<String>,
+    pub result: String,
+    pub termination: Option<GameEndResult>,
+    pub time_control: Option<Json<TimeControl>>,
+    /// Time left to the mover after each move, in milliseconds
+    pub clocks: Vec<i64>,
+    pub started_at: NaiveDateTime,
+    pub ended_at: NaiveDateTime,
+    pub imported: bool,
+    pub white_name: Option<String>,
+    pub black_name: Option<String>,
+    /// Original PGN of imported games
+    pub pgn: Option<String>,
+    pub rated: bool,
+    /// Ratings of the players when the game started, for rated games
+    pub white_rating: Option<i32>,
+    pub black_rating: Option<i32>,
+}
+
+#[derive(Debug)]
+pub struct NewGame {
+    pub white_id: Uuid,
+    pub black_id: Uuid,
+    pub white_name: Option<String>,
+    pub black_name: Option<String>,
+    pub variant: Variant,
+    pub initial_fen: String,
+    pub moves_uci: Vec<String>,
+    pub moves_san: Vec<String>,
+    pub pockets: Option<String>,
+    pub termination: GameEndResult,
+    pub time_control: Option<TimeControl>,
+    pub clocks: Vec<i64>,
+    pub started_at: NaiveDateTime,
+    pub rated: bool,
+    pub white_rating: Option<i32>,
+    pub black_rating: Option<i32>,
+}
+
+#[derive(Debug)]
+pub struct ImportedGame {
+    pub white_name: Option<String>,
+    pub black_name: Option<String>,
+    pub variant: Variant,
+    pub initial_fen: String,
+    pub moves_uci: Vec<String>,
+    pub moves_san: Vec<String>,
+    pub pockets: Option<String>,
+    pub result: String,
+    pub termination: Option<GameEndResult>,
+    pub pgn: String,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct Pagination {
+    /// Bounded so that the offset of the page can't overflow
+    #[serde(default)]
+    #[validate(range(min = 0, max = 1_000_000))]
+    pub page: i64,
+    #[serde(default = "Pagination::default_per_page")]
+    #[validate(range(min = 1, max = 100))]
+    pub per_page: i64,
+}
+
+impl Pagination {
+    fn default_per_page() -> i64 {
+        20
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct GamePage {
+    pub games: Vec<Game>,
+    pub page: i64,
+    pub per_page: i64,
+    pub total: i64,
+}
+
+impl Game {
+    pub async fn create<'e, E>(executor: E, new_game: NewGame) -> Result<Game>
+    where
+        E: Executor<'e, Database = Postgres>,
+    {
+        let game = sqlx::query_as(
+            "insert into games (white_id, black_id, white_name, black_name, initial_fen, moves_uci, moves_san, result, termination, time_control, clocks, started_at, rated, white_rating, black_rating, variant, pockets) \
+             values ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17) returning *",
+        )
+        .bind(new_game.white_id)
+        .bind(new_game.black_id)
+        .bind(new_game.white_name)
+        .bind(new_game.black_name)
+        .bind(new_game.initial_fen)
+        .bind(new_game.moves_uci)
+        .bind(new_game.moves_san)
+        .bind(new_game.termination.score())
+        .bind(new_game.termination)
+        .bind(new_game.time_control.map(Json))
+        .bind(new_game.clocks)
+        .bind(new_game.started_at)
+        .bind(new_game.rated)
+        .bind(new_game.white_rating)
+        .bind(new_game.black_rating)
+        .bind(new_game.variant)
+        .bind(new_game.pockets)
+        .fetch_one(executor)
+        .await?;
+
+        Ok(game)
+    }
+
+    pub async fn import(pool: &PgPool, imported_game: ImportedGame) -> Result<Game> {
+        let game = sqlx::query_as(
+            "insert into games (white_name, black_name, initial_fen, moves_uci, moves_san, result, termination, pgn, variant, pockets, imported, started_at, ended_at) \
+             values ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, true, current_timestamp, current_timestamp) returning *",
+        )
+        .bind(imported_game.white_name)
+        .bind(imported_game.black_name)
+        .bind(imported_game.initial_fen)
+        .bind(imported_game.moves_uci)
+        .bind(imported_game.moves_san)
+        .bind(imported_game.result)
+        .bind(imported_game.termination)
+        .bind(imported_game.pgn)
+        .bind(imported_game.variant)
+        .bind(imported_game.pockets)
+        .fetch_one(pool)
+        .await?;
+
+        Ok(game)
+    }
+
+    /// Gives the games of the anonymous player `from` to the account `to`
+    pub async fn transfer_player(
+        pool: &PgPool,
+        from: Uuid,
+        to: Uuid,
+        username: &str,
+    ) -> Result<u64> {
+        let white = sqlx::query(
+            "update games set white_id = $2, white_name = coalesce(white_name, $3) where white_id = $1",
+        )
+        .bind(from)
+        .bind(to)
+        .bind(username)
+        .execute(pool)
+        .await?;
+
+        let black = sqlx::query(
+            "update games set black_id = $2, black_name = coalesce(black_name, $3) where black_id = $1",
+        )
+        .bind(from)
+        .bind(to)
+        .bind(username)
+        .execute(pool)
+        .await?;
+
+        Ok(white.rows_affected() + black.rows_affected())
+    }
+
+    pub async fn find_by_id(pool: &PgPool, id: Uuid) -> Result<Option<Game>> {
+        let game = sqlx::query_as("select * from games where id = $1")
+            .bind(id)
+            .fetch_optional(pool)
+            .await?;
+
+        Ok(game)
+    }
+
+    pub async fn find_by_player(
+        pool: &PgPool,
+        player_id: Uuid,
+        pagination: &Pagination,
+    ) -> Result<GamePage> {
+        let games = Game::find_range_by_player(
+            pool,
+            player_id,
+            pagination.per_page,
+            pagination.page * pagination.per_page,
+        )
+        .await?;
+
+        let (total,): (i64,) =
+            sqlx::query_as("select count(*) from games where white_id = $1 or black_id = $1")
+                .bind(player_id)
+                .fetch_one(pool)
+                .await?;
+
+        Ok(GamePage {
+            games,
+            page: pagination.page,
+            per_page: pagination.per_page,
+            total,
+        })
+    }
+
+    /// Games of a player, most recent first
+    pub async fn find_range_by_player(
+        pool: &PgPool,
+        player_id: Uuid,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<Game>> {
+        let games = sqlx::query_as(
+            "select * from games where white_id = $1 or black_id = $1 \
+             order by ended_at desc, id limit $2 offset $3",
+        )
+        .bind(player_id)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(games)
+    }
+}
@@ -0,0 +1,880 @@
+use super::model::Game;
+use crate::actors::room::{GameEndResult, Variant};
+use crate::util::chess::{get_san, parse_move};
+use crate::util::position::STANDARD_POSITION;
+use crate::util::variant;
+
+use chess::ChessMove;
+use serde::Serialize;
+use std::mem;
+use uuid::Uuid;
+
+/// Maximum length of a movetext line, as recommended by the PGN standard
+const LINE_LENGTH: usize = 80;
+
+/// Game results a PGN can give
+const RESULTS: [&str; 4] = ["1-0", "0-1", "1/2-1/2", "*"];
+
+/// Exports a stored game in PGN, replaying its moves to write them in SAN.
+///
+/// Imported games are exported as they were imported, keeping their
+/// comments and variations.
+pub fn write_pgn(game: &Game) -> String {
+    if let Some(pgn) = &game.pgn {
+        return format!("{}\n\n", pgn.trim_end());
+    }
+
+    let start = variant::initial_position(game.variant, STANDARD_POSITION);
+    let initial =
+        variant::from_fen(game.variant, &game.initial_fen).unwrap_or_else(|| start.clone());
+    let mut position = initial.clone();
+
+    let event = if game.rated {
+        "Rated game"
+    } else {
+        "Casual game"
+    };
+
+    let mut tags = vec![
+        ("Event", event.to_string()),
+        ("Site", "Rechess".to_string()),
+        ("Date", game.started_at.format("%Y.%m.%d").to_string()),
+        ("Round", "-".to_string()),
+        ("White", player_name(&game.white_name, &game.white_id)),
+        ("Black", player_name(&game.black_name, &game.black_id)),
+        ("Result", game.result.clone()),
+        ("WhiteElo", elo(game.white_rating)),
+        ("BlackElo", elo(game.black_rating)),
+    ];
+
+    // The tag has no way to give a delay, it is left out rather than wrong
+    match &game.time_control {
+        Some(time_control) if time_control.delay.is_some() => (),
+        Some(time_control) => tags.push((
+            "TimeControl",
+            format!("{}+{}", time_control.base, time_control.increment),
+        )),
+        None => tags.push(("TimeControl", "-".to_string())),
+    }
+
+    if let Some(result) = &game.termination {
+        tags.push(("Termination", termination(result).to_string()));
+    }
+
+    if let Some(name) = variant_name(game.variant) {
+        tags.push(("Variant", name.to_string()));
+    }
+
+    // Chess960 games always give their start position
+    if game.variant == Variant::Chess960 || initial.fen() != start.fen() {
+        tags.push(("SetUp", "1".to_string()));
+        tags.push(("FEN", game.initial_fen.clone()));
+    }
+
+    let mut pgn = String::new();
+
+    for (name, value) in tags {
+        pgn.push_str(&format!("[{} \"{}\"]\n", name, escape(&value)));
+    }
+
+    pgn.push('\n');
+
+    let mut tokens = Vec::new();
+    let first_ply = match initial.side_to_move() {
+        chess::Color::White => 0,
+        chess::Color::Black => 1,
+    };
+
+    for (index, uci) in game.moves_uci.iter().enumerate() {
+        let san = if game.variant == Variant::Bughouse {
+            // Drops of a bughouse board use pieces captured on the other
+            // board, its moves can't be replayed on their own
+            match game.moves_san.get(index) {
+                Some(san) => san.clone(),
+                None => break,
+            }
+        } else {
+            match parse_move(position.as_ref(), uci)
+                .ok()
+                .and_then(|chess_move| Some((chess_move, position.play(chess_move)?)))
+            {
+                Some((chess_move, next)) => {
+                    let san = get_san(position.as_ref(), chess_move);
+                    position = next;
+                    san
+                }
+                // The stored moves have been validated by the room
+                None => break,
+            }
+        };
+
+        let ply = index + first_ply;
+        if ply % 2 == 0 {
+            tokens.push(format!("{}.", ply / 2 + 1));
+        } else if index == 0 {
+            tokens.push(format!("{}...", ply / 2 + 1));
+        }
+
+        tokens.push(san);
+
+        if let Some(left) = game.clocks.get(index) {
+            tokens.push(format!("{{ [%clk {}] }}", format_clock(*left)));
+        }
+    }
+
+    tokens.push(game.result.clone());
+
+    let mut line_length = 0;
+    for token in tokens {
+        if line_length > 0 && line_length + token.len() + 1 > LINE_LENGTH {
+            pgn.push('\n');
+            line_length = 0;
+        } else if line_length > 0 {
+            pgn.push(' ');
+            line_length += 1;
+        }
+
+        line_length += token.len();
+        pgn.push_str(&token);
+    }
+
+    pgn.push_str("\n\n");
+
+    pgn
+}
+
+/// Name of `variant` in the Variant tag, standard games have none
+fn variant_name(variant: Variant) -> Option<&'static str> {
+    match variant {
+        Variant::Standard => None,
+        Variant::Chess960 => Some("Chess960"),
+        Variant::KingOfTheHill => Some("King of the Hill"),
+        Variant::ThreeCheck => Some("Three-check"),
+        Variant::Crazyhouse => Some("Crazyhouse"),
+        Variant::Bughouse => Some("Bughouse"),
+    }
+}
+
+/// Reads a Variant tag, ignoring case, spaces and dashes
+fn read_variant(name: &str) -> Option<Variant> {
+    let name: String = name
+        .chars()
+        .filter(|c| c.is_alphanumeric())
+        .collect::<String>()
+        .to_lowercase();
+
+    match name.as_str() {
+        "standard" | "normal" => Some(Variant::Standard),
+        "chess960" | "fischerandom" => Some(Variant::Chess960),
+        "kingofthehill" => Some(Variant::KingOfTheHill),
+        "threecheck" | "3check" => Some(Variant::ThreeCheck),
+        "crazyhouse" => Some(Variant::Crazyhouse),
+        _ => None,
+    }
+}
+
+fn player_name(name: &Option<String>, id: &Option<Uuid>) -> String {
+    match (name, id) {
+        (Some(name), _) => name.clone(),
+        (None, Some(id)) => id.to_string(),
+        (None, None) => "?".to_string(),
+    }
+}
+
+fn elo(rating: Option<i32>) -> String {
+    match rating {
+        Some(rating) => rating.to_string(),
+        None => "?".to_string(),
+    }
+}
+
+fn termination(result: &GameEndResult) -> &'static str {
+    match result {
+        GameEndResult::WhiteOutOfTime | GameEndResult::BlackOutOfTime => "Time forfeit",
+        GameEndResult::WhiteAbandons
+        | GameEndResult::BlackAbandons
+        | GameEndResult::AbandonedDraw => "Abandoned",
+        GameEndResult::Aborted => "Unterminated",
+        _ => "Normal",
+    }
+}
+
+/// Formats milliseconds as `h:mm:ss`
+fn format_clock(millis: i64) -> String {
+    let seconds = millis.max(0) / 1000;
+
+    format!("{}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60)
+}
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Error found while reading a PGN, positions are 1-based
+#[derive(Debug, Serialize)]
+pub struct PgnError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+/// A mainline move as written in the PGN
+#[derive(Debug)]
+pub struct MoveToken {
+    pub line: usize,
+    pub column: usize,
+    pub san: String,
+}
+
+/// Game read from a PGN, only the mainline moves are kept
+#[derive(Debug)]
+pub struct ParsedPgn {
+    pub tags: Vec<(String, String)>,
+    pub variant: Variant,
+    pub initial: Box<dyn variant::Variant>,
+    pub moves: Vec<variant::Move>,
+    /// Where each of `moves` is written
+    pub tokens: Vec<MoveToken>,
+    pub result: String,
+}
+
+impl ParsedPgn {
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|(tag, _)| tag == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Error pointing at the mainline move of index `ply`
+    pub fn illegal_move(&self, ply: usize) -> PgnError {
+        match self.tokens.get(ply) {
+            Some(token) => PgnError {
+                line: token.line,
+                column: token.column,
+                message: format!("illegal move {}", token.san),
+            },
+            None => PgnError {
+                line: 1,
+                column: 1,
+                message: format!("illegal move at ply {}", ply + 1),
+            },
+        }
+    }
+}
+
+/// Position of a line of play, variations start from `before_last`
+struct Line {
+    position: Box<dyn variant::Variant>,
+    before_last: Option<Box<dyn variant::Variant>>,
+}
+
+struct Reader {
+    chars: Vec<char>,
+    index: usize,
+    line: usize,
+    column: usize,
+}
+
+impl Reader {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.index).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.index += 1;
+
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+
+        Some(c)
+    }
+
+    fn position(&self) -> (usize, usize) {
+        (self.line, self.column)
+    }
+
+    fn error(&self, (line, column): (usize, usize), message: String) -> PgnError {
+        PgnError {
+            line,
+            column,
+            message,
+        }
+    }
+
+    /// Skips whitespace and `%` escaped lines
+    fn skip_whitespace(&mut self) {
+        while let Some(c) = self.peek() {
+            if c == '%' && self.column == 1 {
+                self.skip_line();
+            } else if c.is_whitespace() {
+                self.next();
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn skip_line(&mut self) {
+        while let Some(c) = self.next() {
+            if c == '\n' {
+                break;
+            }
+        }
+    }
+
+    fn read_tag(&mut self) -> Result<(String, String), PgnError> {
+        let start = self.position();
+        self.next();
+        self.skip_whitespace();
+
+        let mut name = String::new();
+        while let Some(c) = self.peek() {
+            if c.is_alphanumeric() || c == '_' {
+                name.push(c);
+                self.next();
+            } else {
+                break;
+            }
+        }
+
+        if name.is_empty() {
+            return Err(self.error(self.position(), "expected a tag name".to_string()));
+        }
+
+        self.skip_whitespace();
+        if self.peek() != Some('"') {
+            return Err(self.error(self.position(), format!("expected a value for tag {}", name)));
+        }
+        self.next();
+
+        let mut value = String::new();
+        loop {
+            match self.next() {
+                Some('\\') => match self.next() {
+                    Some(c) => value.push(c),
+                    None => break,
+                },
+                Some('"') => {
+                    self.skip_whitespace();
+                    return match self.next() {
+                        Some(']') => Ok((name, value)),
+                        _ => Err(self.error(start, format!("unterminated tag {}", name))),
+                    };
+                }
+                Some('\n') | None => break,
+                Some(c) => value.push(c),
+            }
+        }
+
+        Err(self.error(start, format!("unterminated value for tag {}", name)))
+    }
+
+    fn read_symbol(&mut self) -> String {
+        let mut symbol = String::new();
+
+        while let Some(c) = self.peek() {
+            if c.is_whitespace() || "{}();[]$".contains(c) {
+                break;
+            }
+            symbol.push(c);
+            self.next();
+        }
+
+        symbol
+    }
+}
+
+/// Reads a single game, validating the moves of the mainline and of every
+/// variation
+pub fn parse_pgn(text: &str) -> Result<ParsedPgn, PgnError> {
+    let mut reader = Reader {
+        chars: text.chars().collect(),
+        index: 0,
+        line: 1,
+        column: 1,
+    };
+
+    let mut tags = Vec::new();
+
+    reader.skip_whitespace();
+    while reader.peek() == Some('[') {
+        tags.push(reader.read_tag()?);
+        reader.skip_whitespace();
+    }
+
+    let variant = match tags.iter().find(|(name, _)| name == "Variant") {
+        Some((_, name)) => read_variant(name)
+            .ok_or_else(|| reader.error((1, 1), format!("unsupported variant \"{}\"", name)))?,
+        None => Variant::Standard,
+    };
+    let initial = match tags.iter().find(|(name, _)| name == "FEN") {
+        Some((_, fen)) => variant::from_fen(variant, fen)
+            .ok_or_else(|| reader.error((1, 1), format!("invalid FEN tag \"{}\"", fen)))?,
+        None => variant::initial_position(variant, STANDARD_POSITION),
+    };
+
+    let tagged_result = match tags.iter().find(|(name, _)| name == "Result") {
+        Some((_, value)) if RESULTS.contains(&value.as_str()) => Some(value.clone()),
+        Some((_, value)) => {
+            return Err(reader.error((1, 1), format!("invalid Result tag \"{}\"", value)))
+        }
+        None => None,
+    };
+
+    let mut moves = Vec::new();
+    let mut tokens = Vec::new();
+    let mut lines = vec![Line {
+        position: initial.clone(),
+        before_last: None,
+    }];
+    let mut result = None;
+
+    loop {
+        reader.skip_whitespace();
+        let start = reader.position();
+
+        let c = match reader.peek() {
+            Some(c) => c,
+            None => break,
+        };
+
+        match c {
+            '{' => {
+                reader.next();
+                loop {
+                    match reader.next() {
+                        Some('}') => break,
+                        Some(_) => (),
+                        None => return Err(reader.error(start, "unterminated comment".to_string())),
+                    }
+                }
+            }
+            ';' => reader.skip_line(),
+            '$' => {
+                reader.next();
+                let nag = reader.read_symbol();
+                if nag.is_empty() || !nag.chars().all(|c| c.is_ascii_digit()) {
+                    return Err(reader.error(start, format!("invalid NAG ${}", nag)));
+                }
+            }
+            '(' => {
+                reader.next();
+                let position = match lines.last().and_then(|line| line.before_last.clone()) {
+                    Some(position) => position,
+                    None => {
+                        return Err(
+                            reader.error(start, "variation without a preceding move".to_string())
+                        )
+                    }
+                };
+                lines.push(Line {
+                    position,
+                    before_last: None,
+                });
+            }
+            ')' => {
+                reader.next();
+                if lines.len() == 1 {
+                    return Err(reader.error(start, "unexpected end of variation".to_string()));
+                }
+                lines.pop();
+            }
+            '[' => {
+                return Err(reader.error(start, "only a single game can be imported".to_string()))
+            }
+            '}' | ']' => return Err(reader.error(start, format!("unexpected '{}'", c))),
+            _ => {
+                let symbol = reader.read_symbol();
+
+                if RESULTS.contains(&symbol.as_str()) {
+                    if lines.len() > 1 {
+                        return Err(reader.error(start, "unterminated variation".to_string()));
+                    }
+                    if matches!(&tagged_result, Some(tagged) if *tagged != symbol) {
+                        return Err(reader.error(
+                            start,
+                            format!("result {} doesn't match the Result tag", symbol),
+                        ));
+                    }
+                    result = Some((symbol, start));
+
+                    reader.skip_whitespace();
+                    if reader.peek().is_some() {
+                        return Err(reader.error(
+                            reader.position(),
+                            "only a single game can be imported".to_string(),
+                        ));
+                    }
+                    break;
+                }
+
+                // Move numbers can be glued to the move, as in "1.e4", and
+                // castling can be written with zeros
+                let san = match symbol.rfind('.') {
+                    Some(dot) => &symbol[dot + 1..],
+                    None => symbol.as_str(),
+                };
+                if san.chars().all(|c| c.is_ascii_digit()) {
+                    continue;
+                }
+
+                let line = lines.last_mut().expect("the mainline is never popped");
+                let (chess_move, next) = read_san(line.position.as_ref(), san)
+                    .ok_or_else(|| reader.error(start, format!("illegal move {}", san)))?;
+
+                line.before_last = Some(mem::replace(&mut line.position, next));
+
+                if lines.len() == 1 {
+                    moves.push(chess_move);
+                    tokens.push(MoveToken {
+                        line: start.0,
+                        column: start.1,
+                        san: san.to_string(),
+                    });
+                }
+            }
+        }
+    }
+
+    if lines.len() > 1 {
+        return Err(reader.error(reader.position(), "unterminated variation".to_string()));
+    }
+
+    let (mut result, result_start) = result
+        .or_else(|| tagged_result.map(|tagged| (tagged, (1, 1))))
+        .unwrap_or_else(|| ("*".to_string(), (1, 1)));
+
+    // Games that ended on the board can only have that result
+    if let Some(end) = lines[0].position.result() {
+        if result == "*" {
+            result = end.score().to_string();
+        } else if result != end.score() {
+            return Err(reader.error(
+                result_start,
+                format!("result {} doesn't match the final position", result),
+            ));
+        }
+    }
+
+    Ok(ParsedPgn {
+        tags,
+        variant,
+        initial,
+        moves,
+        tokens,
+        result,
+    })
+}
+
+/// Finds the legal move written as `san`, ignoring annotations and check
+/// marks, along with the position it leads to. Pawn drops can leave out the
+/// pawn, as in `@e4`.
+fn read_san(
+    position: &dyn variant::Variant,
+    san: &str,
+) -> Option<(variant::Move, Box<dyn variant::Variant>)> {
+    let normalize = |san: &str| {
+        let san = san
+            .trim_end_matches(|c| "+#!?".contains(c))
+            .replace('0', "O")
+            .replace('=', "");
+
+        if san.starts_with('@') {
+            format!("P{}", san)
+        } else {
+            san
+        }
+    };
+    let wanted = normalize(san);
+
+    let chess_move = position
+        .legal_moves()
+        .into_iter()
+        .find(|chess_move| normalize(&get_san(position, *chess_move)) == wanted)
+        .or_else(|| {
+            ChessMove::from_san(position.board(), &wanted)
+                .ok()
+                .map(variant::Move::from)
+        })?;
+
+    Some((chess_move, position.play(chess_move)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::actors::room::{Delay, TimeControl};
+    use chrono::NaiveDate;
+    use sqlx::types::Json;
+
+    fn game(variant: Variant, initial_fen: &str, moves: &[&str], result: &str) -> Game {
+        let started_at = NaiveDate::from_ymd(2021, 10, 9).and_hms(12, 0, 0);
+
+        Game {
+            id: Uuid::nil(),
+            white_id: None,
+            black_id: None,
+            variant,
+            initial_fen: initial_fen.to_string(),
+            moves_uci: moves.iter().map(|uci| uci.to_string()).collect(),
+            moves_san: Vec::new(),
+            pockets: None,
+            result: result.to_string(),
+            termination: None,
+            time_control: None,
+            clocks: Vec::new(),
+            started_at,
+            ended_at: started_at,
+            imported: false,
+            white_name: Some("alice".to_string()),
+            black_name: Some("bob".to_string()),
+            pgn: None,
+            rated: false,
+            white_rating: None,
+            black_rating: None,
+        }
+    }
+
+    fn standard(moves: &[&str], result: &str) -> Game {
+        let fen = variant::initial_position(Variant::Standard, STANDARD_POSITION).fen();
+
+        game(Variant::Standard, &fen, moves, result)
+    }
+
+    fn movetext(pgn: &str) -> &str {
+        pgn.split("\n\n").nth(1).unwrap()
+    }
+
+    #[test]
+    fn exports_tags_and_moves() {
+        let mut game = standard(&["f2f3", "e7e5", "g2g4", "d8h4"], "0-1");
+        game.rated = true;
+        game.white_rating = Some(1500);
+        game.termination = Some(GameEndResult::BlackCheckmates);
+        game.time_control = Some(Json(TimeControl {
+            base: 180,
+            increment: 2,
+            delay: None,
+        }));
+        game.clocks = vec![180_000, 179_500, 3_723_000, -5];
+
+        let pgn = write_pgn(&game);
+
+        assert_eq!(
+            pgn,
+            "[Event \"Rated game\"]\n\
+             [Site \"Rechess\"]\n\
+             [Date \"2021.10.09\"]\n\
+             [Round \"-\"]\n\
+             [White \"alice\"]\n\
+             [Black \"bob\"]\n\
+             [Result \"0-1\"]\n\
+             [WhiteElo \"1500\"]\n\
+             [BlackElo \"?\"]\n\
+             [TimeControl \"180+2\"]\n\
+             [Termination \"Normal\"]\n\
+             \n\
+             1. f3 { [%clk 0:03:00] } e5 { [%clk 0:02:59] } 2. g4 { [%clk 1:02:03] } Qh4#\n\
+             { [%clk 0:00:00] } 0-1\n\n"
+        );
+    }
+
+    #[test]
+    fn delays_leave_out_the_time_control() {
+        let mut game = standard(&[], "*");
+        game.time_control = Some(Json(TimeControl {
+            base: 300,
+            increment: 0,
+            delay: Some(Delay::Simple { seconds: 5 }),
+        }));
+        assert!(!write_pgn(&game).contains("[TimeControl "));
+
+        game.time_control = None;
+        assert!(write_pgn(&game).contains("[TimeControl \"-\"]\n"));
+    }
+
+    #[test]
+    fn long_games_wrap_their_movetext() {
+        let moves = ["g1f3", "g8f6", "f3g1", "f6g8"];
+        let pgn = write_pgn(&standard(&moves.repeat(10), "1/2-1/2"));
+
+        assert!(movetext(&pgn).lines().count() > 1);
+        assert!(movetext(&pgn).lines().all(|line| line.len() <= LINE_LENGTH));
+        assert!(movetext(&pgn).ends_with("20. Ng1 Ng8 1/2-1/2"));
+    }
+
+    #[test]
+    fn custom_positions_give_their_fen() {
+        let fen = "4k3/8/8/8/8/8/4P3/4K3 b - - 0 1";
+        let pgn = write_pgn(&game(Variant::Standard, fen, &["e8d7", "e2e4"], "*"));
+
+        assert!(pgn.contains("[SetUp \"1\"]\n[FEN \"4k3/8/8/8/8/8/4P3/4K3 b - - 0 1\"]\n"));
+        assert_eq!(movetext(&pgn), "1... Kd7 2. e4 *");
+    }
+
+    #[test]
+    fn chess960_games_always_give_their_fen() {
+        let fen = variant::initial_position(Variant::Chess960, STANDARD_POSITION).fen();
+        let pgn = write_pgn(&game(Variant::Chess960, &fen, &[], "*"));
+
+        assert!(pgn.contains("[Variant \"Chess960\"]\n[SetUp \"1\"]\n"));
+    }
+
+    #[test]
+    fn imported_games_keep_their_pgn() {
+        let mut game = standard(&["e2e4"], "*");
+        game.pgn = Some("[Event \"?\"]\n\n1. e4 { best by test } *\n".to_string());
+
+        assert_eq!(
+            write_pgn(&game),
+            "[Event \"?\"]\n\n1. e4 { best by test } *\n\n"
+        );
+    }
+
+    #[test]
+    fn tag_values_are_escaped() {
+        let mut game = standard(&[], "*");
+        game.white_name = Some("a \"quoted\\ name".to_string());
+
+        assert!(write_pgn(&game).contains("[White \"a \\\"quoted\\\\ name\"]"));
+    }
+
+    fn uci_moves(parsed: &ParsedPgn) -> Vec<String> {
+        let mut position = parsed.initial.clone();
+
+        parsed
+            .moves
+            .iter()
+            .map(|chess_move| {
+                let uci = position.uci(*chess_move);
+                position = position.play(*chess_move).unwrap();
+                uci
+            })
+            .collect()
+    }
+
+    #[test]
+    fn imports_the_mainline() {
+        let parsed = parse_pgn(
+            "[Event \"Casual\"]\n\
+             [White \"alice\"]\n\
+             % an escaped line\n\
+             \n\
+             1.e4 {a comment} e5 $1 2. Nf3 (2. f4 exf4 (2... d5)) 2... Nc6!? ; rest of line\n\
+             3. Bc4 Nf6 4. 0-0 1-0\n",
+        )
+        .unwrap();
+
+        assert_eq!(parsed.tag("White"), Some("alice"));
+        assert_eq!(parsed.tag("Black"), None);
+        assert_eq!(parsed.variant, Variant::Standard);
+        assert_eq!(parsed.result, "1-0");
+        assert_eq!(
+            uci_moves(&parsed),
+            ["e2e4", "e7e5", "g1f3", "b8c6", "f1c4", "g8f6", "e1g1"]
+        );
+    }
+
+    #[test]
+    fn result_defaults_to_the_tag() {
+        let tagged = parse_pgn("[Result \"1/2-1/2\"]\n\n1. d4 d5").unwrap();
+        assert_eq!(tagged.result, "1/2-1/2");
+
+        let untagged = parse_pgn("1. d4 d5").unwrap();
+        assert_eq!(untagged.result, "*");
+    }
+
+    #[test]
+    fn starts_from_the_fen_tag() {
+        let parsed =
+            parse_pgn("[FEN \"4k3/8/8/8/8/8/4P3/4K3 b - - 0 1\"]\n\n1... Kd7 2. e4 *").unwrap();
+
+        assert_eq!(uci_moves(&parsed), ["e8d7", "e2e4"]);
+    }
+
+    #[test]
+    fn exported_games_import_back() {
+        let moves = [
+            "e2e4", "c7c5", "g1f3", "d7d6", "d2d4", "c5d4", "f3d4", "g8f6",
+        ];
+        let game = standard(&moves, "*");
+
+        assert_eq!(uci_moves(&parse_pgn(&write_pgn(&game)).unwrap()), moves);
+    }
+
+    #[test]
+    fn errors_point_at_the_faulty_token() {
+        let error = |text| parse_pgn(text).unwrap_err();
+
+        let illegal = error("1. e4 e5\n2. Ke3 *");
+        assert_eq!((illegal.line, illegal.column), (2, 4));
+        assert_eq!(illegal.message, "illegal move Ke3");
+
+        let variation = error("(1. e4) *");
+        assert_eq!((variation.line, variation.column), (1, 1));
+
+        let unterminated = error("1. e4 (1. d4 *");
+        assert_eq!(unterminated.message, "unterminated variation");
+
+        let comment = error("1. e4 { never closed");
+        assert_eq!((comment.line, comment.column), (1, 7));
+
+        assert_eq!(error("1. e4 *\n\n1. d4 *").line, 3);
+        assert_eq!(
+            error("[Variant \"Atomic\"]\n\n*").message,
+            "unsupported variant \"Atomic\""
+        );
+        assert_eq!(
+            error("[FEN \"8/8\"]\n\n*").message,
+            "invalid FEN tag \"8/8\""
+        );
+    }
+
+    #[test]
+    fn moves_keep_their_token() {
+        let parsed = parse_pgn("1. e4 e5\n2. Nf3 *").unwrap();
+        let token = &parsed.tokens[2];
+
+        assert_eq!(parsed.tokens.len(), parsed.moves.len());
+        assert_eq!((token.line, token.column), (2, 4));
+        assert_eq!(token.san, "Nf3");
+
+        let illegal = parsed.illegal_move(2);
+        assert_eq!((illegal.line, illegal.column), (2, 4));
+        assert_eq!(illegal.message, "illegal move Nf3");
+    }
+
+    #[test]
+    fn results_must_agree() {
+        let error = |text| parse_pgn(text).unwrap_err();
+
+        assert_eq!(
+            error("[Result \"2-0\"]\n\n1. e4").message,
+            "invalid Result tag \"2-0\""
+        );
+
+        let tagged = error("[Result \"1-0\"]\n\n1. e4 0-1");
+        assert_eq!((tagged.line, tagged.column), (3, 7));
+        assert_eq!(tagged.message, "result 0-1 doesn't match the Result tag");
+
+        let mated = error("1. f3 e5 2. g4 Qh4# 1-0");
+        assert_eq!((mated.line, mated.column), (1, 21));
+        assert_eq!(mated.message, "result 1-0 doesn't match the final position");
+
+        let tag_only = error("[Result \"1/2-1/2\"]\n\n1. f3 e5 2. g4 Qh4#");
+        assert_eq!((tag_only.line, tag_only.column), (1, 1));
+    }
+
+    #[test]
+    fn finished_games_get_their_result() {
+        let parsed = parse_pgn("1. f3 e5 2. g4 Qh4# *").unwrap();
+
+        assert_eq!(parsed.result, "0-1");
+    }
+}
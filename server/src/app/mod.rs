@@ -1,4 +1,6 @@
 pub mod auth;
+pub mod games;
+pub mod ratings;
 pub mod rooms;
 pub mod users;
 pub mod ws;
\ No newline at end of file
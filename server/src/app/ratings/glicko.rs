@@ -0,0 +1,202 @@
+//! Glicko-2 rating system, as described by Mark Glickman in
+//! <http://www.glicko.net/glicko/glicko2.pdf>.
+//!
+//! Every game is its own rating period, and the deviation of inactive
+//! players grows by one period per day.
+
+use std::f64::consts::PI;
+
+pub const DEFAULT_RATING: f64 = 1500.0;
+pub const DEFAULT_DEVIATION: f64 = 350.0;
+pub const DEFAULT_VOLATILITY: f64 = 0.06;
+
+/// Deviations never grow past the one of a new player
+const MAX_DEVIATION: f64 = DEFAULT_DEVIATION;
+/// Keeps ratings of very active players from freezing
+const MIN_DEVIATION: f64 = 45.0;
+
+/// Conversion factor between the Glicko and the Glicko-2 scales
+const SCALE: f64 = 173.7178;
+/// Constrains the change of volatility over time
+const TAU: f64 = 0.75;
+const EPSILON: f64 = 0.000001;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Glicko {
+    pub rating: f64,
+    pub deviation: f64,
+    pub volatility: f64,
+}
+
+impl Default for Glicko {
+    fn default() -> Self {
+        Self {
+            rating: DEFAULT_RATING,
+            deviation: DEFAULT_DEVIATION,
+            volatility: DEFAULT_VOLATILITY,
+        }
+    }
+}
+
+fn g(phi: f64) -> f64 {
+    1.0 / (1.0 + 3.0 * phi * phi / (PI * PI)).sqrt()
+}
+
+fn expected(mu: f64, mu_j: f64, phi_j: f64) -> f64 {
+    1.0 / (1.0 + (-g(phi_j) * (mu - mu_j)).exp())
+}
+
+impl Glicko {
+    fn mu(&self) -> f64 {
+        (self.rating - DEFAULT_RATING) / SCALE
+    }
+
+    fn phi(&self) -> f64 {
+        self.deviation / SCALE
+    }
+
+    /// Grows the deviation after `periods` rating periods without games
+    pub fn decay(&self, periods: f64) -> Self {
+        let phi = self.phi();
+        let deviation =
+            (phi * phi + periods.max(0.0) * self.volatility * self.volatility).sqrt() * SCALE;
+
+        Self {
+            deviation: deviation.min(MAX_DEVIATION),
+            ..*self
+        }
+    }
+
+    /// New rating after a game against `opponent`, `score` being 1 for a
+    /// win, 0.5 for a draw and 0 for a loss
+    pub fn update(&self, opponent: &Glicko, score: f64) -> Self {
+        let mu = self.mu();
+        let phi = self.phi();
+        let sigma = self.volatility;
+
+        let g_j = g(opponent.phi());
+        let e = expected(mu, opponent.mu(), opponent.phi());
+
+        // Estimated variance and improvement
+        let v = 1.0 / (g_j * g_j * e * (1.0 - e));
+        let delta = v * g_j * (score - e);
+
+        let sigma = self.new_volatility(delta, v, sigma, phi);
+
+        let phi_star = (phi * phi + sigma * sigma).sqrt();
+        let phi = 1.0 / (1.0 / (phi_star * phi_star) + 1.0 / v).sqrt();
+        let mu = mu + phi * phi * g_j * (score - e);
+
+        Self {
+            rating: mu * SCALE + DEFAULT_RATING,
+            deviation: (phi * SCALE).clamp(MIN_DEVIATION, MAX_DEVIATION),
+            volatility: sigma,
+        }
+    }
+
+    /// Solves for the new volatility with the Illinois algorithm
+    fn new_volatility(&self, delta: f64, v: f64, sigma: f64, phi: f64) -> f64 {
+        let a = (sigma * sigma).ln();
+        let f = |x: f64| {
+            let ex = x.exp();
+            let d = phi * phi + v + ex;
+
+            ex * (delta * delta - d) / (2.0 * d * d) - (x - a) / (TAU * TAU)
+        };
+
+        let mut big_a = a;
+        let mut big_b = if delta * delta > phi * phi + v {
+            (delta * delta - phi * phi - v).ln()
+        } else {
+            let mut k = 1.0;
+            while f(a - k * TAU) < 0.0 {
+                k += 1.0;
+            }
+            a - k * TAU
+        };
+
+        let mut f_a = f(big_a);
+        let mut f_b = f(big_b);
+
+        while (big_b - big_a).abs() > EPSILON {
+            let big_c = big_a + (big_a - big_b) * f_a / (f_b - f_a);
+            let f_c = f(big_c);
+
+            if f_c * f_b <= 0.0 {
+                big_a = big_b;
+                f_a = f_b;
+            } else {
+                f_a /= 2.0;
+            }
+
+            big_b = big_c;
+            f_b = f_c;
+        }
+
+        (big_a / 2.0).exp()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn glicko(rating: f64, deviation: f64) -> Glicko {
+        Glicko {
+            rating,
+            deviation,
+            volatility: DEFAULT_VOLATILITY,
+        }
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 0.01,
+            "{} is not close to {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn win_against_a_settled_opponent() {
+        let new = glicko(1500.0, 200.0).update(&glicko(1400.0, 30.0), 1.0);
+
+        assert_close(new.rating, 1563.56);
+        assert_close(new.deviation, 175.40);
+        assert!((new.volatility - DEFAULT_VOLATILITY).abs() < 0.0001);
+    }
+
+    #[test]
+    fn results_move_equal_players_symmetrically() {
+        let player = Glicko::default();
+        let win = player.update(&player, 1.0);
+        let draw = player.update(&player, 0.5);
+        let loss = player.update(&player, 0.0);
+
+        assert_close(win.rating, 1662.31);
+        assert_close(draw.rating, DEFAULT_RATING);
+        assert_close(loss.rating, 2.0 * DEFAULT_RATING - win.rating);
+        assert_close(draw.deviation, 290.32);
+    }
+
+    #[test]
+    fn deviation_stays_within_bounds() {
+        let opponent = glicko(2000.0, MIN_DEVIATION);
+        let settled = (0..1000).fold(opponent, |player, _| player.update(&opponent, 0.5));
+        assert!(settled.deviation >= MIN_DEVIATION);
+
+        let rusty = glicko(1500.0, 100.0).decay(1_000_000.0);
+        assert_close(rusty.deviation, MAX_DEVIATION);
+    }
+
+    #[test]
+    fn decay_grows_the_deviation_over_time() {
+        let player = glicko(1800.0, 100.0);
+
+        assert_eq!(player.decay(0.0), player);
+        assert_eq!(player.decay(-5.0), player);
+        assert_close(player.decay(30.0).deviation, 115.15);
+        assert_close(player.decay(30.0).rating, 1800.0);
+    }
+}
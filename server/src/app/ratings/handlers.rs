@@ -0,0 +1,41 @@
+use super::model::{HistoryQuery, Rating};
+use crate::app::auth::AuthUser;
+
+use actix_web::{get, web, web::ServiceConfig, HttpResponse, Responder};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+// TODO: Proper error handling
+
+pub fn config(config: &mut ServiceConfig) {
+    config
+        .service(get_user_rating_history)
+        .service(get_user_ratings);
+}
+
+/// Current ratings of a user, one per speed played
+#[get("/user/{id}")]
+pub async fn get_user_ratings(
+    id: web::Path<Uuid>,
+    _user: AuthUser,
+    db_pool: web::Data<PgPool>,
+) -> impl Responder {
+    match Rating::find_by_user(&db_pool, *id).await {
+        Ok(data) => HttpResponse::Ok().json(data),
+        Err(e) => HttpResponse::BadRequest().body(format!("got error {:?}", e)),
+    }
+}
+
+/// Ratings of a user after each of their rated games, oldest first
+#[get("/user/{id}/history")]
+pub async fn get_user_rating_history(
+    id: web::Path<Uuid>,
+    query: web::Query<HistoryQuery>,
+    _user: AuthUser,
+    db_pool: web::Data<PgPool>,
+) -> impl Responder {
+    match Rating::find_history(&db_pool, *id, query.speed).await {
+        Ok(data) => HttpResponse::Ok().json(data),
+        Err(e) => HttpResponse::BadRequest().body(format!("got error {:?}", e)),
+    }
+}
@@ -0,0 +1,5 @@
+pub mod glicko;
+pub mod handlers;
+pub mod model;
+
+pub use handlers::config;
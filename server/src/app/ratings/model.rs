@@ -0,0 +1,229 @@
+use super::glicko::Glicko;
+use crate::actors::room::Speed;
+use crate::app::games::model::{Game, NewGame};
+
+use chrono::{NaiveDateTime, Utc};
+use color_eyre::Result;
+use serde::{Deserialize, Serialize};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+/// Length of a rating period, in seconds
+const RATING_PERIOD: f64 = 24.0 * 60.0 * 60.0;
+
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct Rating {
+    pub user_id: Uuid,
+    pub speed: Speed,
+    pub rating: f64,
+    pub deviation: f64,
+    pub volatility: f64,
+    pub games: i32,
+    pub updated_at: NaiveDateTime,
+}
+
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct RatingHistory {
+    pub game_id: Option<Uuid>,
+    pub speed: Speed,
+    pub rating: f64,
+    pub deviation: f64,
+    pub volatility: f64,
+    pub created_at: NaiveDateTime,
+}
+
+/// Rating of a player after a game
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RatingChange {
+    pub rating: f64,
+    pub deviation: f64,
+    pub diff: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct GameRatings {
+    pub white: RatingChange,
+    pub black: RatingChange,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct HistoryQuery {
+    pub speed: Option<Speed>,
+}
+
+impl Rating {
+    /// Rating with the deviation grown by the time spent without playing
+    pub fn current(&self, now: NaiveDateTime) -> Glicko {
+        let idle = (now - self.updated_at).num_seconds() as f64;
+
+        Glicko {
+            rating: self.rating,
+            deviation: self.deviation,
+            volatility: self.volatility,
+        }
+        .decay(idle / RATING_PERIOD)
+    }
+
+    /// Ratings of a user in every speed they played, as of now
+    pub async fn find_by_user(pool: &PgPool, user_id: Uuid) -> Result<Vec<Rating>> {
+        let now = Utc::now().naive_utc();
+        let mut ratings: Vec<Rating> =
+            sqlx::query_as("select * from ratings where user_id = $1 order by speed")
+                .bind(user_id)
+                .fetch_all(pool)
+                .await?;
+
+        for rating in ratings.iter_mut() {
+            rating.deviation = rating.current(now).deviation;
+        }
+
+        Ok(ratings)
+    }
+
+    /// Rating of a user in a speed, as of now
+    pub async fn find(pool: &PgPool, user_id: Uuid, speed: Speed) -> Result<Glicko> {
+        let rating: Option<Rating> =
+            sqlx::query_as("select * from ratings where user_id = $1 and speed = $2")
+                .bind(user_id)
+                .bind(speed)
+                .fetch_optional(pool)
+                .await?;
+
+        Ok(rating.map_or_else(Glicko::default, |rating| {
+            rating.current(Utc::now().naive_utc())
+        }))
+    }
+
+    pub async fn find_history(
+        pool: &PgPool,
+        user_id: Uuid,
+        speed: Option<Speed>,
+    ) -> Result<Vec<RatingHistory>> {
+        let history = sqlx::query_as(
+            "select game_id, speed, rating, deviation, volatility, created_at from rating_history \
+             where user_id = $1 and ($2::varchar is null or speed = $2) order by created_at",
+        )
+        .bind(user_id)
+        .bind(speed)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(history)
+    }
+
+    /// Current ratings of two players, their rows being locked until the
+    /// end of the transaction. Rows are locked in the order of the ids so
+    /// that two games between the same players can't wait on each other.
+    async fn find_current_pair(
+        tx: &mut Transaction<'_, Postgres>,
+        (first, second): (Uuid, Uuid),
+        speed: Speed,
+        now: NaiveDateTime,
+    ) -> Result<(Glicko, Glicko)> {
+        let ratings: Vec<Rating> = sqlx::query_as(
+            "select * from ratings where user_id = any($1) and speed = $2 \
+             order by user_id for update",
+        )
+        .bind(vec![first, second])
+        .bind(speed)
+        .fetch_all(&mut *tx)
+        .await?;
+
+        let current = |user_id: Uuid| {
+            ratings
+                .iter()
+                .find(|rating| rating.user_id == user_id)
+                .map_or_else(Glicko::default, |rating| rating.current(now))
+        };
+
+        Ok((current(first), current(second)))
+    }
+
+    async fn save(
+        tx: &mut Transaction<'_, Postgres>,
+        user_id: Uuid,
+        speed: Speed,
+        game_id: Uuid,
+        glicko: Glicko,
+    ) -> Result<()> {
+        sqlx::query(
+            "insert into ratings (user_id, speed, rating, deviation, volatility, games) \
+             values ($1, $2, $3, $4, $5, 1) \
+             on conflict (user_id, speed) do update set rating = $3, deviation = $4, volatility = $5, \
+             games = ratings.games + 1, updated_at = current_timestamp",
+        )
+        .bind(user_id)
+        .bind(speed)
+        .bind(glicko.rating)
+        .bind(glicko.deviation)
+        .bind(glicko.volatility)
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query(
+            "insert into rating_history (user_id, game_id, speed, rating, deviation, volatility) \
+             values ($1, $2, $3, $4, $5, $6)",
+        )
+        .bind(user_id)
+        .bind(game_id)
+        .bind(speed)
+        .bind(glicko.rating)
+        .bind(glicko.deviation)
+        .bind(glicko.volatility)
+        .execute(&mut *tx)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Stores a rated game and updates the ratings of both players
+    pub async fn record_game(
+        pool: &PgPool,
+        mut new_game: NewGame,
+        speed: Speed,
+        white_score: f64,
+    ) -> Result<GameRatings> {
+        let now = Utc::now().naive_utc();
+        let mut tx = pool.begin().await?;
+
+        let (white, black) =
+            Rating::find_current_pair(&mut tx, (new_game.white_id, new_game.black_id), speed, now)
+                .await?;
+
+        new_game.rated = true;
+        new_game.white_rating = Some(white.rating.round() as i32);
+        new_game.black_rating = Some(black.rating.round() as i32);
+
+        let game = Game::create(&mut tx, new_game).await?;
+        let white_id = game.white_id.unwrap_or_default();
+        let black_id = game.black_id.unwrap_or_default();
+
+        let new_white = white.update(&black, white_score);
+        let new_black = black.update(&white, 1.0 - white_score);
+
+        // Ratings seen for the first time are inserted, in the same order as
+        // the locks for the same reason
+        let mut saves = [(white_id, new_white), (black_id, new_black)];
+        saves.sort_by_key(|(user_id, _)| *user_id);
+        for (user_id, glicko) in saves.iter() {
+            Rating::save(&mut tx, *user_id, speed, game.id, *glicko).await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(GameRatings {
+            white: RatingChange::new(&white, &new_white),
+            black: RatingChange::new(&black, &new_black),
+        })
+    }
+}
+
+impl RatingChange {
+    fn new(before: &Glicko, after: &Glicko) -> Self {
+        Self {
+            rating: after.rating,
+            deviation: after.deviation,
+            diff: after.rating - before.rating,
+        }
+    }
+}
@@ -1,27 +1,37 @@
 use super::model::{self, Response};
+use crate::actors::room::room_key;
 use crate::util::redis::get_hashmap;
 use actix::prelude::*;
-use actix_redis::{Command, Error, RedisActor, RespValue};
-use actix_web::{delete, get, post, web, web::ServiceConfig, HttpResponse, Responder};
-use log::info;
+use actix_redis::{Command, RedisActor};
+use actix_web::{get, web, web::ServiceConfig, HttpResponse, Responder};
 use redis_async::resp_array;
-use std::collections::hash_map::RandomState;
-use std::collections::HashMap;
-use std::str;
+
+/// Fields of the room hash holding player ids, which are not for clients to
+/// know
+const ID_FIELDS: [&str; 7] = [
+    "creator", "white", "black", "white_0", "black_0", "white_1", "black_1",
+];
 
 pub fn config(config: &mut ServiceConfig) {
     config.service(get_room);
 }
 
+/// Left open to anonymous players, who load the room before joining it
 #[get("/{id}")]
 pub async fn get_room(id: web::Path<String>, redis: web::Data<Addr<RedisActor>>) -> impl Responder {
     match redis
-        .send(Command(resp_array!["HGETALL", format!("rc:room:{}", id)]))
+        .send(Command(resp_array!["HGETALL", room_key(&id)]))
         .await
     {
         Ok(resp) => match resp {
             Ok(data) => match get_hashmap(data) {
-                Some(hashmap) => HttpResponse::Ok().json(&hashmap),
+                Some(mut hashmap) => {
+                    for field in ID_FIELDS.iter() {
+                        hashmap.remove(*field);
+                    }
+
+                    HttpResponse::Ok().json(&hashmap)
+                }
                 None => HttpResponse::BadRequest().json(Response::NotStarted),
             },
             Err(_) => HttpResponse::BadRequest().json(Response::Error(model::Error::RedisError)),
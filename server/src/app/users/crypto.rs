@@ -1,22 +1,214 @@
+use crate::app::auth::model::Claims;
+
+use actix_web::web;
 use argon2::{
-    password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
-    Argon2,
+    password_hash::{self, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Algorithm, Argon2, Params,
 };
+use chrono::{Duration, Utc};
 use color_eyre::Result;
-use rand_core::OsRng;
+use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
+use rand_core::{OsRng, RngCore};
+use std::convert::TryFrom;
 use std::sync::Arc;
+use uuid::Uuid;
+
+/// How long a token stays valid after login, in days
+const TOKEN_LIFETIME: i64 = 7;
 
 pub struct CryptoService {
     pub key: Arc<String>,
+    /// Parameters new password hashes are made with
+    pub params: Params,
+    /// Hash of a random password, checked for unknown usernames so that they
+    /// take as long to reject as wrong passwords
+    dummy_hash: String,
+}
+
+/// Outcome of a password check
+pub enum Verification {
+    Invalid,
+    Valid,
+    /// The password is valid but was hashed with outdated parameters, holds
+    /// a hash made with the current ones
+    Rehashed(String),
 }
 
 impl CryptoService {
-    pub async fn hash_password(&self, password: String) -> Result<String> {
-        let argon2 = Argon2::default();
+    pub fn new(key: Arc<String>, params: Params) -> Result<Self> {
+        let mut service = CryptoService {
+            key,
+            params,
+            dummy_hash: String::new(),
+        };
+
+        let mut password = [0; 32];
+        OsRng.fill_bytes(&mut password);
         let salt = SaltString::generate(&mut OsRng);
-        Ok(argon2
-            .hash_password_simple(&password.as_bytes(), salt.as_ref())
+        service.dummy_hash = service
+            .hasher()?
+            .hash_password_simple(&password, salt.as_ref())?
+            .to_string();
+
+        Ok(service)
+    }
+
+    fn hasher(&self) -> Result<Argon2<'static>> {
+        Argon2::new(
+            None,
+            self.params.t_cost,
+            self.params.m_cost,
+            self.params.p_cost,
+            self.params.version,
+        )
+        .map_err(|e| password_hash::Error::from(e).into())
+    }
+
+    /// Hashing is slow on purpose, so it runs on the blocking thread pool
+    pub async fn hash_password(&self, password: String) -> Result<String> {
+        let argon2 = self.hasher()?;
+
+        let hash = web::block(move || {
+            let salt = SaltString::generate(&mut OsRng);
+
+            argon2
+                .hash_password_simple(password.as_bytes(), salt.as_ref())
+                .map(|hash| hash.to_string())
+        })
+        .await??;
+
+        Ok(hash)
+    }
+
+    pub async fn verify_password(&self, password: String, hash: String) -> Result<Verification> {
+        let argon2 = self.hasher()?;
+        let params = self.params;
+
+        let verification = web::block(move || {
+            // Accounts created before passwords were hashed can't log in
+            let hash = match PasswordHash::new(&hash) {
+                Ok(hash) => hash,
+                Err(_) => return Ok(Verification::Invalid),
+            };
+
+            if argon2.verify_password(password.as_bytes(), &hash).is_err() {
+                return Ok(Verification::Invalid);
+            }
+
+            if hash.algorithm == Algorithm::default().ident()
+                && Params::try_from(&hash).ok() == Some(params)
+            {
+                return Ok(Verification::Valid);
+            }
+
+            let salt = SaltString::generate(&mut OsRng);
+
+            argon2
+                .hash_password_simple(password.as_bytes(), salt.as_ref())
+                .map(|hash| Verification::Rehashed(hash.to_string()))
+        })
+        .await??;
+
+        Ok(verification)
+    }
+
+    /// Runs a password check that always fails, for logins with an unknown
+    /// username
+    pub async fn verify_dummy(&self, password: String) -> Result<()> {
+        self.verify_password(password, self.dummy_hash.clone())
+            .await?;
+
+        Ok(())
+    }
+
+    pub fn generate_jwt(&self, id: Uuid, username: String) -> Result<String> {
+        let claims = Claims {
+            sub: id,
+            username,
+            exp: (Utc::now() + Duration::days(TOKEN_LIFETIME)).timestamp(),
+        };
+
+        Ok(encode(
+            &Header::default(),
+            &claims,
+            &EncodingKey::from_secret(self.key.as_bytes()),
+        )?)
+    }
+
+    /// Checks the signature and expiration of `token`
+    pub fn verify_jwt(&self, token: &str) -> Result<Claims> {
+        let data = decode::<Claims>(
+            token,
+            &DecodingKey::from_secret(self.key.as_bytes()),
+            &Validation::default(),
+        )?;
+
+        Ok(data.claims)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::rt::System;
+
+    fn service(t_cost: u32) -> CryptoService {
+        let params = Params {
+            t_cost,
+            ..Params::default()
+        };
+
+        CryptoService::new(Arc::new("secret".to_string()), params).unwrap()
+    }
+
+    fn verify(service: &CryptoService, password: &str, hash: &str) -> Verification {
+        System::new()
+            .block_on(service.verify_password(password.to_string(), hash.to_string()))
             .unwrap()
-            .to_string())
+    }
+
+    #[test]
+    fn only_the_right_password_verifies() {
+        let service = service(1);
+        let hash = System::new()
+            .block_on(service.hash_password("hunter2".to_string()))
+            .unwrap();
+
+        assert!(matches!(
+            verify(&service, "hunter2", &hash),
+            Verification::Valid
+        ));
+        assert!(matches!(
+            verify(&service, "hunter3", &hash),
+            Verification::Invalid
+        ));
+        assert!(matches!(
+            verify(&service, "hunter2", "plaintext"),
+            Verification::Invalid
+        ));
+    }
+
+    #[test]
+    fn outdated_hashes_are_replaced() {
+        let old = service(1);
+        let hash = System::new()
+            .block_on(old.hash_password("hunter2".to_string()))
+            .unwrap();
+
+        let current = service(2);
+        let rehashed = match verify(&current, "hunter2", &hash) {
+            Verification::Rehashed(rehashed) => rehashed,
+            _ => panic!("the hash should be replaced"),
+        };
+
+        assert_ne!(rehashed, hash);
+        assert!(matches!(
+            verify(&current, "hunter2", &rehashed),
+            Verification::Valid
+        ));
+        assert!(matches!(
+            verify(&current, "hunter3", &hash),
+            Verification::Invalid
+        ));
     }
 }
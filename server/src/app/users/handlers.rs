@@ -1,6 +1,14 @@
+use super::crypto::CryptoService;
 use super::model::{NewUser, User};
+use crate::actors::room_manager::RoomManager;
+use crate::app::auth::link::link_anonymous;
+use crate::app::auth::AuthUser;
+
+use actix::prelude::*;
+use actix_session::Session;
 
 use actix_web::{delete, get, post, web, web::ServiceConfig, HttpResponse, Responder};
+use log::error;
 use sqlx::PgPool;
 use uuid::Uuid;
 use validator::Validate;
@@ -16,7 +24,7 @@ pub fn config(config: &mut ServiceConfig) {
 }
 
 #[get("/")]
-pub async fn get_users(db_pool: web::Data<PgPool>) -> impl Responder {
+pub async fn get_users(_user: AuthUser, db_pool: web::Data<PgPool>) -> impl Responder {
     match User::find_all(&db_pool).await {
         Ok(data) => HttpResponse::Ok().json(data),
         Err(e) => HttpResponse::BadRequest().body(format!("got error {:?}", e)),
@@ -24,20 +32,44 @@ pub async fn get_users(db_pool: web::Data<PgPool>) -> impl Responder {
 }
 
 #[post("/")]
-pub async fn create_user(data: web::Json<NewUser>, db_pool: web::Data<PgPool>) -> impl Responder {
+pub async fn create_user(
+    data: web::Json<NewUser>,
+    db_pool: web::Data<PgPool>,
+    crypto: web::Data<CryptoService>,
+    room_manager: web::Data<Addr<RoomManager>>,
+    session: Session,
+) -> impl Responder {
     match data.validate() {
         Ok(_) => (),
         Err(e) => return HttpResponse::BadRequest().body(format!("{}", e)),
     };
 
-    match User::create(&db_pool, data.into_inner()).await {
-        Ok(data) => HttpResponse::Ok().json(data),
-        Err(e) => HttpResponse::BadRequest().body(format!("{}", e)),
+    let password_hash = match crypto.hash_password(data.password.clone()).await {
+        Ok(hash) => hash,
+        Err(e) => {
+            error!("Could not hash password: {:?}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    let user = match User::create(&db_pool, data.into_inner(), password_hash).await {
+        Ok(user) => user,
+        Err(e) => return HttpResponse::BadRequest().body(format!("{}", e)),
+    };
+
+    if let Err(e) = link_anonymous(&session, &db_pool, &room_manager, &user).await {
+        error!("Could not link anonymous player to {}: {:?}", user.username, e);
     }
+
+    HttpResponse::Ok().json(user)
 }
 
 #[get("/{id}")]
-pub async fn get_user(id: web::Path<Uuid>, db_pool: web::Data<PgPool>) -> impl Responder {
+pub async fn get_user(
+    id: web::Path<Uuid>,
+    _user: AuthUser,
+    db_pool: web::Data<PgPool>,
+) -> impl Responder {
     match User::find_by_id(&db_pool, *id).await {
         Ok(data) => HttpResponse::Ok().json(data),
         Err(e) => HttpResponse::BadRequest().body(format!("got error {:?}", e)),
@@ -45,7 +77,15 @@ pub async fn get_user(id: web::Path<Uuid>, db_pool: web::Data<PgPool>) -> impl R
 }
 
 #[delete("/{id}")]
-pub async fn delete_user(id: web::Path<Uuid>, db_pool: web::Data<PgPool>) -> impl Responder {
+pub async fn delete_user(
+    id: web::Path<Uuid>,
+    user: AuthUser,
+    db_pool: web::Data<PgPool>,
+) -> impl Responder {
+    if user.id != *id {
+        return HttpResponse::Forbidden().finish();
+    }
+
     match User::delete(&db_pool, *id).await {
         Ok(_data) => HttpResponse::Ok().finish(),
         Err(e) => HttpResponse::BadRequest().body(format!("{}", e)),
@@ -27,9 +27,7 @@ pub struct NewUser {
 }
 
 impl User {
-    pub async fn create(pool: &PgPool, new_user: NewUser) -> Result<User> {
-        let password_hash = "aa";
-
+    pub async fn create(pool: &PgPool, new_user: NewUser, password_hash: String) -> Result<User> {
         let user = sqlx::query_as(
             "insert into users (username, email, password_hash) values ($1, $2, $3) returning *",
         )
@@ -59,6 +57,27 @@ impl User {
         Ok(user)
     }
 
+    pub async fn find_by_username(pool: &PgPool, username: &str) -> Result<Option<User>> {
+        let user = sqlx::query_as("select * from users where username = $1")
+            .bind(username)
+            .fetch_optional(pool)
+            .await?;
+
+        Ok(user)
+    }
+
+    pub async fn update_password_hash(pool: &PgPool, id: Uuid, password_hash: &str) -> Result<()> {
+        sqlx::query(
+            "update users set password_hash = $2, updated_at = current_timestamp where id = $1",
+        )
+        .bind(id)
+        .bind(password_hash)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
     pub async fn delete(pool: &PgPool, id: Uuid) -> Result<bool> {
         let user = sqlx::query("delete from users where id = $1")
             .bind(id)
@@ -1,51 +1,63 @@
+use crate::actors::matchmaker::Matchmaker;
+use crate::actors::room::PlayerInfo;
 use crate::actors::websocket::{Connection, WebsocketSession};
 use crate::actors::room_manager;
+use crate::app::auth::link::anonymous_id_or_new;
+use crate::app::auth::model::AuthError;
+use crate::app::auth::AuthUser;
 
 use actix::prelude::*;
-use actix_redis::RedisActor;
-use actix_web::{get, web, web::ServiceConfig, Error, HttpRequest, HttpResponse, Responder};
+use actix_web::{get, web, web::ServiceConfig, HttpRequest, Responder};
 use actix_web_actors::ws;
 use actix_session::{Session};
 
-use log::info;
-use std::time::Instant;
-use uuid::Uuid;
-
 pub fn config(config: &mut ServiceConfig) {
     config.service(join_room).service(join_lobby);
 }
 
+/// Logged in users play under their account, anyone else anonymously under
+/// the id stored in their session cookie
+fn player(
+    user: Result<AuthUser, AuthError>,
+    session: &Session,
+) -> Result<PlayerInfo, actix_web::Error> {
+    let id = match user {
+        Ok(user) => {
+            return Ok(PlayerInfo {
+                id: user.id,
+                username: Some(user.username),
+            })
+        }
+        Err(AuthError::MissingToken) => anonymous_id_or_new(session)?,
+        Err(e) => return Err(e.into()),
+    };
+
+    Ok(PlayerInfo { id, username: None })
+}
+
 #[get("/play/{room_name}")]
 pub async fn join_room(
     req: HttpRequest,
     stream: web::Payload,
     srv: web::Data<Addr<room_manager::RoomManager>>,
+    matchmaker: web::Data<Addr<Matchmaker>>,
     room_id: web::Path<String>,
     session: Session,
+    user: Result<AuthUser, AuthError>,
 ) -> impl Responder {
-    if let Some(id) = session.get::<uuid::Uuid>("rc-id")? {
-        ws::start(
-            WebsocketSession::new(
-                id,
-                Connection::Play(room_id.clone()),
-                srv.get_ref().clone(),
-            ),
-            &req,
-            stream,
-        )
-    } else {
-        let id = uuid::Uuid::new_v4();
-        session.insert("rc-id", id)?;
-        ws::start(
-            WebsocketSession::new(
-                id,
-                Connection::Play(room_id.clone()),
-                srv.get_ref().clone(),
-            ),
-            &req,
-            stream,
-        )
-    }
+    let player = player(user, &session)?;
+
+    ws::start(
+        WebsocketSession::new(
+            player.id,
+            player.username,
+            Connection::Play(room_id.clone()),
+            srv.get_ref().clone(),
+            matchmaker.get_ref().clone(),
+        ),
+        &req,
+        stream,
+    )
 }
 
 #[get("/")]
@@ -53,29 +65,21 @@ pub async fn join_lobby(
     req: HttpRequest,
     stream: web::Payload,
     srv: web::Data<Addr<room_manager::RoomManager>>,
+    matchmaker: web::Data<Addr<Matchmaker>>,
     session: Session,
+    user: Result<AuthUser, AuthError>,
 ) -> impl Responder {
-    if let Some(id) = session.get::<uuid::Uuid>("rc-id")? {
-        ws::start(
-            WebsocketSession::new(
-                id,
-                Connection::Lobby,
-                srv.get_ref().clone(),
-            ),
-            &req,
-            stream,
-        )
-    } else {
-        let id = uuid::Uuid::new_v4();
-        session.insert("rc-id", id)?;
-        ws::start(
-            WebsocketSession::new(
-                id,
-                Connection::Lobby,
-                srv.get_ref().clone(),
-            ),
-            &req,
-            stream,
-        )
-    }
+    let player = player(user, &session)?;
+
+    ws::start(
+        WebsocketSession::new(
+            player.id,
+            player.username,
+            Connection::Lobby,
+            srv.get_ref().clone(),
+            matchmaker.get_ref().clone(),
+        ),
+        &req,
+        stream,
+    )
 }
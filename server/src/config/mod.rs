@@ -1,13 +1,17 @@
 use actix::prelude::*;
 use actix_redis::RedisActor;
-use color_eyre::{owo_colors::OwoColorize, Result};
-use eyre::WrapErr;
-use futures::prelude::*;
+use crate::app::users::crypto::CryptoService;
+use argon2::Params;
+use color_eyre::Result;
+use eyre::{eyre, WrapErr};
 use log::info;
+use redis_async::client::{pubsub_connect, PubsubConnection};
 use serde::Deserialize;
 use sqlx::postgres::{PgPool, PgPoolOptions};
+use std::net::ToSocketAddrs;
 use std::sync::Arc;
 use std::time::Duration;
+use uuid::Uuid;
 
 #[derive(Deserialize)]
 pub struct Config {
@@ -16,13 +20,44 @@ pub struct Config {
     pub database_url: String,
     pub redis_url: String,
     pub secret_key: String,
+    /// Name of this server instance, must be unique when several of them
+    /// share the same Redis. Without one, every run gets a random name and
+    /// its rooms are taken over by the next run once it is seen dead.
+    #[serde(default = "default_node_id")]
+    pub node_id: String,
+    /// Seconds a waiting room stays open once its creator left
+    #[serde(default = "default_waiting_room_grace")]
+    pub waiting_room_grace: u64,
+    /// Seconds a game goes on once both players left
+    #[serde(default = "default_abandoned_room_grace")]
+    pub abandoned_room_grace: u64,
+}
+
+fn default_node_id() -> String {
+    Uuid::new_v4().to_string()
+}
+
+fn default_waiting_room_grace() -> u64 {
+    5 * 60
+}
+
+fn default_abandoned_room_grace() -> u64 {
+    15 * 60
 }
 
 impl Config {
     pub fn from_env() -> Result<Config> {
         info!("Loading configuration");
 
-        envy::from_env().context("loading configuration from environment")
+        let config: Config =
+            envy::from_env().context("loading configuration from environment")?;
+
+        // Session cookies are signed with a key derived from it
+        if config.secret_key.len() < 32 {
+            return Err(eyre!("SECRET_KEY must be at least 32 bytes long"));
+        }
+
+        Ok(config)
     }
 
     pub async fn db_pool(&self) -> Result<PgPool> {
@@ -35,7 +70,26 @@ impl Config {
             .context("creating database connection pool")
     }
 
+    pub fn crypto_service(&self) -> Result<CryptoService> {
+        CryptoService::new(Arc::new(self.secret_key.clone()), Params::default())
+    }
+
     pub async fn redis_con(&self) -> Addr<RedisActor> {
         RedisActor::start(&self.redis_url)
     }
+
+    pub async fn redis_pubsub(&self) -> Result<PubsubConnection> {
+        info!("Connecting to Redis pub/sub");
+
+        let addr = self
+            .redis_url
+            .to_socket_addrs()
+            .context("resolving redis address")?
+            .next()
+            .ok_or_else(|| eyre!("no address for {}", self.redis_url))?;
+
+        pubsub_connect(addr)
+            .await
+            .context("connecting to redis pub/sub")
+    }
 }
@@ -3,9 +3,9 @@ mod app;
 mod config;
 mod util;
 
-use crate::app::{auth, rooms, users, ws};
+use crate::app::{auth, games, ratings, rooms, users, ws};
 use crate::config::Config;
-use crate::actors::room_manager;
+use crate::actors::{matchmaker, room_manager, sweeper};
 
 use actix::prelude::*;
 use actix_cors::Cors;
@@ -13,7 +13,10 @@ use actix_session::CookieSession;
 use actix_web::{http, middleware::Logger, web, web::Data, App, HttpServer};
 use color_eyre::Result;
 use dotenv::dotenv;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
 use std::sync::{atomic::AtomicUsize, Arc};
+use std::time::Duration;
 
 #[actix_web::main]
 async fn main() -> Result<()> {
@@ -28,8 +31,27 @@ async fn main() -> Result<()> {
 
     let app_state = Arc::new(AtomicUsize::new(0));
     let pool = config.db_pool().await.expect("Data configuration");
+    let crypto_service = Data::new(config.crypto_service().expect("Crypto configuration"));
     let redis = config.redis_con().await;
-    let server = room_manager::RoomManager::new(redis.clone()).start();
+    let pubsub = config.redis_pubsub().await?;
+    let server = room_manager::RoomManager::new(
+        config.node_id.clone(),
+        redis.clone(),
+        pubsub,
+        pool.clone(),
+        StdRng::from_entropy(),
+    )
+    .start();
+    let matchmaker =
+        matchmaker::Matchmaker::new(server.clone(), pool.clone(), StdRng::from_entropy()).start();
+    sweeper::Sweeper::new(
+        server.clone(),
+        Duration::from_secs(config.waiting_room_grace),
+        Duration::from_secs(config.abandoned_room_grace),
+    )
+    .start();
+
+    let session_key = config.secret_key.clone();
 
     HttpServer::new(move || {
         let cors = Cors::default()
@@ -39,21 +61,30 @@ async fn main() -> Result<()> {
             .allowed_header(http::header::CONTENT_TYPE)
             .max_age(3600);
 
+        // Websocket upgrades carry the token in the query string, which must
+        // not end up in the access log
+        let logger = Logger::new(r#"%a "%{method}xi %U" %s %b "%{Referer}i" "%{User-Agent}i" %T"#)
+            .custom_request_replace("method", |req| req.method().to_string());
+
         App::new()
             .wrap(cors)
-            .wrap(Logger::default())
-            .wrap(CookieSession::signed(&[0; 32]).secure(false))
+            .wrap(logger)
+            .wrap(CookieSession::signed(session_key.as_bytes()).secure(false))
             .app_data(Data::new(app_state.clone()))
             .app_data(Data::new(pool.clone()))
+            .app_data(crypto_service.clone())
             .app_data(Data::new(redis.clone()))
             .app_data(Data::new(server.clone()))
+            .app_data(Data::new(matchmaker.clone()))
             .service(web::scope("/ws").configure(ws::config))
             .service(
                 web::scope("/api").service(
                     web::scope("/v1")
                         .service(web::scope("/users").configure(users::config))
                         .service(web::scope("/auth").configure(auth::config))
-                        .service(web::scope("/rooms").configure(rooms::config)),
+                        .service(web::scope("/rooms").configure(rooms::config))
+                        .service(web::scope("/games").configure(games::config))
+                        .service(web::scope("/ratings").configure(ratings::config)),
                 ),
             )
     })
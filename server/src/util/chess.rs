@@ -1,23 +1,448 @@
-use chess::{Board, MoveGen};
-use std::collections::HashMap;
+use super::position::CastlingSide;
+use super::variant::{Move, Variant};
 
-pub fn get_dests(board: &Board) -> HashMap<String, String> {
-    let mut movegen = MoveGen::new_legal(&board);
-    let mut dests: HashMap<String, String> = HashMap::new();
+use chess::{BitBoard, Board, ChessMove, Color, MoveGen, Piece, Square};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
 
-    for chess_move in movegen {
-        match dests.get_mut(&chess_move.get_source().to_string()) {
-            Some(dest) => {
-                dest.push_str(&chess_move.get_dest().to_string());
+/// How a legal move is played on the board
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MoveKind {
+    Normal,
+    Promotion,
+    Castle,
+    EnPassant,
+    /// A piece put on the board from the pocket
+    Drop,
+}
+
+/// A legal move of the side to move, `uci` is what the client sends to
+/// play it
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LegalMove {
+    /// Square the piece leaves, missing for drops
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub from: Option<String>,
+    pub to: String,
+    pub uci: String,
+    pub kind: MoveKind,
+    /// Piece a pawn promotes to, in lowercase
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub promotion: Option<String>,
+    /// Piece dropped from the pocket, in lowercase
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub piece: Option<String>,
+}
+
+/// Why a move sent by a player can't be played
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MoveError {
+    Illegal,
+    /// The move reaches the last rank without saying what to promote to
+    PromotionRequired,
+}
+
+/// Lists every legal move of `position`.
+///
+/// Promotions are listed once per piece and drops once per piece and
+/// square. Castling is listed as the king moving onto its rook and, unless
+/// another move has the same squares, as the king moving to its destination.
+pub fn get_dests(position: &dyn Variant) -> Vec<LegalMove> {
+    let board = position.board();
+    let moves = position.legal_moves();
+    let mut dests = Vec::new();
+
+    for legal_move in &moves {
+        let chess_move = match legal_move {
+            Move::Normal(chess_move) => chess_move,
+            Move::Drop(piece, square) => {
+                dests.push(LegalMove {
+                    from: None,
+                    to: square.to_string(),
+                    uci: legal_move.to_string(),
+                    kind: MoveKind::Drop,
+                    promotion: None,
+                    piece: Some(piece.to_string(Color::Black)),
+                });
+                continue;
             }
-            None => {
-                dests.insert(
-                    chess_move.get_source().to_string(),
-                    chess_move.get_dest().to_string(),
-                );
+        };
+
+        let source = chess_move.get_source();
+        let dest = chess_move.get_dest();
+        let castling = position.castling_side(*chess_move);
+
+        let kind = if chess_move.get_promotion().is_some() {
+            MoveKind::Promotion
+        } else if castling.is_some() {
+            MoveKind::Castle
+        } else if board.piece_on(source) == Some(Piece::Pawn)
+            && source.get_file() != dest.get_file()
+            && board.piece_on(dest).is_none()
+        {
+            MoveKind::EnPassant
+        } else {
+            MoveKind::Normal
+        };
+
+        if let Some(side) = castling {
+            let king_dest = Square::make_square(source.get_rank(), side.king_file());
+            let ambiguous = moves
+                .iter()
+                .any(|other| *other == Move::Normal(ChessMove::new(source, king_dest, None)));
+
+            if king_dest != source && !ambiguous {
+                dests.push(LegalMove {
+                    from: Some(source.to_string()),
+                    to: king_dest.to_string(),
+                    uci: format!("{}{}", source, king_dest),
+                    kind,
+                    promotion: None,
+                    piece: None,
+                });
             }
         }
+
+        dests.push(LegalMove {
+            from: Some(source.to_string()),
+            to: dest.to_string(),
+            uci: chess_move.to_string(),
+            kind,
+            promotion: chess_move
+                .get_promotion()
+                .map(|piece| piece.to_string(Color::Black)),
+            piece: None,
+        });
     }
 
     dests
 }
+
+/// Reads a move sent in UCI, castling can be written as the king moving
+/// onto its rook or to its destination and drops as in `N@f3`
+pub fn parse_move(position: &dyn Variant, uci: &str) -> Result<Move, MoveError> {
+    let parsed = Move::from_str(uci).map_err(|_| MoveError::Illegal)?;
+
+    if position.play(parsed).is_some() {
+        return Ok(parsed);
+    }
+
+    let chess_move = parsed.chess_move().ok_or(MoveError::Illegal)?;
+    let source = chess_move.get_source();
+    let dest = chess_move.get_dest();
+
+    let castle = position
+        .legal_moves()
+        .into_iter()
+        .filter_map(Move::chess_move)
+        .find(|other| {
+            other.get_source() == source
+                && position
+                    .castling_side(*other)
+                    .map(|side| Square::make_square(source.get_rank(), side.king_file()))
+                    == Some(dest)
+        });
+    if let (Some(castle), None) = (castle, chess_move.get_promotion()) {
+        return Ok(castle.into());
+    }
+
+    let unpromoted = position.board().piece_on(source) == Some(Piece::Pawn)
+        && chess_move.get_promotion().is_none();
+    if unpromoted
+        && position
+            .play(ChessMove::new(source, dest, Some(Piece::Queen)).into())
+            .is_some()
+    {
+        return Err(MoveError::PromotionRequired);
+    }
+
+    Err(MoveError::Illegal)
+}
+
+/// Writes `chess_move` in standard algebraic notation, `position` being the
+/// position before the move. Drops are written as in UCI.
+pub fn get_san(position: &dyn Variant, chess_move: Move) -> String {
+    let mut san = match chess_move {
+        Move::Normal(board_move) => get_board_san(position, board_move),
+        Move::Drop(..) => chess_move.to_string(),
+    };
+
+    if let Some(after) = position.play(chess_move) {
+        if after.is_checkmate() {
+            san.push('#');
+        } else if after.in_check() {
+            san.push('+');
+        }
+    }
+
+    san
+}
+
+/// Standard algebraic notation of a move on the board, without check
+fn get_board_san(position: &dyn Variant, chess_move: ChessMove) -> String {
+    let board = position.board();
+    let source = chess_move.get_source();
+    let dest = chess_move.get_dest();
+    let piece = board.piece_on(source).unwrap_or(Piece::Pawn);
+    let mut san = String::new();
+
+    let file_distance = source.get_file().to_index() as i8 - dest.get_file().to_index() as i8;
+
+    if let Some(side) = position.castling_side(chess_move) {
+        san.push_str(match side {
+            CastlingSide::King => "O-O",
+            CastlingSide::Queen => "O-O-O",
+        });
+    } else {
+        let capture = board.piece_on(dest).is_some() || (piece == Piece::Pawn && file_distance != 0);
+        let source_name = source.to_string();
+
+        if piece == Piece::Pawn {
+            if capture {
+                san.push_str(&source_name[..1]);
+            }
+        } else {
+            san.push_str(&piece.to_string(Color::White));
+
+            // Other pieces of the same kind that can reach the destination
+            let others: Vec<ChessMove> = MoveGen::new_legal(board)
+                .filter(|other| {
+                    other.get_dest() == dest
+                        && other.get_source() != source
+                        && board.piece_on(other.get_source()) == Some(piece)
+                })
+                .collect();
+
+            if !others.is_empty() {
+                let same_file = others
+                    .iter()
+                    .any(|other| other.get_source().get_file() == source.get_file());
+                let same_rank = others
+                    .iter()
+                    .any(|other| other.get_source().get_rank() == source.get_rank());
+
+                if !same_file {
+                    san.push_str(&source_name[..1]);
+                } else if !same_rank {
+                    san.push_str(&source_name[1..]);
+                } else {
+                    san.push_str(&source_name);
+                }
+            }
+        }
+
+        if capture {
+            san.push('x');
+        }
+
+        san.push_str(&dest.to_string());
+
+        if let Some(promotion) = chess_move.get_promotion() {
+            san.push('=');
+            san.push_str(&promotion.to_string(Color::White));
+        }
+    }
+
+    san
+}
+
+/// Replays `moves` from `position`, returning them in standard algebraic
+/// notation
+pub fn get_san_moves(position: &dyn Variant, moves: &[Move]) -> Vec<String> {
+    let mut position = position.box_clone();
+
+    moves
+        .iter()
+        .map(|chess_move| {
+            let san = get_san(position.as_ref(), *chess_move);
+            if let Some(next) = position.play(*chess_move) {
+                position = next;
+            }
+            san
+        })
+        .collect()
+}
+
+/// Squares of the same colour as a1
+const DARK_SQUARES: u64 = 0xAA55_AA55_AA55_AA55;
+
+/// How close a position is to a draw by repetition or by the fifty-move
+/// rule
+pub struct DrawCounters {
+    /// Times the position occurred, counting the current one
+    pub repetitions: usize,
+    /// Moves of both sides since the last capture or pawn move
+    pub halfmoves: usize,
+}
+
+/// Whether no sequence of legal moves can checkmate either king: kings
+/// alone, with a single minor piece, or with bishops all on one colour
+pub fn is_insufficient_material(board: &Board) -> bool {
+    let majors = board.pieces(Piece::Pawn) | board.pieces(Piece::Rook) | board.pieces(Piece::Queen);
+    if majors.popcnt() != 0 {
+        return false;
+    }
+
+    let knights = board.pieces(Piece::Knight).popcnt();
+    let bishops = *board.pieces(Piece::Bishop);
+    let dark = BitBoard::new(DARK_SQUARES);
+
+    match (knights, bishops.popcnt()) {
+        (0, 0) | (1, 0) | (0, 1) => true,
+        (0, _) => (bishops & dark).popcnt() == 0 || (bishops & !dark).popcnt() == 0,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::position::Position;
+    use crate::util::variant::Crazyhouse;
+
+    fn san(fen: &str, uci: &str) -> String {
+        let position = Position::from_fen(fen, false).unwrap();
+        let chess_move = parse_move(&position, uci).unwrap();
+
+        get_san(&position, chess_move)
+    }
+
+    #[test]
+    fn castling_is_written_with_letters() {
+        let fen = "r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1";
+
+        assert_eq!(san(fen, "e1g1"), "O-O");
+        assert_eq!(san(fen, "e1c1"), "O-O-O");
+    }
+
+    #[test]
+    fn ambiguous_pieces_give_their_file_then_their_rank() {
+        assert_eq!(san("4k3/8/8/8/8/5N2/8/1N2K3 w - - 0 1", "b1d2"), "Nbd2");
+        assert_eq!(san("4k3/8/8/R7/8/8/8/R3K3 w - - 0 1", "a1a3"), "R1a3");
+        assert_eq!(san("4k3/8/8/8/8/8/8/R3K2R w - - 0 1", "h1f1"), "Rf1");
+    }
+
+    #[test]
+    fn pawn_captures_give_their_file() {
+        assert_eq!(san("4k3/8/8/3Pp3/8/8/8/4K3 w - e6 0 1", "d5e6"), "dxe6");
+        assert_eq!(san("4k3/8/8/8/8/2p5/1P6/4K3 w - - 0 1", "b2c3"), "bxc3");
+    }
+
+    #[test]
+    fn promotions_and_checks_are_marked() {
+        assert_eq!(san("4k3/1P6/8/8/8/8/8/4K3 w - - 0 1", "b7b8q"), "b8=Q+");
+        assert_eq!(san("4k3/1P6/8/8/8/8/8/4K3 w - - 0 1", "b7b8n"), "b8=N");
+        assert_eq!(san("6k1/5ppp/8/8/8/8/8/R3K3 w - - 0 1", "a1a8"), "Ra8#");
+    }
+
+    fn position(fen: &str, chess960: bool) -> Position {
+        Position::from_fen(fen, chess960).unwrap()
+    }
+
+    fn dests(position: &dyn Variant) -> Vec<(String, MoveKind)> {
+        get_dests(position)
+            .into_iter()
+            .map(|dest| (dest.uci, dest.kind))
+            .collect()
+    }
+
+    fn normal(uci: &str) -> Result<Move, MoveError> {
+        Ok(Move::Normal(ChessMove::from_str(uci).unwrap()))
+    }
+
+    #[test]
+    fn promotions_need_their_piece() {
+        let position = position("4k3/1P6/8/8/8/8/8/4K3 w - - 0 1", false);
+
+        assert_eq!(
+            parse_move(&position, "b7b8"),
+            Err(MoveError::PromotionRequired)
+        );
+        assert_eq!(parse_move(&position, "b7b8n"), normal("b7b8n"));
+        assert_eq!(parse_move(&position, "e1e3"), Err(MoveError::Illegal));
+        assert_eq!(parse_move(&position, "b7"), Err(MoveError::Illegal));
+
+        let promotions: Vec<_> = get_dests(&position)
+            .into_iter()
+            .filter(|dest| dest.kind == MoveKind::Promotion)
+            .filter_map(|dest| dest.promotion)
+            .collect();
+        assert_eq!(promotions.len(), 4);
+        assert!(promotions.contains(&"q".to_string()));
+    }
+
+    #[test]
+    fn castling_reads_both_forms() {
+        let position = position("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1", false);
+
+        assert_eq!(parse_move(&position, "e1g1"), normal("e1h1"));
+        assert_eq!(parse_move(&position, "e1h1"), normal("e1h1"));
+        assert_eq!(parse_move(&position, "e1c1"), normal("e1a1"));
+
+        let dests = dests(&position);
+        for uci in ["e1g1", "e1h1", "e1c1", "e1a1"] {
+            assert!(dests.contains(&(uci.to_string(), MoveKind::Castle)));
+        }
+    }
+
+    #[test]
+    fn castling_only_onto_the_rook_when_the_king_move_exists() {
+        // The king can step to c1 without castling, and castles to g1 where
+        // its rook stands
+        let position = position("4k3/8/8/8/8/8/8/RK4R1 w AG - 0 1", true);
+        let dests = dests(&position);
+
+        assert!(dests.contains(&("b1a1".to_string(), MoveKind::Castle)));
+        assert!(dests.contains(&("b1c1".to_string(), MoveKind::Normal)));
+        assert!(!dests.contains(&("b1c1".to_string(), MoveKind::Castle)));
+        assert_eq!(dests.iter().filter(|(uci, _)| uci == "b1g1").count(), 1);
+
+        assert_eq!(parse_move(&position, "b1c1"), normal("b1c1"));
+        assert_eq!(parse_move(&position, "b1g1"), normal("b1g1"));
+    }
+
+    #[test]
+    fn en_passant_and_drops_have_their_kind() {
+        let position = position("4k3/8/8/3Pp3/8/8/8/4K3 w - e6 0 1", false);
+        assert!(dests(&position).contains(&("d5e6".to_string(), MoveKind::EnPassant)));
+
+        let crazyhouse = Crazyhouse::from_fen("4k3/8/8/8/8/8/8/4K3[N] w - - 0 1").unwrap();
+        let drop = get_dests(&crazyhouse)
+            .into_iter()
+            .find(|dest| dest.uci == "N@f3")
+            .unwrap();
+
+        assert_eq!(drop.kind, MoveKind::Drop);
+        assert_eq!(drop.from, None);
+        assert_eq!(drop.piece.as_deref(), Some("n"));
+        assert_eq!(
+            parse_move(&crazyhouse, "N@f3"),
+            Ok(Move::Drop(Piece::Knight, Square::F3))
+        );
+    }
+
+    fn insufficient(fen: &str) -> bool {
+        is_insufficient_material(&Board::from_str(fen).unwrap())
+    }
+
+    #[test]
+    fn lone_minor_pieces_cannot_mate() {
+        assert!(insufficient("4k3/8/8/8/8/8/8/4K3 w - - 0 1"));
+        assert!(insufficient("4k3/8/8/8/8/8/8/2B1K3 w - - 0 1"));
+        assert!(insufficient("4k3/8/8/8/8/8/8/1N2K3 b - - 0 1"));
+
+        assert!(!insufficient("4k3/8/8/8/8/8/8/1NN1K3 w - - 0 1"));
+        assert!(!insufficient("4k3/8/8/8/8/8/8/1N2K1b1 w - - 0 1"));
+        assert!(!insufficient("4k3/8/8/8/8/8/4P3/4K3 w - - 0 1"));
+        assert!(!insufficient("4k3/8/8/8/8/8/8/R3K3 w - - 0 1"));
+    }
+
+    #[test]
+    fn bishops_on_one_colour_cannot_mate() {
+        // c1, f4 and h6 are all dark squares
+        assert!(insufficient("4k3/8/7b/8/5B2/8/8/2B1K3 w - - 0 1"));
+        // d1 is light
+        assert!(!insufficient("4k3/8/7b/8/8/8/8/3BK3 w - - 0 1"));
+    }
+}
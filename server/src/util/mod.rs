@@ -1,2 +1,4 @@
 pub mod redis;
 pub mod chess;
+pub mod position;
+pub mod variant;
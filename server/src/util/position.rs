@@ -0,0 +1,568 @@
+use chess::{
+    get_rank, BitBoard, Board, BoardBuilder, ChessMove, Color, File, MoveGen, Piece, Rank, Square,
+    EMPTY,
+};
+use std::convert::TryFrom;
+use std::str::FromStr;
+
+/*
+   The `chess` crate only castles with the king on the e-file and the rooks
+   in the corners, so its boards are kept without castling rights and
+   castling is handled here for any Chess960 start position.
+
+   Castling moves are stored as the king moving onto its rook, which can't
+   be confused with another move. They are written that way in UCI for
+   Chess960 games and as the king moving two squares for standard ones.
+*/
+
+/// Number of the standard start position among the Chess960 ones
+pub const STANDARD_POSITION: u16 = 518;
+
+/// Squares of the two knights among the five left once the bishops and the
+/// queen are placed, by knight code of the Scharnagl numbering
+const KNIGHTS: [(usize, usize); 10] = [
+    (0, 1),
+    (0, 2),
+    (0, 3),
+    (0, 4),
+    (1, 2),
+    (1, 3),
+    (1, 4),
+    (2, 3),
+    (2, 4),
+    (3, 4),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CastlingSide {
+    /// Towards the h-file
+    King,
+    /// Towards the a-file
+    Queen,
+}
+
+impl CastlingSide {
+    const ALL: [CastlingSide; 2] = [CastlingSide::King, CastlingSide::Queen];
+
+    fn to_index(self) -> usize {
+        self as usize
+    }
+
+    /// File the king ends on
+    pub fn king_file(self) -> File {
+        match self {
+            CastlingSide::King => File::G,
+            CastlingSide::Queen => File::C,
+        }
+    }
+
+    /// File the rook ends on
+    pub fn rook_file(self) -> File {
+        match self {
+            CastlingSide::King => File::F,
+            CastlingSide::Queen => File::D,
+        }
+    }
+}
+
+/// Position of a standard or Chess960 game
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Position {
+    /// Board without any castling rights, see `castling`
+    board: Board,
+    /// Files of the rooks each color can still castle with, by color and
+    /// side index
+    castling: [[Option<File>; 2]; 2],
+    /// Moves of both sides since the last capture or pawn move
+    halfmoves: usize,
+    fullmoves: usize,
+    chess960: bool,
+}
+
+impl Position {
+    pub fn standard() -> Self {
+        Position {
+            chess960: false,
+            ..Position::chess960(STANDARD_POSITION)
+        }
+    }
+
+    /// Start position `number` of Chess960, from 0 to 959 in Scharnagl's
+    /// numbering
+    pub fn chess960(number: u16) -> Self {
+        let mut code = number as usize % 960;
+        let mut back_rank: [Option<Piece>; 8] = [None; 8];
+
+        back_rank[code % 4 * 2 + 1] = Some(Piece::Bishop);
+        code /= 4;
+        back_rank[code % 4 * 2] = Some(Piece::Bishop);
+        code /= 4;
+        place(&mut back_rank, code % 6, Piece::Queen);
+        code /= 6;
+
+        // The second knight goes first so the first one keeps its index
+        let (first_knight, second_knight) = KNIGHTS[code];
+        place(&mut back_rank, second_knight, Piece::Knight);
+        place(&mut back_rank, first_knight, Piece::Knight);
+
+        for piece in [Piece::Rook, Piece::King, Piece::Rook] {
+            place(&mut back_rank, 0, piece);
+        }
+
+        let mut builder = BoardBuilder::new();
+        for (index, piece) in back_rank.iter().enumerate() {
+            let file = File::from_index(index);
+            let piece = piece.unwrap_or(Piece::Rook);
+
+            builder
+                .piece(Square::make_square(Rank::First, file), piece, Color::White)
+                .piece(Square::make_square(Rank::Eighth, file), piece, Color::Black)
+                .piece(
+                    Square::make_square(Rank::Second, file),
+                    Piece::Pawn,
+                    Color::White,
+                )
+                .piece(
+                    Square::make_square(Rank::Seventh, file),
+                    Piece::Pawn,
+                    Color::Black,
+                );
+        }
+
+        let rooks: Vec<File> = (0..8)
+            .filter(|index| back_rank[*index] == Some(Piece::Rook))
+            .map(File::from_index)
+            .collect();
+        let rights = [rooks.last().copied(), rooks.first().copied()];
+
+        Position {
+            board: Board::try_from(builder).unwrap_or_default(),
+            castling: [rights, rights],
+            halfmoves: 0,
+            fullmoves: 1,
+            chess960: true,
+        }
+    }
+
+    /// Reads a position in X-FEN or Shredder-FEN, castling rights naming
+    /// the file of their rook or the outermost rook of a side
+    pub fn from_fen(fen: &str, chess960: bool) -> Option<Self> {
+        let fields: Vec<&str> = fen.split_whitespace().collect();
+        if fields.len() < 4 {
+            return None;
+        }
+
+        let board =
+            Board::from_str(&format!("{} {} - {}", fields[0], fields[1], fields[3])).ok()?;
+        let mut castling = [[None; 2]; 2];
+
+        for c in fields[2].chars().filter(|c| *c != '-') {
+            let color = if c.is_ascii_uppercase() {
+                Color::White
+            } else {
+                Color::Black
+            };
+            let king = board.king_square(color);
+            if king.get_rank() != color.to_my_backrank() {
+                return None;
+            }
+
+            let rooks = back_rank_rooks(&board, color);
+            let file = match c.to_ascii_lowercase() {
+                'k' => rooks
+                    .iter()
+                    .copied()
+                    .filter(|file| *file > king.get_file())
+                    .max_by_key(|file| file.to_index()),
+                'q' => rooks
+                    .iter()
+                    .copied()
+                    .filter(|file| *file < king.get_file())
+                    .min_by_key(|file| file.to_index()),
+                letter @ 'a'..='h' => Some(File::from_index(letter as usize - 'a' as usize))
+                    .filter(|file| rooks.contains(file)),
+                _ => None,
+            }?;
+
+            let side = if file > king.get_file() {
+                CastlingSide::King
+            } else {
+                CastlingSide::Queen
+            };
+            castling[color.to_index()][side.to_index()] = Some(file);
+        }
+
+        Some(Position {
+            board,
+            castling,
+            halfmoves: fields.get(4).and_then(|n| n.parse().ok()).unwrap_or(0),
+            fullmoves: fields.get(5).and_then(|n| n.parse().ok()).unwrap_or(1),
+            chess960,
+        })
+    }
+
+    /// Writes the position in X-FEN: castling rights are written KQkq
+    /// unless another rook stands between the castling rook and the edge
+    pub fn fen(&self) -> String {
+        let board = self.board.to_string();
+        let fields: Vec<&str> = board.split_whitespace().collect();
+        let mut castling = String::new();
+
+        for color in [Color::White, Color::Black] {
+            let rooks = back_rank_rooks(&self.board, color);
+
+            for side in CastlingSide::ALL {
+                let file = match self.castling[color.to_index()][side.to_index()] {
+                    Some(file) => file,
+                    None => continue,
+                };
+
+                let hidden = rooks.iter().any(|other| match side {
+                    CastlingSide::King => *other > file,
+                    CastlingSide::Queen => *other < file,
+                });
+                let letter = match side {
+                    _ if hidden => (b'a' + file.to_index() as u8) as char,
+                    CastlingSide::King => 'k',
+                    CastlingSide::Queen => 'q',
+                };
+
+                castling.push(match color {
+                    Color::White => letter.to_ascii_uppercase(),
+                    Color::Black => letter,
+                });
+            }
+        }
+
+        if castling.is_empty() {
+            castling.push('-');
+        }
+
+        format!(
+            "{} {} {} {} {} {}",
+            fields[0], fields[1], castling, fields[3], self.halfmoves, self.fullmoves
+        )
+    }
+
+    pub fn board(&self) -> &Board {
+        &self.board
+    }
+
+    pub fn side_to_move(&self) -> Color {
+        self.board.side_to_move()
+    }
+
+    pub fn halfmoves(&self) -> usize {
+        self.halfmoves
+    }
+
+    /// Identifies the position for repetitions: the pieces, the side to
+    /// move, the en passant square and the castling rights
+    pub fn repetition_key(&self) -> u64 {
+        let rights = self.castling.iter().flatten().fold(0, |rights, file| {
+            rights << 4 | file.map_or(0, |file| file.to_index() as u64 + 1)
+        });
+
+        self.board.get_hash() ^ mix(rights)
+    }
+
+    /// Side `chess_move` castles to, if it is the king moving onto a rook it
+    /// can castle with
+    pub fn castling_side(&self, chess_move: ChessMove) -> Option<CastlingSide> {
+        let color = self.board.side_to_move();
+        let rank = color.to_my_backrank();
+
+        if chess_move.get_source() != self.board.king_square(color)
+            || chess_move.get_promotion().is_some()
+        {
+            return None;
+        }
+
+        CastlingSide::ALL.iter().copied().find(|side| {
+            self.castling[color.to_index()][side.to_index()]
+                .map(|file| Square::make_square(rank, file))
+                == Some(chess_move.get_dest())
+        })
+    }
+
+    /// Every legal move, castling being the king moving onto its rook
+    pub fn legal_moves(&self) -> Vec<ChessMove> {
+        let color = self.board.side_to_move();
+        let king = self.board.king_square(color);
+        let mut moves: Vec<ChessMove> = MoveGen::new_legal(&self.board).collect();
+
+        for side in CastlingSide::ALL {
+            let rook = self.castling[color.to_index()][side.to_index()];
+
+            if let (Some(file), Some(_)) = (rook, self.castle(side)) {
+                let rook = Square::make_square(king.get_rank(), file);
+                moves.push(ChessMove::new(king, rook, None));
+            }
+        }
+
+        moves
+    }
+
+    /// Position after `chess_move`, if it is legal
+    pub fn play(&self, chess_move: ChessMove) -> Option<Position> {
+        let color = self.board.side_to_move();
+        let source = chess_move.get_source();
+        let dest = chess_move.get_dest();
+        let mut next = *self;
+
+        if let Some(side) = self.castling_side(chess_move) {
+            next.board = self.castle(side)?;
+            next.castling[color.to_index()] = [None; 2];
+            next.halfmoves += 1;
+        } else {
+            if !self.board.legal(chess_move) {
+                return None;
+            }
+
+            let piece = self.board.piece_on(source);
+            next.board = self.board.make_move_new(chess_move);
+            next.halfmoves = if piece == Some(Piece::Pawn) || self.board.piece_on(dest).is_some() {
+                0
+            } else {
+                self.halfmoves + 1
+            };
+
+            if piece == Some(Piece::King) {
+                next.castling[color.to_index()] = [None; 2];
+            }
+
+            // Rooks that move or get captured can't castle anymore
+            for color in [Color::White, Color::Black] {
+                for right in next.castling[color.to_index()].iter_mut() {
+                    let square =
+                        right.map(|file| Square::make_square(color.to_my_backrank(), file));
+                    if square == Some(source) || square == Some(dest) {
+                        *right = None;
+                    }
+                }
+            }
+        }
+
+        if color == Color::Black {
+            next.fullmoves += 1;
+        }
+
+        Some(next)
+    }
+
+    /// Position after the side to move puts `piece` on the empty `square`,
+    /// if its king isn't left in check. Pawns can't be put on the first or
+    /// last rank.
+    pub fn drop_piece(&self, piece: Piece, square: Square) -> Option<Position> {
+        let color = self.board.side_to_move();
+        let back_ranks = get_rank(Rank::First) | get_rank(Rank::Eighth);
+
+        if piece == Piece::King
+            || self.board.piece_on(square).is_some()
+            || (piece == Piece::Pawn && back_ranks & BitBoard::from_square(square) != EMPTY)
+        {
+            return None;
+        }
+
+        let mut builder = BoardBuilder::from(self.board);
+        builder
+            .piece(square, piece, color)
+            .side_to_move(!color)
+            .en_passant(None);
+
+        // Like a pawn move, a drop can't be undone
+        Some(Position {
+            board: Board::try_from(builder).ok()?,
+            halfmoves: 0,
+            fullmoves: self.fullmoves + (color == Color::Black) as usize,
+            ..*self
+        })
+    }
+
+    /// Writes `chess_move` in UCI
+    pub fn uci(&self, chess_move: ChessMove) -> String {
+        match self.castling_side(chess_move) {
+            Some(side) if !self.chess960 => {
+                let source = chess_move.get_source();
+                let dest = Square::make_square(source.get_rank(), side.king_file());
+                format!("{}{}", source, dest)
+            }
+            _ => chess_move.to_string(),
+        }
+    }
+
+    /// Board after castling to `side`, if the side to move can: the squares
+    /// the king and the rook cross must be empty and the king can't be in
+    /// check, cross an attacked square or end in check
+    fn castle(&self, side: CastlingSide) -> Option<Board> {
+        let color = self.board.side_to_move();
+        let file = self.castling[color.to_index()][side.to_index()]?;
+
+        if self.board.checkers().popcnt() != 0 {
+            return None;
+        }
+
+        let rank = color.to_my_backrank();
+        let king = self.board.king_square(color);
+        let rook = Square::make_square(rank, file);
+        let king_dest = Square::make_square(rank, side.king_file());
+        let rook_dest = Square::make_square(rank, side.rook_file());
+
+        let king_path = rank_span(king, king_dest);
+        let others =
+            *self.board.combined() & !BitBoard::from_square(king) & !BitBoard::from_square(rook);
+        if (king_path | rank_span(rook, rook_dest)) & others != EMPTY {
+            return None;
+        }
+
+        let mut builder = BoardBuilder::from(self.board);
+        builder
+            .clear_square(king)
+            .clear_square(rook)
+            .en_passant(None);
+
+        for square in king_path {
+            let mut crossing = builder;
+            crossing.piece(square, Piece::King, color);
+
+            if Board::try_from(crossing).ok()?.checkers().popcnt() != 0 {
+                return None;
+            }
+        }
+
+        builder
+            .piece(king_dest, Piece::King, color)
+            .piece(rook_dest, Piece::Rook, color)
+            .side_to_move(!color);
+
+        Board::try_from(builder).ok()
+    }
+}
+
+/// Spreads the bits of `value` to combine it with a position hash
+pub fn mix(value: u64) -> u64 {
+    value.wrapping_mul(0x9E37_79B9_7F4A_7C15)
+}
+
+/// Puts `piece` on the `empty_index`-th empty square of `back_rank`
+fn place(back_rank: &mut [Option<Piece>; 8], empty_index: usize, piece: Piece) {
+    if let Some(square) = back_rank
+        .iter_mut()
+        .filter(|square| square.is_none())
+        .nth(empty_index)
+    {
+        *square = Some(piece);
+    }
+}
+
+fn back_rank_rooks(board: &Board, color: Color) -> Vec<File> {
+    let rooks = board.pieces(Piece::Rook) & board.color_combined(color);
+
+    (rooks & get_rank(color.to_my_backrank()))
+        .map(|square| square.get_file())
+        .collect()
+}
+
+/// Squares of a rank from `from` to `to`, both included
+fn rank_span(from: Square, to: Square) -> BitBoard {
+    let (low, high) = if from.get_file() < to.get_file() {
+        (from.get_file(), to.get_file())
+    } else {
+        (to.get_file(), from.get_file())
+    };
+
+    (low.to_index()..=high.to_index()).fold(EMPTY, |squares, index| {
+        squares
+            | BitBoard::from_square(Square::make_square(
+                from.get_rank(),
+                File::from_index(index),
+            ))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn square(name: &str) -> Square {
+        Square::from_str(name).unwrap()
+    }
+
+    fn chess_move(uci: &str) -> ChessMove {
+        ChessMove::new(square(&uci[..2]), square(&uci[2..4]), None)
+    }
+
+    #[test]
+    fn start_positions_follow_the_scharnagl_numbering() {
+        assert_eq!(
+            Position::chess960(STANDARD_POSITION).fen(),
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1"
+        );
+        assert_eq!(
+            Position::chess960(0).fen(),
+            "bbqnnrkr/pppppppp/8/8/8/8/PPPPPPPP/BBQNNRKR w KQkq - 0 1"
+        );
+        assert_eq!(
+            Position::chess960(959).fen(),
+            "rkrnnqbb/pppppppp/8/8/8/8/PPPPPPPP/RKRNNQBB w KQkq - 0 1"
+        );
+    }
+
+    #[test]
+    fn castles_onto_the_rook() {
+        let position = Position::from_fen("4k3/8/8/8/8/8/8/RK4R1 w AG - 0 1", true).unwrap();
+        let castling = chess_move("b1g1");
+
+        assert_eq!(position.castling_side(castling), Some(CastlingSide::King));
+        assert!(position.legal_moves().contains(&castling));
+        assert_eq!(position.uci(castling), "b1g1");
+        assert_eq!(
+            position.play(castling).unwrap().fen(),
+            "4k3/8/8/8/8/8/8/R4RK1 b - - 1 1"
+        );
+
+        let queenside = position.play(chess_move("b1a1")).unwrap();
+        assert_eq!(queenside.fen(), "4k3/8/8/8/8/8/8/2KR2R1 b - - 1 1");
+    }
+
+    #[test]
+    fn standard_castling_is_written_as_a_king_move() {
+        let position =
+            Position::from_fen("r3k2r/pppppppp/8/8/8/8/PPPPPPPP/R3K2R w KQkq - 0 1", false)
+                .unwrap();
+
+        assert_eq!(position.uci(chess_move("e1h1")), "e1g1");
+        assert_eq!(position.uci(chess_move("e1a1")), "e1c1");
+        assert_eq!(position.castling_side(chess_move("e1g1")), None);
+    }
+
+    #[test]
+    fn castling_needs_a_free_and_safe_path() {
+        let attacked = Position::from_fen("4kr2/8/8/8/8/8/8/RK4R1 w AG - 0 1", true).unwrap();
+        assert!(attacked.play(chess_move("b1g1")).is_none());
+        assert!(attacked.play(chess_move("b1a1")).is_some());
+
+        let blocked = Position::from_fen("4k3/8/8/8/8/8/8/RK1N2R1 w AG - 0 1", true).unwrap();
+        assert!(blocked.play(chess_move("b1g1")).is_none());
+    }
+
+    #[test]
+    fn moving_a_rook_loses_its_right() {
+        let position = Position::from_fen("4k3/8/8/8/8/8/8/RK4R1 w AG - 0 1", true).unwrap();
+        let moved = position.play(chess_move("g1g2")).unwrap();
+
+        assert_eq!(moved.fen(), "4k3/8/8/8/8/8/6R1/RK6 b Q - 1 1");
+    }
+
+    #[test]
+    fn hidden_rooks_are_written_with_their_file() {
+        let fen = "4k3/8/8/8/8/8/8/RRK5 w B - 0 1";
+        let position = Position::from_fen(fen, true).unwrap();
+
+        assert_eq!(
+            position.castling_side(chess_move("c1b1")),
+            Some(CastlingSide::Queen)
+        );
+        assert_eq!(position.fen(), fen);
+    }
+}
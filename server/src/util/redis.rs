@@ -1,5 +1,33 @@
+use actix::prelude::*;
+use actix_redis::{Command, RedisActor};
+use color_eyre::Result;
+use eyre::eyre;
 use redis_async::resp::RespValue;
+use redis_async::resp_array;
 use std::collections::HashMap;
+use std::time::Duration;
+
+/// Builds a HSET command setting every field of `fields`
+pub fn hset(key: String, fields: Vec<(&str, String)>) -> Command {
+    let mut args = vec![RespValue::from("HSET"), RespValue::from(key)];
+
+    for (field, value) in fields {
+        args.push(RespValue::from(field));
+        args.push(RespValue::from(value));
+    }
+
+    Command(RespValue::Array(args))
+}
+
+/// Builds an EXPIRE command making `key` expire after `ttl`
+pub fn expire(key: String, ttl: Duration) -> Command {
+    Command(resp_array!["EXPIRE", key, ttl.as_secs().to_string()])
+}
+
+/// Builds a PERSIST command removing the expiry of `key`
+pub fn persist(key: String) -> Command {
+    Command(resp_array!["PERSIST", key])
+}
 
 pub fn get_hashmap(redis_hash: RespValue) -> Option<HashMap<String, String>> {
     if let RespValue::Array(redis_array) = redis_hash {
@@ -34,3 +62,42 @@ pub fn get_hashmap(redis_hash: RespValue) -> Option<HashMap<String, String>> {
         None
     }
 }
+
+/// Collects every key matching `pattern`, without blocking Redis like KEYS
+pub async fn scan_keys(redis: &Addr<RedisActor>, pattern: &str) -> Result<Vec<String>> {
+    let mut keys = Vec::new();
+    let mut cursor = "0".to_string();
+
+    loop {
+        let resp = redis
+            .send(Command(resp_array!["SCAN", cursor, "MATCH", pattern, "COUNT", "100"]))
+            .await??;
+
+        match resp {
+            RespValue::Array(mut items) if items.len() == 2 => {
+                if let RespValue::Array(batch) = items.pop().unwrap() {
+                    keys.extend(batch.into_iter().filter_map(|key| match key {
+                        RespValue::BulkString(key) => Some(String::from_utf8_lossy(&key).to_string()),
+                        _ => None,
+                    }));
+                }
+
+                cursor = match items.pop().unwrap() {
+                    RespValue::BulkString(cursor) => String::from_utf8_lossy(&cursor).to_string(),
+                    _ => return Err(eyre!("unexpected SCAN cursor")),
+                };
+            }
+            other => return Err(eyre!("unexpected SCAN response: {:?}", other)),
+        }
+
+        if cursor == "0" {
+            return Ok(keys);
+        }
+    }
+}
+
+pub async fn hgetall(redis: &Addr<RedisActor>, key: &str) -> Result<HashMap<String, String>> {
+    let resp = redis.send(Command(resp_array!["HGETALL", key])).await??;
+
+    get_hashmap(resp).ok_or_else(|| eyre!("unexpected HGETALL response for {}", key))
+}
@@ -0,0 +1,131 @@
+use super::{Crazyhouse, Move, Pockets, Variant};
+use crate::actors::room::GameEndResult;
+use crate::util::position::Position;
+
+use chess::{between, Color, Piece, EMPTY};
+
+/// A board of bughouse: crazyhouse where captured pieces go to the partner
+/// of the mover on the other board, drops come from the partner's captures
+#[derive(Debug, Clone, Copy)]
+pub struct Bughouse {
+    board: Crazyhouse,
+}
+
+impl Bughouse {
+    pub fn new(position: Position) -> Self {
+        Bughouse {
+            board: Crazyhouse::new(position),
+        }
+    }
+
+    /// Reads an X-FEN with pockets, as crazyhouse does
+    pub fn from_fen(fen: &str) -> Option<Self> {
+        Some(Bughouse {
+            board: Crazyhouse::from_fen(fen)?,
+        })
+    }
+
+    /// Position after `chess_move`, if it is legal, with the piece it
+    /// captured for the partner of the mover
+    pub fn play_capturing(&self, chess_move: Move) -> Option<(Self, Option<Piece>)> {
+        let (board, captured) = self.board.play_capturing(chess_move)?;
+
+        Some((Bughouse { board }, captured))
+    }
+
+    /// Puts a piece captured on the other board in the pocket of `color`
+    pub fn receive(&mut self, color: Color, piece: Piece) {
+        self.board.receive(color, piece);
+    }
+}
+
+impl Variant for Bughouse {
+    fn position(&self) -> &Position {
+        self.board.position()
+    }
+
+    fn box_clone(&self) -> Box<dyn Variant> {
+        Box::new(*self)
+    }
+
+    fn play(&self, chess_move: Move) -> Option<Box<dyn Variant>> {
+        self.play_capturing(chess_move)
+            .map(|(next, _)| Box::new(next) as Box<dyn Variant>)
+    }
+
+    fn legal_moves(&self) -> Vec<Move> {
+        self.board.legal_moves()
+    }
+
+    fn pockets(&self) -> Option<Pockets> {
+        self.board.pockets()
+    }
+
+    /// A side without moves waits for its partner to send a piece, only a
+    /// check no drop could ever block is checkmate
+    fn result(&self) -> Option<GameEndResult> {
+        if !self.in_check() || !self.legal_moves().is_empty() {
+            return None;
+        }
+
+        let board = self.board();
+        let king = board.king_square(board.side_to_move());
+        let checkers = *board.checkers();
+        let blockable = checkers.popcnt() == 1
+            && checkers
+                .into_iter()
+                .any(|checker| between(checker, king) != EMPTY);
+
+        if blockable {
+            None
+        } else {
+            Some(GameEndResult::checkmates(!self.side_to_move()))
+        }
+    }
+
+    /// Captured pieces come back, material never runs out
+    fn is_insufficient_material(&self) -> bool {
+        false
+    }
+
+    fn fen(&self) -> String {
+        self.board.fen()
+    }
+
+    fn repetition_key(&self) -> u64 {
+        self.board.repetition_key()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(fen: &str) -> Option<GameEndResult> {
+        Bughouse::from_fen(fen).unwrap().result()
+    }
+
+    #[test]
+    fn contact_checks_are_mate() {
+        assert!(matches!(
+            result("k7/1Q6/1K6/8/8/8/8/8[] b - - 0 1"),
+            Some(GameEndResult::WhiteCheckmates)
+        ));
+        assert!(matches!(
+            result("6rk/5Npp/8/8/8/8/8/K7[] b - - 0 1"),
+            Some(GameEndResult::WhiteCheckmates)
+        ));
+    }
+
+    #[test]
+    fn players_wait_for_a_piece_to_block() {
+        // Nothing to drop yet, but the partner may send one
+        assert!(result("k7/8/1K6/8/8/8/8/7R[] b - - 0 1").is_none());
+    }
+
+    #[test]
+    fn positions_without_moves_go_on() {
+        assert!(result("k7/2Q5/1K6/8/8/8/8/8[] b - - 0 1").is_none());
+        assert!(result(&Position::standard().fen()).is_none());
+    }
+}
@@ -0,0 +1,455 @@
+use super::{Move, Variant};
+use crate::util::position::{mix, Position};
+
+use chess::{BitBoard, Color, File, Piece, Rank, Square, EMPTY};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// Pieces that can be dropped, in the order pockets are written
+const POCKET_PIECES: [Piece; 5] = [
+    Piece::Queen,
+    Piece::Rook,
+    Piece::Bishop,
+    Piece::Knight,
+    Piece::Pawn,
+];
+
+/// Pieces a side has captured and can drop
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Pocket {
+    pub pawn: u8,
+    pub knight: u8,
+    pub bishop: u8,
+    pub rook: u8,
+    pub queen: u8,
+}
+
+impl Pocket {
+    pub fn count(&self, piece: Piece) -> u8 {
+        match piece {
+            Piece::Pawn => self.pawn,
+            Piece::Knight => self.knight,
+            Piece::Bishop => self.bishop,
+            Piece::Rook => self.rook,
+            Piece::Queen => self.queen,
+            Piece::King => 0,
+        }
+    }
+
+    fn count_mut(&mut self, piece: Piece) -> Option<&mut u8> {
+        match piece {
+            Piece::Pawn => Some(&mut self.pawn),
+            Piece::Knight => Some(&mut self.knight),
+            Piece::Bishop => Some(&mut self.bishop),
+            Piece::Rook => Some(&mut self.rook),
+            Piece::Queen => Some(&mut self.queen),
+            Piece::King => None,
+        }
+    }
+}
+
+/// Pockets of both sides
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Pockets {
+    pub white: Pocket,
+    pub black: Pocket,
+}
+
+impl Pockets {
+    pub fn get(&self, color: Color) -> &Pocket {
+        match color {
+            Color::White => &self.white,
+            Color::Black => &self.black,
+        }
+    }
+
+    fn get_mut(&mut self, color: Color) -> &mut Pocket {
+        match color {
+            Color::White => &mut self.white,
+            Color::Black => &mut self.black,
+        }
+    }
+
+    /// Puts `piece` in the pocket of `color`, a captured king can't happen
+    fn add(&mut self, color: Color, piece: Piece) {
+        if let Some(count) = self.get_mut(color).count_mut(piece) {
+            *count = count.saturating_add(1);
+        }
+    }
+
+    /// Takes `piece` out of the pocket of `color`, if it is there
+    fn remove(&mut self, color: Color, piece: Piece) -> Option<()> {
+        let count = self.get_mut(color).count_mut(piece)?;
+        *count = count.checked_sub(1)?;
+        Some(())
+    }
+
+    /// Packs the counts of every piece for repetitions
+    fn key(&self) -> u64 {
+        [Color::White, Color::Black]
+            .iter()
+            .flat_map(|color| {
+                POCKET_PIECES
+                    .iter()
+                    .map(move |piece| self.get(*color).count(*piece))
+            })
+            .fold(0, |key, count| key << 5 | u64::from(count))
+    }
+}
+
+/// Writes the pieces of both pockets as in the brackets of a crazyhouse FEN,
+/// white ones in uppercase, as in `QNpp`
+impl fmt::Display for Pockets {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for color in [Color::White, Color::Black] {
+            for piece in POCKET_PIECES {
+                for _ in 0..self.get(color).count(piece) {
+                    write!(f, "{}", piece.to_string(color))?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl FromStr for Pockets {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut pockets = Pockets::default();
+
+        for c in s.chars() {
+            let color = if c.is_ascii_uppercase() {
+                Color::White
+            } else {
+                Color::Black
+            };
+            let piece = POCKET_PIECES
+                .iter()
+                .copied()
+                .find(|piece| piece.to_string(color) == c.to_string())
+                .ok_or(())?;
+
+            pockets.add(color, piece);
+        }
+
+        Ok(pockets)
+    }
+}
+
+/// Standard chess where captured pieces change sides and can be dropped back
+/// on the board instead of moving
+#[derive(Debug, Clone, Copy)]
+pub struct Crazyhouse {
+    position: Position,
+    pockets: Pockets,
+    /// Pieces that were pawns, they go back to the pockets as pawns
+    promoted: BitBoard,
+}
+
+impl Crazyhouse {
+    pub fn new(position: Position) -> Self {
+        Crazyhouse {
+            position,
+            pockets: Pockets::default(),
+            promoted: EMPTY,
+        }
+    }
+
+    /// Reads an X-FEN with the pockets in brackets after the pieces and the
+    /// promoted pieces followed by `~`, as in `rnb~qkbnr/.../RNBQKBNR[Pp] w`
+    pub fn from_fen(fen: &str) -> Option<Self> {
+        let mut fields: Vec<&str> = fen.split_whitespace().collect();
+        let placement = fields.first()?.to_string();
+
+        let (placement, pockets) = match placement.split_once('[') {
+            Some((placement, pockets)) => (
+                placement,
+                pockets.strip_suffix(']')?.parse::<Pockets>().ok()?,
+            ),
+            None => (placement.as_str(), Pockets::default()),
+        };
+
+        let mut pieces = String::new();
+        let mut promoted = EMPTY;
+        let (mut rank, mut file): (usize, usize) = (7, 0);
+
+        for c in placement.chars() {
+            match c {
+                '/' => {
+                    rank = rank.checked_sub(1)?;
+                    file = 0;
+                }
+                '~' => promoted |= BitBoard::from_square(square(rank, file.checked_sub(1)?)),
+                '1'..='8' => file += c as usize - '0' as usize,
+                _ => file += 1,
+            }
+
+            if file > 8 {
+                return None;
+            }
+            if c != '~' {
+                pieces.push(c);
+            }
+        }
+
+        fields[0] = &pieces;
+        let position = Position::from_fen(&fields.join(" "), false)?;
+
+        Some(Crazyhouse {
+            promoted: promoted & position.board().combined(),
+            position,
+            pockets,
+        })
+    }
+
+    /// Plays `chess_move` and hands back the piece it captured instead of
+    /// putting it in a pocket, a promoted piece is captured as a pawn
+    pub(super) fn play_capturing(&self, chess_move: Move) -> Option<(Self, Option<Piece>)> {
+        let board = self.position.board();
+        let color = board.side_to_move();
+        let mut pockets = self.pockets;
+        let mut promoted = self.promoted;
+        let mut taken = None;
+
+        let position = match chess_move {
+            Move::Drop(piece, square) => {
+                pockets.remove(color, piece)?;
+                self.position.drop_piece(piece, square)?
+            }
+            Move::Normal(chess_move) => {
+                let source = chess_move.get_source();
+                let dest = chess_move.get_dest();
+
+                // Castling moves the king onto its own rook
+                let captured = if self.position.castling_side(chess_move).is_some() {
+                    None
+                } else if let Some(piece) = board.piece_on(dest) {
+                    Some((piece, dest))
+                } else if board.piece_on(source) == Some(Piece::Pawn)
+                    && source.get_file() != dest.get_file()
+                {
+                    Some((
+                        Piece::Pawn,
+                        Square::make_square(source.get_rank(), dest.get_file()),
+                    ))
+                } else {
+                    None
+                };
+
+                let position = self.position.play(chess_move)?;
+
+                if let Some((piece, square)) = captured {
+                    let square = BitBoard::from_square(square);
+                    if promoted & square != EMPTY {
+                        taken = Some(Piece::Pawn);
+                    } else {
+                        taken = Some(piece);
+                    }
+                    promoted &= !square;
+                }
+
+                if promoted & BitBoard::from_square(source) != EMPTY
+                    || chess_move.get_promotion().is_some()
+                {
+                    promoted &= !BitBoard::from_square(source);
+                    promoted |= BitBoard::from_square(dest);
+                }
+
+                position
+            }
+        };
+
+        let next = Crazyhouse {
+            position,
+            pockets,
+            promoted,
+        };
+
+        Some((next, taken))
+    }
+
+    /// Puts `piece` in the pocket of `color`
+    pub(super) fn receive(&mut self, color: Color, piece: Piece) {
+        self.pockets.add(color, piece);
+    }
+
+    /// Drops of the side to move that don't leave its king in check
+    fn drops(&self) -> Vec<Move> {
+        let board = self.position.board();
+        let pocket = self.pockets.get(board.side_to_move());
+        let empty = !*board.combined();
+
+        POCKET_PIECES
+            .iter()
+            .filter(|piece| pocket.count(**piece) != 0)
+            .flat_map(|piece| empty.map(move |square| Move::Drop(*piece, square)))
+            .filter(|drop| self.play(*drop).is_some())
+            .collect()
+    }
+}
+
+impl Variant for Crazyhouse {
+    fn position(&self) -> &Position {
+        &self.position
+    }
+
+    fn box_clone(&self) -> Box<dyn Variant> {
+        Box::new(*self)
+    }
+
+    fn play(&self, chess_move: Move) -> Option<Box<dyn Variant>> {
+        let (mut next, captured) = self.play_capturing(chess_move)?;
+
+        if let Some(piece) = captured {
+            next.receive(self.position.board().side_to_move(), piece);
+        }
+
+        Some(Box::new(next))
+    }
+
+    fn legal_moves(&self) -> Vec<Move> {
+        let mut moves: Vec<Move> = self
+            .position
+            .legal_moves()
+            .into_iter()
+            .map(Move::from)
+            .collect();
+        moves.extend(self.drops());
+
+        moves
+    }
+
+    fn pockets(&self) -> Option<Pockets> {
+        Some(self.pockets)
+    }
+
+    /// Captured pieces come back, material never runs out
+    fn is_insufficient_material(&self) -> bool {
+        false
+    }
+
+    fn fen(&self) -> String {
+        let fen = self.position.fen();
+        let (placement, rest) = fen.split_once(' ').unwrap_or((&fen, ""));
+        let mut pieces = String::new();
+        let (mut rank, mut file) = (7, 0);
+
+        for c in placement.chars() {
+            pieces.push(c);
+
+            match c {
+                '/' => {
+                    rank -= 1;
+                    file = 0;
+                }
+                '1'..='8' => file += c as usize - '0' as usize,
+                _ => {
+                    if self.promoted & BitBoard::from_square(square(rank, file)) != EMPTY {
+                        pieces.push('~');
+                    }
+                    file += 1;
+                }
+            }
+        }
+
+        format!("{}[{}] {}", pieces, self.pockets, rest)
+    }
+
+    fn repetition_key(&self) -> u64 {
+        self.position.repetition_key()
+            ^ mix(self.pockets.key() + 1)
+            ^ mix(self.promoted.0).rotate_left(32)
+    }
+}
+
+fn square(rank: usize, file: usize) -> Square {
+    Square::make_square(Rank::from_index(rank), File::from_index(file))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn play(variant: Box<dyn Variant>, moves: &[&str]) -> Box<dyn Variant> {
+        moves.iter().fold(variant, |variant, uci| {
+            variant.play(Move::from_str(uci).unwrap()).unwrap()
+        })
+    }
+
+    fn crazyhouse(fen: &str) -> Box<dyn Variant> {
+        Box::new(Crazyhouse::from_fen(fen).unwrap())
+    }
+
+    #[test]
+    fn pockets_are_read_and_written() {
+        let pockets: Pockets = "pNQp".parse().unwrap();
+
+        assert_eq!(pockets.white.queen, 1);
+        assert_eq!(pockets.white.knight, 1);
+        assert_eq!(pockets.black.pawn, 2);
+        assert_eq!(pockets.to_string(), "QNpp");
+        assert!("Qk".parse::<Pockets>().is_err());
+        assert_eq!("".parse::<Pockets>(), Ok(Pockets::default()));
+    }
+
+    #[test]
+    fn captures_fill_the_pocket() {
+        let game = play(
+            Box::new(Crazyhouse::new(Position::standard())),
+            &["e2e4", "d7d5", "e4d5", "d8d5"],
+        );
+
+        assert_eq!(
+            game.fen(),
+            "rnb1kbnr/ppp1pppp/8/3q4/8/8/PPPP1PPP/RNBQKBNR[Pp] w KQkq - 0 3"
+        );
+
+        let dropped = play(game, &["P@e4"]);
+        assert_eq!(dropped.pockets().unwrap().white.pawn, 0);
+        assert_eq!(dropped.board().piece_on(Square::E4), Some(Piece::Pawn));
+    }
+
+    #[test]
+    fn promoted_pieces_go_back_as_pawns() {
+        let fen = "4k2q/8/8/8/8/8/8/4K2Q~[] b - - 0 1";
+        let position = crazyhouse(fen);
+        assert_eq!(position.fen(), fen);
+
+        let captured = play(position, &["h8h1"]);
+        assert_eq!(captured.pockets().unwrap().black.pawn, 1);
+        assert_eq!(captured.pockets().unwrap().black.queen, 0);
+    }
+
+    #[test]
+    fn drops_follow_the_rules() {
+        let position = crazyhouse("4k3/8/8/8/8/8/8/r3K3[PNn] w - - 0 1");
+        let drop = |uci: &str| position.play(Move::from_str(uci).unwrap()).is_some();
+
+        // The king is in check, only drops blocking it are legal
+        assert!(drop("N@c1"));
+        assert!(!drop("N@e4"));
+        assert!(!drop("P@c1"));
+        assert!(!drop("N@e8"));
+
+        let safe = crazyhouse("4k3/8/8/8/8/8/8/4K3[PNn] w - - 0 1");
+        let legal = safe.legal_moves();
+        assert!(legal.contains(&Move::Drop(Piece::Knight, Square::D4)));
+        assert!(!legal.contains(&Move::Drop(Piece::Pawn, Square::A8)));
+        assert!(!legal.contains(&Move::Drop(Piece::Pawn, Square::A1)));
+        assert!(legal.contains(&Move::Drop(Piece::Pawn, Square::A2)));
+        assert!(!legal.contains(&Move::Drop(Piece::Bishop, Square::D4)));
+    }
+
+    #[test]
+    fn drops_are_written_in_uci() {
+        let drop = Move::Drop(Piece::Knight, Square::F3);
+
+        assert_eq!(drop.to_string(), "N@f3");
+        assert_eq!(Move::from_str("N@f3").unwrap(), drop);
+        assert_eq!(Move::from_str("n@f3").unwrap(), drop);
+        assert!(Move::from_str("K@f3").is_err());
+    }
+}
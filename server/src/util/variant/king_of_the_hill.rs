@@ -0,0 +1,110 @@
+use super::{end_of_moves, Move, Variant};
+use crate::actors::room::GameEndResult;
+use crate::util::position::Position;
+
+use chess::{BitBoard, Color, Piece, EMPTY};
+
+/// d4, e4, d5 and e5
+const CENTER: u64 = 0x0000_0018_1800_0000;
+
+/// Standard chess where a king reaching the center wins the game
+#[derive(Debug, Clone, Copy)]
+pub struct KingOfTheHill {
+    position: Position,
+}
+
+impl KingOfTheHill {
+    pub fn new(position: Position) -> Self {
+        KingOfTheHill { position }
+    }
+
+    /// Side whose king stands on the center
+    fn king_of_the_hill(&self) -> Option<Color> {
+        let board = self.position.board();
+        let kings = board.pieces(Piece::King) & BitBoard::new(CENTER);
+
+        [Color::White, Color::Black]
+            .iter()
+            .copied()
+            .find(|color| kings & board.color_combined(*color) != EMPTY)
+    }
+}
+
+impl Variant for KingOfTheHill {
+    fn position(&self) -> &Position {
+        &self.position
+    }
+
+    fn box_clone(&self) -> Box<dyn Variant> {
+        Box::new(*self)
+    }
+
+    fn play(&self, chess_move: Move) -> Option<Box<dyn Variant>> {
+        if self.king_of_the_hill().is_some() {
+            return None;
+        }
+
+        let position = self.position.play(chess_move.chess_move()?)?;
+        Some(Box::new(KingOfTheHill { position }))
+    }
+
+    fn legal_moves(&self) -> Vec<Move> {
+        match self.king_of_the_hill() {
+            Some(_) => Vec::new(),
+            None => self
+                .position
+                .legal_moves()
+                .into_iter()
+                .map(Move::from)
+                .collect(),
+        }
+    }
+
+    fn is_checkmate(&self) -> bool {
+        self.in_check() && self.position.legal_moves().is_empty()
+    }
+
+    fn result(&self) -> Option<GameEndResult> {
+        match self.king_of_the_hill() {
+            Some(color) => Some(GameEndResult::king_in_center(color)),
+            None => end_of_moves(self),
+        }
+    }
+
+    /// A lone king can still walk to the center
+    fn is_insufficient_material(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn play(variant: &dyn Variant, uci: &str) -> Option<Box<dyn Variant>> {
+        variant.play(Move::from_str(uci).unwrap())
+    }
+
+    #[test]
+    fn reaching_the_center_wins() {
+        let position = Position::from_fen("4k3/8/8/8/8/4K3/8/8 w - - 0 1", false).unwrap();
+        let hill = KingOfTheHill::new(position);
+        assert!(hill.result().is_none());
+
+        let center = play(&hill, "e3e4").unwrap();
+        assert!(matches!(
+            center.result(),
+            Some(GameEndResult::WhiteKingInCenter)
+        ));
+        assert!(center.legal_moves().is_empty());
+        assert!(play(center.as_ref(), "e8d8").is_none());
+    }
+
+    #[test]
+    fn bare_kings_keep_playing() {
+        let position = Position::from_fen("4k3/8/8/8/8/4K3/8/8 w - - 0 1", false).unwrap();
+
+        assert!(!KingOfTheHill::new(position).is_insufficient_material());
+    }
+}
@@ -0,0 +1,519 @@
+mod bughouse;
+mod crazyhouse;
+mod king_of_the_hill;
+mod three_check;
+
+pub use bughouse::Bughouse;
+pub use crazyhouse::{Crazyhouse, Pockets};
+pub use king_of_the_hill::KingOfTheHill;
+pub use three_check::ThreeCheck;
+
+use super::chess::{is_insufficient_material, DrawCounters};
+use super::position::{CastlingSide, Position};
+use crate::actors::room::{self, GameEndResult};
+
+use chess::{Board, ChessMove, Color, Piece, Square};
+use std::fmt;
+use std::str::FromStr;
+
+/// A move of any variant
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Move {
+    /// A piece moving on the board
+    Normal(ChessMove),
+    /// A piece taken from the pocket and put on an empty square
+    Drop(Piece, Square),
+}
+
+impl Move {
+    /// The move on the board, unless it is a drop
+    pub fn chess_move(self) -> Option<ChessMove> {
+        match self {
+            Move::Normal(chess_move) => Some(chess_move),
+            Move::Drop(..) => None,
+        }
+    }
+}
+
+impl From<ChessMove> for Move {
+    fn from(chess_move: ChessMove) -> Self {
+        Move::Normal(chess_move)
+    }
+}
+
+/// Writes the move in UCI, drops as the piece followed by `@` and the
+/// square, as in `N@f3`
+impl fmt::Display for Move {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Move::Normal(chess_move) => write!(f, "{}", chess_move),
+            Move::Drop(piece, square) => write!(f, "{}@{}", piece.to_string(Color::White), square),
+        }
+    }
+}
+
+impl FromStr for Move {
+    type Err = chess::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (piece, square) = match s.split_once('@') {
+            Some(drop) => drop,
+            None => return ChessMove::from_str(s).map(Move::Normal),
+        };
+
+        let piece = match piece.to_ascii_lowercase().as_str() {
+            "p" => Piece::Pawn,
+            "n" => Piece::Knight,
+            "b" => Piece::Bishop,
+            "r" => Piece::Rook,
+            "q" => Piece::Queen,
+            _ => return Err(chess::Error::InvalidUciMove),
+        };
+        if square.len() != 2 {
+            return Err(chess::Error::InvalidUciMove);
+        }
+
+        Ok(Move::Drop(piece, Square::from_str(square)?))
+    }
+}
+
+/// Rules of a chess variant, implemented by the positions of its games.
+///
+/// Positions are values, playing a move gives the next one. Variants keep a
+/// standard `Position` for the pieces and add their own state around it.
+pub trait Variant: fmt::Debug + Send {
+    /// Pieces, side to move and castling rights of the position
+    fn position(&self) -> &Position;
+
+    fn box_clone(&self) -> Box<dyn Variant>;
+
+    /// Position after `chess_move`, if it is legal
+    fn play(&self, chess_move: Move) -> Option<Box<dyn Variant>>;
+
+    /// Every legal move, castling being the king moving onto its rook
+    fn legal_moves(&self) -> Vec<Move> {
+        self.position()
+            .legal_moves()
+            .into_iter()
+            .map(Move::from)
+            .collect()
+    }
+
+    /// Pieces each side can drop, in variants that have pockets
+    fn pockets(&self) -> Option<Pockets> {
+        None
+    }
+
+    /// How the game ends in this position, if it does
+    fn result(&self) -> Option<GameEndResult> {
+        end_of_moves(self)
+    }
+
+    /// Whether neither side can win anymore
+    fn is_insufficient_material(&self) -> bool {
+        is_insufficient_material(self.board())
+    }
+
+    /// Writes the position in X-FEN
+    fn fen(&self) -> String {
+        self.position().fen()
+    }
+
+    /// Identifies the position for repetitions
+    fn repetition_key(&self) -> u64 {
+        self.position().repetition_key()
+    }
+
+    fn board(&self) -> &Board {
+        self.position().board()
+    }
+
+    fn side_to_move(&self) -> Color {
+        self.position().side_to_move()
+    }
+
+    fn in_check(&self) -> bool {
+        self.board().checkers().popcnt() != 0
+    }
+
+    fn is_checkmate(&self) -> bool {
+        self.in_check() && self.legal_moves().is_empty()
+    }
+
+    /// Moves of both sides since the last capture or pawn move
+    fn halfmoves(&self) -> usize {
+        self.position().halfmoves()
+    }
+
+    fn castling_side(&self, chess_move: ChessMove) -> Option<CastlingSide> {
+        self.position().castling_side(chess_move)
+    }
+
+    /// Writes `chess_move` in UCI
+    fn uci(&self, chess_move: Move) -> String {
+        match chess_move {
+            Move::Normal(chess_move) => self.position().uci(chess_move),
+            Move::Drop(..) => chess_move.to_string(),
+        }
+    }
+}
+
+impl Clone for Box<dyn Variant> {
+    fn clone(&self) -> Self {
+        self.box_clone()
+    }
+}
+
+impl Variant for Position {
+    fn position(&self) -> &Position {
+        self
+    }
+
+    fn box_clone(&self) -> Box<dyn Variant> {
+        Box::new(*self)
+    }
+
+    fn play(&self, chess_move: Move) -> Option<Box<dyn Variant>> {
+        Position::play(self, chess_move.chess_move()?)
+            .map(|next| Box::new(next) as Box<dyn Variant>)
+    }
+}
+
+/// Checkmate or stalemate when the side to move has no legal move
+fn end_of_moves<V: Variant + ?Sized>(variant: &V) -> Option<GameEndResult> {
+    if !variant.legal_moves().is_empty() {
+        return None;
+    }
+
+    if variant.in_check() {
+        Some(GameEndResult::checkmates(!variant.side_to_move()))
+    } else {
+        Some(GameEndResult::Stalemate)
+    }
+}
+
+/// Start position of a game of `variant`, `start_position` numbering the
+/// Chess960 ones
+pub fn initial_position(variant: room::Variant, start_position: u16) -> Box<dyn Variant> {
+    match variant {
+        room::Variant::Standard => Box::new(Position::standard()),
+        room::Variant::Chess960 => Box::new(Position::chess960(start_position)),
+        room::Variant::KingOfTheHill => Box::new(KingOfTheHill::new(Position::standard())),
+        room::Variant::ThreeCheck => Box::new(ThreeCheck::new(Position::standard())),
+        room::Variant::Crazyhouse => Box::new(Crazyhouse::new(Position::standard())),
+        room::Variant::Bughouse => Box::new(Bughouse::new(Position::standard())),
+    }
+}
+
+/// Reads a position of `variant` in X-FEN
+pub fn from_fen(variant: room::Variant, fen: &str) -> Option<Box<dyn Variant>> {
+    let position: Box<dyn Variant> = match variant {
+        room::Variant::Standard => Box::new(Position::from_fen(fen, false)?),
+        room::Variant::Chess960 => Box::new(Position::from_fen(fen, true)?),
+        room::Variant::KingOfTheHill => {
+            Box::new(KingOfTheHill::new(Position::from_fen(fen, false)?))
+        }
+        room::Variant::ThreeCheck => Box::new(ThreeCheck::from_fen(fen)?),
+        room::Variant::Crazyhouse => Box::new(Crazyhouse::from_fen(fen)?),
+        room::Variant::Bughouse => Box::new(Bughouse::from_fen(fen)?),
+    };
+
+    Some(position)
+}
+
+/// Why a player can't resign, abort or answer a draw offer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GameError {
+    GameOver,
+    NoDrawOffer,
+    /// Both sides have played a move
+    AbortNotAllowed,
+}
+
+/// What offering a draw did
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DrawOffer {
+    /// The offer waits for the opponent
+    Made,
+    /// The side had already offered a draw
+    Pending,
+    /// The opponent had offered one too, the game is drawn
+    Accepted,
+}
+
+/// Moves of a game along with every position they went through
+#[derive(Debug, Clone)]
+pub struct ChessGame {
+    /// Positions from the initial one, one more than there are moves
+    positions: Vec<Box<dyn Variant>>,
+    moves: Vec<Move>,
+    /// Side with a pending draw offer
+    draw_offer: Option<Color>,
+    result: Option<GameEndResult>,
+}
+
+impl ChessGame {
+    pub fn new(initial: Box<dyn Variant>) -> Self {
+        ChessGame {
+            positions: vec![initial],
+            moves: Vec::new(),
+            draw_offer: None,
+            result: None,
+        }
+    }
+
+    pub fn initial_position(&self) -> &dyn Variant {
+        self.positions[0].as_ref()
+    }
+
+    pub fn current_position(&self) -> &dyn Variant {
+        self.positions[self.moves.len()].as_ref()
+    }
+
+    pub fn side_to_move(&self) -> Color {
+        self.current_position().side_to_move()
+    }
+
+    pub fn moves(&self) -> &[Move] {
+        &self.moves
+    }
+
+    /// Plays `chess_move` if it is legal and the game goes on. Making a
+    /// move cancels any pending draw offer.
+    pub fn make_move(&mut self, chess_move: Move) -> bool {
+        if self.result.is_some() {
+            return false;
+        }
+
+        match self.current_position().play(chess_move) {
+            Some(position) => {
+                self.positions.push(position);
+                self.moves.push(chess_move);
+                self.draw_offer = None;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Ends the game, no move nor request is accepted anymore
+    pub fn end(&mut self, result: GameEndResult) {
+        self.result = Some(result);
+    }
+
+    fn ongoing(&self) -> Result<(), GameError> {
+        match self.result {
+            Some(_) => Err(GameError::GameOver),
+            None => Ok(()),
+        }
+    }
+
+    pub fn draw_offer(&self) -> Option<Color> {
+        self.draw_offer
+    }
+
+    /// Result of `color` resigning
+    pub fn resign(&self, color: Color) -> Result<GameEndResult, GameError> {
+        self.ongoing()?;
+
+        Ok(GameEndResult::resigns(color))
+    }
+
+    /// Games can only be aborted until both sides have played a move
+    pub fn abort(&self) -> Result<GameEndResult, GameError> {
+        self.ongoing()?;
+
+        if self.moves.len() < 2 {
+            Ok(GameEndResult::Aborted)
+        } else {
+            Err(GameError::AbortNotAllowed)
+        }
+    }
+
+    /// Offering a draw to someone who offered one is accepting it
+    pub fn offer_draw(&mut self, color: Color) -> Result<DrawOffer, GameError> {
+        self.ongoing()?;
+
+        Ok(match self.draw_offer {
+            Some(offer) if offer != color => DrawOffer::Accepted,
+            Some(_) => DrawOffer::Pending,
+            None => {
+                self.draw_offer = Some(color);
+                DrawOffer::Made
+            }
+        })
+    }
+
+    /// Result of `color` accepting the draw offered by the opponent
+    pub fn accept_draw(&self, color: Color) -> Result<GameEndResult, GameError> {
+        self.ongoing()?;
+
+        if self.draw_offer == Some(!color) {
+            Ok(GameEndResult::DrawAccepted)
+        } else {
+            Err(GameError::NoDrawOffer)
+        }
+    }
+
+    pub fn decline_draw(&mut self, color: Color) -> Result<(), GameError> {
+        self.ongoing()?;
+
+        if self.draw_offer == Some(!color) {
+            self.draw_offer = None;
+            Ok(())
+        } else {
+            Err(GameError::NoDrawOffer)
+        }
+    }
+
+    /// Moves of the game in UCI
+    pub fn uci_moves(&self) -> Vec<String> {
+        self.positions
+            .iter()
+            .zip(&self.moves)
+            .map(|(position, chess_move)| position.uci(*chess_move))
+            .collect()
+    }
+
+    /// Counts repetitions of the current position, only positions since the
+    /// last capture or pawn move can repeat
+    pub fn draw_counters(&self) -> DrawCounters {
+        let current = self.current_position();
+        let key = current.repetition_key();
+
+        DrawCounters {
+            repetitions: self
+                .positions
+                .iter()
+                .rev()
+                .take(current.halfmoves() + 1)
+                .filter(|position| position.repetition_key() == key)
+                .count(),
+            halfmoves: current.halfmoves(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn play(game: &mut ChessGame, moves: &[&str]) {
+        for uci in moves {
+            assert!(game.make_move(Move::from_str(uci).unwrap()));
+        }
+    }
+
+    #[test]
+    fn repetitions_count_the_current_position() {
+        let mut game = ChessGame::new(Box::new(Position::standard()));
+        let shuffle = ["g1f3", "g8f6", "f3g1", "f6g8"];
+
+        assert_eq!(game.draw_counters().repetitions, 1);
+
+        play(&mut game, &shuffle);
+        assert_eq!(game.draw_counters().repetitions, 2);
+        assert_eq!(game.draw_counters().halfmoves, 4);
+
+        play(&mut game, &shuffle);
+        assert_eq!(game.draw_counters().repetitions, 3);
+
+        play(&mut game, &shuffle);
+        play(&mut game, &shuffle);
+        assert_eq!(game.draw_counters().repetitions, 5);
+    }
+
+    #[test]
+    fn pawn_moves_reset_the_counters() {
+        let mut game = ChessGame::new(Box::new(Position::standard()));
+
+        play(&mut game, &["g1f3", "g8f6", "f3g1", "f6g8", "e2e4"]);
+        assert_eq!(game.draw_counters().repetitions, 1);
+        assert_eq!(game.draw_counters().halfmoves, 0);
+
+        play(&mut game, &["g8f6"]);
+        assert_eq!(game.draw_counters().halfmoves, 1);
+    }
+
+    #[test]
+    fn castling_rights_tell_positions_apart() {
+        let mut game = ChessGame::new(Box::new(Position::standard()));
+
+        // The kings come back, without their castling rights
+        play(&mut game, &["e2e4", "e7e5", "e1e2", "e8e7", "e2e1", "e7e8"]);
+        assert_eq!(game.draw_counters().repetitions, 1);
+
+        play(&mut game, &["e1e2", "e8e7", "e2e1", "e7e8"]);
+        assert_eq!(game.draw_counters().repetitions, 2);
+    }
+
+    #[test]
+    fn games_can_be_aborted_until_both_sides_moved() {
+        let mut game = ChessGame::new(Box::new(Position::standard()));
+        assert!(matches!(game.abort(), Ok(GameEndResult::Aborted)));
+
+        play(&mut game, &["e2e4"]);
+        assert!(matches!(game.abort(), Ok(GameEndResult::Aborted)));
+
+        play(&mut game, &["e7e5"]);
+        assert_eq!(game.abort().unwrap_err(), GameError::AbortNotAllowed);
+    }
+
+    #[test]
+    fn moves_cancel_draw_offers() {
+        let mut game = ChessGame::new(Box::new(Position::standard()));
+
+        assert_eq!(game.offer_draw(Color::White), Ok(DrawOffer::Made));
+        assert_eq!(game.offer_draw(Color::White), Ok(DrawOffer::Pending));
+        assert_eq!(game.draw_offer(), Some(Color::White));
+
+        play(&mut game, &["e2e4"]);
+        assert_eq!(game.draw_offer(), None);
+        assert_eq!(
+            game.accept_draw(Color::Black).unwrap_err(),
+            GameError::NoDrawOffer
+        );
+    }
+
+    #[test]
+    fn draw_offers_are_answered_by_the_opponent() {
+        let mut game = ChessGame::new(Box::new(Position::standard()));
+        game.offer_draw(Color::White).unwrap();
+
+        assert_eq!(
+            game.accept_draw(Color::White).unwrap_err(),
+            GameError::NoDrawOffer
+        );
+        assert!(matches!(
+            game.accept_draw(Color::Black),
+            Ok(GameEndResult::DrawAccepted)
+        ));
+        assert_eq!(game.offer_draw(Color::Black), Ok(DrawOffer::Accepted));
+
+        assert_eq!(game.decline_draw(Color::White), Err(GameError::NoDrawOffer));
+        assert_eq!(game.decline_draw(Color::Black), Ok(()));
+        assert_eq!(game.draw_offer(), None);
+    }
+
+    #[test]
+    fn ended_games_reject_requests() {
+        let mut game = ChessGame::new(Box::new(Position::standard()));
+        play(&mut game, &["f2f3", "e7e5"]);
+        game.offer_draw(Color::Black).unwrap();
+        assert!(matches!(
+            game.resign(Color::White),
+            Ok(GameEndResult::WhiteResigns)
+        ));
+
+        game.end(GameEndResult::WhiteResigns);
+
+        assert_eq!(game.resign(Color::Black).unwrap_err(), GameError::GameOver);
+        assert_eq!(game.abort().unwrap_err(), GameError::GameOver);
+        assert_eq!(game.offer_draw(Color::White), Err(GameError::GameOver));
+        assert_eq!(
+            game.accept_draw(Color::White).unwrap_err(),
+            GameError::GameOver
+        );
+        assert!(!game.make_move(Move::from_str("g2g4").unwrap()));
+    }
+}
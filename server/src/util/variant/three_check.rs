@@ -0,0 +1,177 @@
+use super::{end_of_moves, Move, Variant};
+use crate::actors::room::GameEndResult;
+use crate::util::position::{mix, Position};
+
+use chess::Color;
+
+/// Checks that win the game
+const CHECKS: u8 = 3;
+
+/// Standard chess where giving a third check wins the game
+#[derive(Debug, Clone, Copy)]
+pub struct ThreeCheck {
+    position: Position,
+    /// Checks given by each side, by color index
+    checks: [u8; 2],
+}
+
+impl ThreeCheck {
+    pub fn new(position: Position) -> Self {
+        ThreeCheck {
+            position,
+            checks: [0, 0],
+        }
+    }
+
+    /// Reads an X-FEN with the checks each side has left to give after the
+    /// en passant square, as in `3+3`. The older `+0+0` field, counting the
+    /// checks given, is read too.
+    pub fn from_fen(fen: &str) -> Option<Self> {
+        let mut fields: Vec<&str> = fen.split_whitespace().collect();
+        let mut checks = [0, 0];
+
+        if let Some(field) = fields.get(4).filter(|field| field.contains('+')) {
+            let given = field.starts_with('+');
+            let counts = field
+                .trim_start_matches('+')
+                .split('+')
+                .map(|count| count.parse::<u8>().ok().filter(|count| *count <= CHECKS))
+                .collect::<Option<Vec<u8>>>()?;
+
+            if counts.len() != 2 {
+                return None;
+            }
+
+            for (index, count) in counts.iter().enumerate() {
+                checks[index] = if given { *count } else { CHECKS - count };
+            }
+
+            fields.remove(4);
+        }
+
+        Some(ThreeCheck {
+            position: Position::from_fen(&fields.join(" "), false)?,
+            checks,
+        })
+    }
+
+    /// Side that gave its third check
+    fn winner(&self) -> Option<Color> {
+        [Color::White, Color::Black]
+            .iter()
+            .copied()
+            .find(|color| self.checks[color.to_index()] >= CHECKS)
+    }
+}
+
+impl Variant for ThreeCheck {
+    fn position(&self) -> &Position {
+        &self.position
+    }
+
+    fn box_clone(&self) -> Box<dyn Variant> {
+        Box::new(*self)
+    }
+
+    fn play(&self, chess_move: Move) -> Option<Box<dyn Variant>> {
+        if self.winner().is_some() {
+            return None;
+        }
+
+        let color = self.position.side_to_move();
+        let position = self.position.play(chess_move.chess_move()?)?;
+        let mut checks = self.checks;
+
+        if position.board().checkers().popcnt() != 0 {
+            checks[color.to_index()] += 1;
+        }
+
+        Some(Box::new(ThreeCheck { position, checks }))
+    }
+
+    fn legal_moves(&self) -> Vec<Move> {
+        match self.winner() {
+            Some(_) => Vec::new(),
+            None => self
+                .position
+                .legal_moves()
+                .into_iter()
+                .map(Move::from)
+                .collect(),
+        }
+    }
+
+    fn result(&self) -> Option<GameEndResult> {
+        match self.winner() {
+            Some(color) => Some(GameEndResult::three_checks(color)),
+            None => end_of_moves(self),
+        }
+    }
+
+    fn is_checkmate(&self) -> bool {
+        self.in_check() && self.position.legal_moves().is_empty()
+    }
+
+    /// Any piece can give a check, only two bare kings are a draw
+    fn is_insufficient_material(&self) -> bool {
+        self.board().combined().popcnt() == 2
+    }
+
+    fn fen(&self) -> String {
+        let fen = self.position.fen();
+        let mut fields: Vec<&str> = fen.split_whitespace().collect();
+        let left = format!(
+            "{}+{}",
+            CHECKS - self.checks[0].min(CHECKS),
+            CHECKS - self.checks[1].min(CHECKS)
+        );
+        fields.insert(4, &left);
+
+        fields.join(" ")
+    }
+
+    fn repetition_key(&self) -> u64 {
+        let checks = u64::from(self.checks[0]) << 8 | u64::from(self.checks[1]);
+
+        self.position.repetition_key() ^ mix(checks + 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn reads_checks_left_and_checks_given() {
+        let left = ThreeCheck::from_fen("4k3/8/8/8/8/8/8/R3K3 w - - 1+3 0 1").unwrap();
+        let given = ThreeCheck::from_fen("4k3/8/8/8/8/8/8/R3K3 w - - +2+0 0 1").unwrap();
+
+        assert_eq!(left.checks, [2, 0]);
+        assert_eq!(given.checks, [2, 0]);
+        assert_eq!(left.fen(), "4k3/8/8/8/8/8/8/R3K3 w - - 1+3 0 1");
+        assert!(ThreeCheck::from_fen("4k3/8/8/8/8/8/8/R3K3 w - - 4+3 0 1").is_none());
+    }
+
+    #[test]
+    fn third_check_wins() {
+        let position = ThreeCheck::from_fen("4k3/8/8/8/8/8/8/R3K3 w - - 1+3 0 1").unwrap();
+        assert!(position.result().is_none());
+
+        let checked = position.play(Move::from_str("a1a8").unwrap()).unwrap();
+        assert!(matches!(
+            checked.result(),
+            Some(GameEndResult::WhiteThreeChecks)
+        ));
+        assert!(checked.legal_moves().is_empty());
+        assert_eq!(checked.fen(), "R3k3/8/8/8/8/8/8/4K3 b - - 0+3 1 1");
+    }
+
+    #[test]
+    fn checks_count_for_repetitions() {
+        let fresh = ThreeCheck::from_fen("4k3/8/8/8/8/8/8/R3K3 w - - 3+3 0 1").unwrap();
+        let checked = ThreeCheck::from_fen("4k3/8/8/8/8/8/8/R3K3 w - - 2+3 0 1").unwrap();
+
+        assert_ne!(fresh.repetition_key(), checked.repetition_key());
+    }
+}